@@ -7,7 +7,9 @@ use std::{
 };
 
 use bollard::{
-    container::{InspectContainerOptions, ListContainersOptions},
+    container::{
+        InspectContainerOptions, ListContainersOptions, StartContainerOptions, StopContainerOptions,
+    },
     secret::{
         ContainerInspectResponse, ContainerSummary, ContainerSummaryNetworkSettings, EventActor,
         EventMessage, EventMessageScopeEnum, EventMessageTypeEnum, NetworkSettings,
@@ -15,11 +17,13 @@ use bollard::{
     system::EventsOptions,
 };
 use eyre::Context;
-use mcproxy_model::Upstream;
+use mcproxy_model::{Hostname, Upstream};
 use tokio_stream::StreamExt;
 use tracing::{debug, error, info, warn};
 
-use crate::discovery::{ActiveServer, DiscoveredServers, ServerId, ServerInsertionError};
+use crate::discovery::{
+    ActiveServer, DiscoveredServers, ServerCommand, ServerId, ServerInsertionError, StoppedServer,
+};
 
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct ContainerId([u8; 32]);
@@ -120,27 +124,19 @@ where
     })
 }
 
-fn gather_server_information(
-    ip: IpAddr,
-    mut labels: HashMap<String, String>,
-) -> Option<ActiveServer> {
-    let replica_behavior = extract_label(&labels, "mcproxy.replica_behavior");
+fn gather_hostnames(labels: &HashMap<String, String>) -> Option<Vec<Hostname>> {
+    let replica_behavior = extract_label(labels, "mcproxy.replica_behavior");
 
-    if let Some(hostname) = labels.remove("mcproxy") {
+    if let Some(hostname) = labels.get("mcproxy") {
         let hostname = match replica_behavior.zip(labels.get("com.docker.compose.container-number"))
         {
             Some((ReplicaBehavior::IndexSubdomain, replica)) => {
-                Arc::from(format!("{replica}.{hostname}"))
+                Hostname::from(format!("{replica}.{hostname}"))
             }
-            None => Arc::from(hostname),
+            None => Hostname::from(hostname.as_str()),
         };
 
-        let port = extract_label(&labels, "mcproxy.port");
-
-        Some(ActiveServer {
-            hostnames: vec![hostname],
-            upstream: Upstream::from(SocketAddr::new(ip, port.unwrap_or(25565))),
-        })
+        Some(vec![hostname])
     } else {
         warn!(
             label_name = "mcproxy",
@@ -151,14 +147,36 @@ fn gather_server_information(
     }
 }
 
-pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), eyre::Report> {
-    tracing::info!("docker discovery started");
+fn gather_server_information(ip: IpAddr, labels: HashMap<String, String>) -> Option<ActiveServer> {
+    let hostnames = gather_hostnames(&labels)?;
+    let port = extract_label(&labels, "mcproxy.port");
+
+    Some(ActiveServer {
+        hostnames,
+        upstream: Upstream::from(SocketAddr::new(ip, port.unwrap_or(25565))),
+    })
+}
 
+pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), eyre::Report> {
     let docker =
         bollard::Docker::connect_with_defaults().wrap_err("failed to connect to docker socket")?;
 
+    discover(docker, discovered_servers).await
+}
+
+async fn discover(
+    docker: bollard::Docker,
+    discovered_servers: Arc<DiscoveredServers>,
+) -> Result<(), eyre::Report> {
+    tracing::info!("docker discovery started");
+
+    let mut commands = discovered_servers
+        .take_commands()
+        .ok_or_else(|| eyre::eyre!("another discovery service already receives commands"))?;
+
     let current_containers = match docker
         .list_containers(Some(ListContainersOptions {
+            all: true,
             filters: HashMap::from_iter([("label", vec!["mcproxy"])]),
             ..Default::default()
         }))
@@ -174,19 +192,40 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
 
     // dbg!(&current_containers);
 
+    // Stopped containers can not be routed to, but are remembered so that they can be started
+    let (current_containers, stopped_containers): (Vec<_>, Vec<_>) = current_containers
+        .into_iter()
+        .partition(|container| container.state.as_deref() == Some("running"));
+
+    for container in stopped_containers {
+        let Some((id, labels)) = container.id.zip(container.labels) else {
+            continue;
+        };
+
+        let Ok(id) =
+            ContainerId::from_str(&id).inspect_err(|error| warn!(%error, "container id malformed"))
+        else {
+            continue;
+        };
+
+        if let Some(hostnames) = gather_hostnames(&labels) {
+            discovered_servers.insert_stopped(ServerId::Docker(id), StoppedServer { hostnames });
+        }
+    }
+
     let current_active_servers =
             current_containers
                 .into_iter()
                 .filter_map(|container| match container {
                     ContainerSummary {
                         id: Some(id),
-                        ports: Some(ports),
+                        ports: Some(_),
                         labels: Some(labels),
                         network_settings: Some(ContainerSummaryNetworkSettings { networks: Some(networks) }),
                         ..
                     } => {
                         // FIXME: smarter network selection.... Use docker networks, also error handling is a mess here
-                        let ip = networks.iter().next().and_then(|(network_name, endpoint_settings)| {
+                        let ip = networks.iter().next().and_then(|(_network_name, endpoint_settings)| {
                             endpoint_settings.ip_address.as_ref()
                         }).and_then(|ip| IpAddr::from_str(ip).inspect_err(|err| error!(
                             error = %err,
@@ -212,13 +251,6 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
             Err(ServerInsertionError::ServerIdExists) => {
                 error!("server id already exists in mapping")
             }
-            Err(ServerInsertionError::HostnameExists(error)) => {
-                warn!(
-                    hostname = error.hostname(),
-                    %container,
-                    "server with hostname exists already"
-                );
-            }
             Ok(()) => (),
         };
     }
@@ -227,19 +259,32 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
 
     info!(
         count = discovered_servers.len(),
-        "discovered running docker servers"
+        stopped = discovered_servers.stopped_servers.len(),
+        "discovered docker servers"
     );
 
     let mut events = docker.events(Some(EventsOptions::<&str> {
         filters: HashMap::from_iter([
             ("type", vec!["container"]),
-            ("event", vec!["start", "stop"]),
+            ("event", vec!["create", "start", "die", "stop", "destroy"]),
             ("label", vec!["mcproxy"]),
         ]),
         ..Default::default()
     }));
 
-    while let Some(event) = events.next().await {
+    loop {
+        let event = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            Some(command) = commands.recv() => {
+                // Starting or stopping a container can take a while, so don't hold up the events
+                tokio::spawn(handle_command(docker.clone(), discovered_servers.clone(), command));
+                continue;
+            }
+        };
+
         match event {
             Ok(EventMessage {
                 typ: Some(EventMessageTypeEnum::CONTAINER),
@@ -293,7 +338,7 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
                                     networks
                                         .iter()
                                         .next()
-                                        .and_then(|(network_name, endpoint_settings)| {
+                                        .and_then(|(_network_name, endpoint_settings)| {
                                             endpoint_settings.ip_address.as_ref()
                                         })
                                         .and_then(|ip| {
@@ -324,7 +369,7 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
                         if let Some(server) = gather_server_information(ip, attributes) {
                             debug!(%id, "inserting discovered server mapping");
                             if let Err(error) =
-                                discovered_servers.insert(ServerId::Docker(id), server)
+                                discovered_servers.mark_running(ServerId::Docker(id), server)
                             {
                                 error!(%error, "failed to record discovered server");
                                 continue;
@@ -334,9 +379,21 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
                             continue;
                         }
                     }
-                    "stop" => {
+                    // A container dies whenever it exits, but is only stopped when asked to
+                    "create" | "die" | "stop" => {
+                        debug!(%id, "recording stopped discovered server");
+                        if let Some(hostnames) = gather_hostnames(&attributes) {
+                            discovered_servers
+                                .mark_stopped(ServerId::Docker(id), StoppedServer { hostnames });
+                        } else {
+                            discovered_servers.remove(ServerId::Docker(id));
+                        }
+                    }
+                    "destroy" => {
                         debug!(%id, "removing discovered server mapping");
                         discovered_servers.remove(ServerId::Docker(id));
+                        discovered_servers.remove_stopped(ServerId::Docker(id));
+                        discovered_servers.clear_sleeping(ServerId::Docker(id));
                     }
                     _ => warn!(action, "unknown action received"),
                 }
@@ -352,3 +409,165 @@ pub async fn docker(discovered_servers: Arc<DiscoveredServers>) -> Result<(), ey
 
     Ok(())
 }
+
+async fn handle_command(
+    docker: bollard::Docker,
+    discovered_servers: Arc<DiscoveredServers>,
+    command: ServerCommand,
+) {
+    match command {
+        ServerCommand::Start(ServerId::Docker(id)) => {
+            info!(%id, "starting container");
+
+            if let Err(error) = docker
+                .start_container(&id.to_string(), None::<StartContainerOptions<String>>)
+                .await
+            {
+                error!(%id, %error, "failed to start container");
+                discovered_servers.fail_starting(ServerId::Docker(id));
+            }
+        }
+        ServerCommand::Stop(ServerId::Docker(id)) => {
+            info!(%id, "stopping idle container");
+
+            if let Err(error) = docker
+                .stop_container(&id.to_string(), None::<StopContainerOptions>)
+                .await
+            {
+                error!(%id, %error, "failed to stop container");
+                discovered_servers.clear_sleeping(ServerId::Docker(id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{str::FromStr, sync::Arc, time::Duration};
+
+    use mcproxy_model::Hostname;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
+
+    use super::{discover, ContainerId};
+    use crate::discovery::{DiscoveredServers, ServerId};
+
+    const CONTAINER_ID: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    /// Answer just enough of the Docker API for discovery, reporting every container started or stopped
+    async fn fake_docker(stream: UnixStream, commands: mpsc::UnboundedSender<String>) {
+        let mut stream = BufReader::new(stream);
+
+        loop {
+            let mut request_line = String::new();
+            if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                return;
+            }
+
+            // Skip the headers, none of the requests made have a body
+            let mut header = String::new();
+            while stream.read_line(&mut header).await.unwrap() > 2 {
+                header.clear();
+            }
+
+            let path = request_line.split(' ').nth(1).unwrap();
+            let response = if path.contains("/containers/json") {
+                let body = format!(
+                    r#"[{{"Id": "{CONTAINER_ID}", "State": "exited", "Labels": {{"mcproxy": "Sleepy.Test"}}}}]"#
+                );
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+            } else if path.contains("/events") {
+                // Stream no events, but keep the connection open
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n")
+                    .await
+                    .unwrap();
+                std::future::pending::<()>().await;
+                unreachable!()
+            } else if let Some((id, action)) = path
+                .split_once("/containers/")
+                .and_then(|(_, rest)| rest.split('?').next()?.split_once('/'))
+            {
+                commands.send(format!("{action} {id}")).unwrap();
+                "HTTP/1.1 204 No Content\r\n\r\n".to_string()
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+            };
+
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn start_and_stop_container() {
+        let socket =
+            std::env::temp_dir().join(format!("mcproxy-fake-docker-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let (command_sender, mut commands) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(fake_docker(stream, command_sender.clone()));
+            }
+        });
+
+        let docker = bollard::Docker::connect_with_socket(
+            socket.to_str().unwrap(),
+            5,
+            bollard::API_DEFAULT_VERSION,
+        )
+        .unwrap();
+        let discovered_servers = Arc::new(DiscoveredServers::default());
+        tokio::spawn(discover(docker, discovered_servers.clone()));
+
+        let hostname = Hostname::from("sleepy.test");
+        let id = timeout(Duration::from_secs(5), async {
+            loop {
+                match discovered_servers.get_stopped_by_hostname(&hostname) {
+                    Some(id) => break id,
+                    None => sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .expect("stopped container should be discovered");
+        assert_eq!(
+            id,
+            ServerId::Docker(ContainerId::from_str(CONTAINER_ID).unwrap())
+        );
+        assert!(discovered_servers.get_by_hostname(&hostname).is_empty());
+
+        assert!(discovered_servers.start(id));
+        assert!(!discovered_servers.start(id), "server is already starting");
+        assert!(discovered_servers.starting_since(&hostname).is_some());
+
+        let command = timeout(Duration::from_secs(5), commands.recv())
+            .await
+            .expect("container should be started")
+            .unwrap();
+        assert_eq!(command, format!("start {CONTAINER_ID}"));
+
+        assert!(discovered_servers.put_to_sleep(id));
+        assert!(
+            !discovered_servers.put_to_sleep(id),
+            "server is already asleep"
+        );
+        assert!(discovered_servers.sleeping_since(&hostname).is_some());
+
+        let command = timeout(Duration::from_secs(5), commands.recv())
+            .await
+            .expect("container should be stopped")
+            .unwrap();
+        assert_eq!(command, format!("stop {CONTAINER_ID}"));
+
+        let _ = std::fs::remove_file(&socket);
+    }
+}
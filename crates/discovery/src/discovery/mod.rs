@@ -1,10 +1,11 @@
 use std::{
     fmt::{Debug, Display},
-    ops::{Bound, ControlFlow},
-    sync::Arc,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
 };
 
 use dashmap::DashMap;
+use tokio::sync::mpsc;
 
 use mcproxy_model::{Hostname, Upstream};
 
@@ -13,7 +14,7 @@ mod docker;
 
 #[derive(Debug)]
 pub struct ActiveServer {
-    hostnames: Vec<Arc<str>>,
+    hostnames: Vec<Hostname>,
 
     upstream: Upstream,
 }
@@ -24,6 +25,19 @@ impl ActiveServer {
     }
 }
 
+/// A discovered server which exists, but is not running
+#[derive(Debug)]
+pub struct StoppedServer {
+    hostnames: Vec<Hostname>,
+}
+
+/// A request to change whether a discovered server is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerCommand {
+    Start(ServerId),
+    Stop(ServerId),
+}
+
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum ServerId {
     // TODO: extract this and all other generic discovery to other crate
@@ -48,48 +62,174 @@ impl Display for ServerId {
 
 enum ServerInsertionError {
     ServerIdExists,
-    HostnameExists(HostnameExistsError),
 }
 
 impl Display for ServerInsertionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ServerInsertionError::ServerIdExists => write!(f, "server id already exists"),
-            ServerInsertionError::HostnameExists(error) => Display::fmt(error, f),
         }
     }
 }
 
-struct HostnameExistsError {
-    hostname: Arc<str>,
-}
-impl HostnameExistsError {
-    pub fn hostname(&self) -> &str {
-        &self.hostname
-    }
-}
-
-impl Display for HostnameExistsError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "hostname {} already mapped", self.hostname)
-    }
-}
-
 #[derive(Default, Debug)]
 pub struct DiscoveredServers {
     active_servers: DashMap<ServerId, ActiveServer>,
 
-    hostname_index: DashMap<Arc<str>, ServerId>,
+    /// Every server claiming a hostname, which are pooled when there is more than one
+    hostname_index: DashMap<Hostname, Vec<ServerId>>,
+
+    stopped_servers: DashMap<ServerId, StoppedServer>,
+
+    stopped_hostname_index: DashMap<Hostname, ServerId>,
+
+    /// Servers started by the proxy which have not accepted a connection yet
+    starting_servers: DashMap<ServerId, Instant>,
+
+    /// How long each server took to accept a connection the last time the proxy started it
+    start_durations: DashMap<ServerId, Duration>,
+
+    /// Servers stopped by the proxy for being idle, which have not started again since
+    sleeping_servers: DashMap<ServerId, Instant>,
+
+    /// Sends commands to the discovery service that found the servers
+    commands: OnceLock<mpsc::UnboundedSender<ServerCommand>>,
 }
 
 impl DiscoveredServers {
-    pub fn get_by_hostname(
-        &self,
-        hostname: Hostname,
-    ) -> Option<dashmap::mapref::one::Ref<ServerId, ActiveServer>> {
+    /// The upstreams of every running server claiming a hostname, in a stable order
+    pub fn get_by_hostname(&self, hostname: &Hostname) -> Vec<Upstream> {
+        let Some(ids) = self.hostname_index.get(hostname) else {
+            return Vec::new();
+        };
+
+        ids.iter()
+            .filter_map(|id| self.active_servers.get(id))
+            .map(|server| server.upstream())
+            .collect()
+    }
+
+    /// Snapshot of every hostname currently mapped, along with the servers it maps to
+    pub fn mappings(&self) -> Vec<(Hostname, ServerId, Upstream)> {
         self.hostname_index
-            .get(hostname.as_ref())
-            .and_then(|id| self.active_servers.get(&*id))
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .value()
+                    .iter()
+                    .filter_map(|id| {
+                        let server = self.active_servers.get(id)?;
+
+                        Some((entry.key().clone(), *id, server.upstream()))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Find the stopped server a hostname would map to once it is running
+    pub fn get_stopped_by_hostname(&self, hostname: &Hostname) -> Option<ServerId> {
+        self.stopped_hostname_index.get(hostname).map(|id| *id)
+    }
+
+    /// Find the server a hostname maps to, whether it is running or not
+    fn id_by_hostname(&self, hostname: &Hostname) -> Option<ServerId> {
+        self.hostname_index
+            .get(hostname)
+            .and_then(|ids| ids.first().copied())
+            .or_else(|| self.get_stopped_by_hostname(hostname))
+    }
+
+    /// When the server a hostname maps to was started, if it has not accepted a connection since
+    pub fn starting_since(&self, hostname: &Hostname) -> Option<Instant> {
+        let id = self.id_by_hostname(hostname)?;
+
+        self.starting_servers.get(&id).map(|since| *since)
+    }
+
+    /// Record that the server a hostname maps to accepted a connection, and so has started
+    pub fn finish_starting(&self, hostname: &Hostname) {
+        let Some(id) = self.id_by_hostname(hostname) else {
+            return;
+        };
+
+        if let Some((_, since)) = self.starting_servers.remove(&id) {
+            self.start_durations.insert(id, since.elapsed());
+        }
+    }
+
+    /// How much longer the server a hostname maps to should take to start, going by how long it
+    /// took the last time
+    pub fn start_eta(&self, hostname: &Hostname) -> Option<Duration> {
+        let id = self.id_by_hostname(hostname)?;
+        let since = *self.starting_servers.get(&id)?;
+        let took = *self.start_durations.get(&id)?;
+
+        Some(took.saturating_sub(since.elapsed()))
+    }
+
+    /// Ask the discovery service to start a stopped server
+    ///
+    /// Returns `false` if the server is already starting, or nothing can start it.
+    pub fn start(&self, id: ServerId) -> bool {
+        let Some(commands) = self.commands.get() else {
+            return false;
+        };
+
+        match self.starting_servers.entry(id) {
+            dashmap::Entry::Occupied(_) => false,
+            dashmap::Entry::Vacant(vacant) => {
+                vacant.insert(Instant::now());
+
+                commands.send(ServerCommand::Start(id)).is_ok()
+            }
+        }
+    }
+
+    /// Snapshot of every running server, along with its upstream
+    pub fn active_servers(&self) -> Vec<(ServerId, Upstream)> {
+        self.active_servers
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().upstream()))
+            .collect()
+    }
+
+    /// When the server a hostname maps to was put to sleep, if it has not started since
+    pub fn sleeping_since(&self, hostname: &Hostname) -> Option<Instant> {
+        let id = self.id_by_hostname(hostname)?;
+
+        self.sleeping_servers.get(&id).map(|since| *since)
+    }
+
+    /// Amount of servers put to sleep that have not started since
+    pub fn sleeping_len(&self) -> usize {
+        self.sleeping_servers.len()
+    }
+
+    /// Ask the discovery service to stop an idle server
+    ///
+    /// Returns `false` if the server is already asleep, or nothing can stop it.
+    pub fn put_to_sleep(&self, id: ServerId) -> bool {
+        let Some(commands) = self.commands.get() else {
+            return false;
+        };
+
+        match self.sleeping_servers.entry(id) {
+            dashmap::Entry::Occupied(_) => false,
+            dashmap::Entry::Vacant(vacant) => {
+                vacant.insert(Instant::now());
+
+                commands.send(ServerCommand::Stop(id)).is_ok()
+            }
+        }
+    }
+
+    /// Snapshot of every hostname of a stopped server, along with the server it maps to
+    pub fn stopped_mappings(&self) -> Vec<(Hostname, ServerId)> {
+        self.stopped_hostname_index
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
     }
 
     pub fn len(&self) -> usize {
@@ -100,31 +240,31 @@ impl DiscoveredServers {
         self.active_servers.is_empty()
     }
 
+    /// Record a running server without a discovery service finding it
+    #[cfg(feature = "test-util")]
+    pub fn insert_active(&self, id: ServerId, hostnames: Vec<Hostname>, upstream: Upstream) {
+        let _ = self.insert(
+            id,
+            ActiveServer {
+                hostnames,
+                upstream,
+            },
+        );
+    }
+
+    /// Record a running server, adding it to the pool of any hostname that is already claimed
     fn insert(&self, id: ServerId, server: ActiveServer) -> Result<(), ServerInsertionError> {
         let vacant_entry = match self.active_servers.entry(id) {
             dashmap::Entry::Occupied(_) => return Err(ServerInsertionError::ServerIdExists),
             dashmap::Entry::Vacant(vacant) => vacant,
         };
 
-        let add_hostnames = || {
-            for (index, hostname) in server.hostnames.iter().cloned().enumerate() {
-                match self.hostname_index.entry(hostname) {
-                    dashmap::Entry::Occupied(_) => {
-                        return ControlFlow::Break(index);
-                    }
-                    dashmap::Entry::Vacant(empty) => empty.insert(id),
-                };
-            }
-            ControlFlow::Continue(())
-        };
-
-        if let ControlFlow::Break(conflict_index) = add_hostnames() {
-            // Undo addition when error encountered
-            self.drop_index(&server, Some(conflict_index));
+        for hostname in &server.hostnames {
+            let mut ids = self.hostname_index.entry(hostname.clone()).or_default();
 
-            return Err(ServerInsertionError::HostnameExists(HostnameExistsError {
-                hostname: server.hostnames[conflict_index].clone(),
-            }));
+            if let Err(index) = ids.binary_search(&id) {
+                ids.insert(index, id);
+            }
         }
 
         vacant_entry.insert(server);
@@ -132,23 +272,76 @@ impl DiscoveredServers {
         Ok(())
     }
 
-    fn remove(&self, id: ServerId) -> Option<ActiveServer> {
-        let (id, server) = self.active_servers.remove(&id)?;
+    /// Receive the commands sent to the discovery service
+    ///
+    /// Only one discovery service can receive them, so this returns `None` after the first call.
+    fn take_commands(&self) -> Option<mpsc::UnboundedReceiver<ServerCommand>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.commands.set(sender).ok().map(|()| receiver)
+    }
 
-        self.drop_index(&server, None);
+    /// Record a server that exists but is not running, replacing any previous record of it
+    fn insert_stopped(&self, id: ServerId, server: StoppedServer) {
+        self.remove_stopped(id);
+
+        for hostname in &server.hostnames {
+            self.stopped_hostname_index.insert(hostname.clone(), id);
+        }
+
+        self.stopped_servers.insert(id, server);
+    }
+
+    fn remove_stopped(&self, id: ServerId) -> Option<StoppedServer> {
+        let (_, server) = self.stopped_servers.remove(&id)?;
+
+        for hostname in &server.hostnames {
+            self.stopped_hostname_index
+                .remove_if(hostname, |_, server_id| *server_id == id);
+        }
 
         Some(server)
     }
 
-    fn drop_index(&self, server: &ActiveServer, until: Option<usize>) {
-        let range = match until {
-            Some(until) => (Bound::Unbounded, Bound::Excluded(until)),
-            None => (Bound::Unbounded, Bound::Unbounded),
-        };
+    /// Record that a server is running, moving it out of the stopped servers
+    fn mark_running(&self, id: ServerId, server: ActiveServer) -> Result<(), ServerInsertionError> {
+        self.remove_stopped(id);
+        self.sleeping_servers.remove(&id);
+
+        self.insert(id, server)
+    }
+
+    /// Record that a server stopped, so it can be started again
+    fn mark_stopped(&self, id: ServerId, server: StoppedServer) {
+        self.remove(id);
+        self.starting_servers.remove(&id);
 
-        for hostname in &server.hostnames[range] {
-            self.hostname_index.remove(hostname);
+        self.insert_stopped(id, server);
+    }
+
+    /// Forget a server that failed to start, so that starting it can be tried again
+    fn fail_starting(&self, id: ServerId) {
+        self.starting_servers.remove(&id);
+    }
+
+    /// Forget that a server was put to sleep, because it failed to stop or no longer exists
+    fn clear_sleeping(&self, id: ServerId) {
+        self.sleeping_servers.remove(&id);
+    }
+
+    fn remove(&self, id: ServerId) -> Option<ActiveServer> {
+        let (_, server) = self.active_servers.remove(&id)?;
+
+        for hostname in &server.hostnames {
+            if let Some(mut ids) = self.hostname_index.get_mut(hostname) {
+                ids.retain(|server_id| *server_id != id);
+            }
+
+            self.hostname_index
+                .remove_if(hostname, |_, ids| ids.is_empty());
         }
+
+        Some(server)
     }
 }
 
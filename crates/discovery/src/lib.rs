@@ -0,0 +1,5 @@
+mod discovery;
+
+pub use discovery::{
+    begin, ActiveServer, DiscoveredServers, ServerCommand, ServerId, StoppedServer,
+};
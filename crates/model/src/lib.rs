@@ -2,25 +2,65 @@ use std::{fmt::Display, net::SocketAddr, sync::Arc};
 
 use serde::Deserialize;
 
+/// A hostname, normalized on construction so that equivalent spellings compare equal
+///
+/// Internationalized names are converted to their ASCII (punycode) form, letters are
+/// lowercased and a trailing dot is removed.
 #[derive(Debug, Deserialize, schemars::JsonSchema, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
-#[serde(transparent)]
+#[serde(from = "String")]
 pub struct Hostname(Arc<str>);
 
+impl Hostname {
+    fn normalize(hostname: &str) -> Option<String> {
+        let trimmed = hostname.strip_suffix('.').unwrap_or(hostname);
+
+        let normalized = match idna::domain_to_ascii(trimmed) {
+            Ok(ascii) => ascii,
+            // Not a valid domain name, but still usable as a routing key
+            Err(_) => trimmed.to_ascii_lowercase(),
+        };
+
+        (normalized != hostname).then_some(normalized)
+    }
+
+    /// Whether this is a valid DNS name, rather than only a routing key
+    ///
+    /// Checked on the normalized form, so internationalized names are valid once in punycode.
+    pub fn is_dns_name(&self) -> bool {
+        !self.0.is_empty()
+            && self.0.len() <= 253
+            && self.0.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label
+                        .bytes()
+                        .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+            })
+    }
+}
+
 impl From<String> for Hostname {
     fn from(value: String) -> Self {
-        Hostname(Arc::from(value))
+        Hostname(Arc::from(Hostname::normalize(&value).unwrap_or(value)))
     }
 }
 
 impl From<&str> for Hostname {
     fn from(value: &str) -> Self {
-        Hostname(Arc::from(value))
+        Hostname(Arc::from(
+            Hostname::normalize(value).as_deref().unwrap_or(value),
+        ))
     }
 }
 
 impl From<Arc<str>> for Hostname {
     fn from(value: Arc<str>) -> Self {
-        Hostname(value)
+        match Hostname::normalize(&value) {
+            Some(normalized) => Hostname(Arc::from(normalized)),
+            None => Hostname(value),
+        }
     }
 }
 
@@ -151,3 +191,43 @@ impl schemars::JsonSchema for Upstream {
         gen.root_schema_for::<&str>()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::Hostname;
+
+    #[test]
+    fn hostname_normalization() {
+        for (raw, normalized) in [
+            ("play.example.com", "play.example.com"),
+            ("Play.Example.com.", "play.example.com"),
+            ("BÜCHER.example", "xn--bcher-kva.example"),
+            ("xn--bcher-kva.example", "xn--bcher-kva.example"),
+            ("127.0.0.1", "127.0.0.1"),
+            ("Not A..Domain", "not a..domain"),
+        ] {
+            assert_eq!(Hostname::from(raw).as_ref(), normalized, "{raw}");
+            assert_eq!(
+                Hostname::from(String::from(raw)).as_ref(),
+                normalized,
+                "{raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn dns_names() {
+        for (hostname, valid) in [
+            ("play.example.com", true),
+            ("BÜCHER.example", true),
+            ("127.0.0.1", true),
+            ("localhost", true),
+            ("Not A..Domain", false),
+            ("-play.example.com", false),
+            ("<img src=x onerror=alert(1)>", false),
+            ("", false),
+        ] {
+            assert_eq!(Hostname::from(hostname).is_dns_name(), valid, "{hostname}");
+        }
+    }
+}
@@ -0,0 +1,22 @@
+#![no_main]
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/proto/mod.rs"]
+mod proto;
+
+use proto::{
+    codec::{encode, Decode, Reader},
+    nbt::Tag,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tag) = Tag::decode(&mut Reader::new(data, 0)) {
+        // Whatever was read must be written the same way once read back
+        let written = encode(&tag);
+        let read_back = Tag::decode(&mut Reader::new(&written, 0)).unwrap();
+
+        assert_eq!(encode(&read_back), written);
+    }
+});
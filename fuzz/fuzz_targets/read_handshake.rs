@@ -0,0 +1,22 @@
+#![no_main]
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/proto/mod.rs"]
+mod proto;
+
+use proto::{io::read_handshake, limits::ProtocolLimits};
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    // Errors are expected, only panics are failures
+    let _ = runtime.block_on(read_handshake(
+        &mut { data },
+        &["///".to_string()],
+        &ProtocolLimits::default(),
+    ));
+});
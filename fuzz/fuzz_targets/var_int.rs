@@ -0,0 +1,19 @@
+#![no_main]
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/proto/mod.rs"]
+mod proto;
+
+use proto::codec::{encode, Decode, Reader, VarInt};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(var_int) = VarInt::decode(&mut Reader::new(data, 0)) {
+        // Whatever was read must survive a round trip
+        let written = encode(&var_int);
+        let read_back = VarInt::decode(&mut Reader::new(&written, 0)).unwrap();
+
+        assert_eq!(read_back, var_int);
+    }
+});
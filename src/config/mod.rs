@@ -1,7 +1,14 @@
 use base64::Engine;
-use schema::{Config, GenericConfig, PlaceholderServerConfig, PlaceholderServerResponses};
+use schema::{
+    AccessConfig, Config, GenericConfig, PlaceholderServerConfig, PlaceholderServerResponses,
+    PlayerEntry, ServerOptions,
+};
 use serde::de::DeserializeOwned;
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::{fs, io};
 use tracing::{trace_span, Instrument};
 use tracing_error::{InstrumentError, TracedError};
@@ -20,10 +27,26 @@ async fn load_toml<T: DeserializeOwned>(path: &Path) -> Result<T, TracedError<io
             .await
             .map_err(InstrumentError::in_current_span)?,
     )
-    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    .map_err(io::Error::other)
     .map_err(InstrumentError::in_current_span)
 }
 
+/// Read a PNG file into the base64 data URL sent to clients
+async fn read_favicon(
+    working_directory: &Path,
+    favicon: &str,
+) -> Result<String, TracedError<io::Error>> {
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64::prelude::BASE64_STANDARD.encode(
+            &fs::read(working_directory.join(favicon))
+                .instrument(trace_span!("fs::read"))
+                .await
+                .map_err(InstrumentError::in_current_span)?
+        )
+    ))
+}
+
 /// Convert the favicon from a URL to the rendered base64 data
 #[tracing::instrument(name = "config::load_favicon")]
 async fn load_favicon(
@@ -31,23 +54,133 @@ async fn load_favicon(
     response: StatusResponse,
 ) -> Result<StatusResponse, TracedError<io::Error>> {
     Ok(StatusResponse {
-        favicon: if let Some(favicon) = response.favicon {
-            Some(format!(
-                "data:image/png;base64,{}",
-                base64::prelude::BASE64_STANDARD.encode(
-                    &fs::read(working_directory.join(favicon))
-                        .instrument(trace_span!("fs::read"))
-                        .await
-                        .map_err(InstrumentError::in_current_span)?
-                )
-            ))
-        } else {
-            None
+        favicon: match response.favicon {
+            Some(favicon) => Some(read_favicon(working_directory, &favicon).await?),
+            None => None,
         },
         ..response
     })
 }
 
+/// Convert the favicon overriding a server's status from a URL to the rendered base64 data
+#[tracing::instrument(name = "config::load_status_favicon", skip(options))]
+async fn load_status_favicon(
+    config_directory: &Path,
+    options: &mut Arc<ServerOptions>,
+) -> Result<(), TracedError<io::Error>> {
+    let Some(favicon) = options
+        .status
+        .as_ref()
+        .and_then(|status| status.favicon.as_ref())
+    else {
+        return Ok(());
+    };
+
+    let favicon = read_favicon(config_directory, favicon).await?;
+    if let Some(status) = &mut Arc::make_mut(options).status {
+        status.favicon = Some(favicon);
+    }
+
+    Ok(())
+}
+
+/// Refuse to forward player info the proxy never authenticated, unless the config says it may
+fn check_forwarding(options: &ServerOptions) -> Result<(), TracedError<io::Error>> {
+    let Some(forwarding) = options
+        .forwarding
+        .as_ref()
+        .filter(|forwarding| !forwarding.allows_unauthenticated())
+    else {
+        return Ok(());
+    };
+
+    let error = io::Error::other(format!(
+        "{forwarding} forwarding lets anyone join the upstream as any player, since mcproxy does \
+         not authenticate players; set `allow_unauthenticated = true` in `forwarding` to accept this"
+    ));
+
+    Err(InstrumentError::in_current_span(error))
+}
+
+/// Load a placeholder response from its own file, relative to the config directory
+#[tracing::instrument(name = "config::load_response")]
+async fn load_response(
+    config_directory: &Path,
+    path: &Option<PathBuf>,
+) -> Result<Option<StatusResponse>, TracedError<io::Error>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    let config_file = config_directory
+        .join(path)
+        .canonicalize()
+        .map_err(InstrumentError::in_current_span)?;
+
+    Ok(Some(load_response_file(&config_file).await?))
+}
+
+/// Load a placeholder response from the canonical path to its file
+async fn load_response_file(config_file: &Path) -> Result<StatusResponse, TracedError<io::Error>> {
+    let config_directory = config_file.parent().expect("path should have a parent");
+
+    load_favicon(config_directory, load_toml(config_file).await?).await
+}
+
+/// Load the placeholder responses of a server, sharing any loaded from the same file before
+#[tracing::instrument(name = "config::load_server_responses", skip(options, loaded))]
+async fn load_server_responses(
+    config_directory: &Path,
+    options: &mut Arc<ServerOptions>,
+    loaded: &mut HashMap<PathBuf, Arc<StatusResponse>>,
+) -> Result<(), TracedError<io::Error>> {
+    let files = Arc::make_mut(options).responses.files_mut();
+
+    for file in files.into_iter().flatten() {
+        let config_file = config_directory
+            .join(&file.path)
+            .canonicalize()
+            .map_err(InstrumentError::in_current_span)?;
+
+        let response = match loaded.get(&config_file) {
+            Some(response) => response.clone(),
+            None => {
+                let response = Arc::new(load_response_file(&config_file).await?);
+                loaded.insert(config_file, response.clone());
+
+                response
+            }
+        };
+
+        file.response = Some(response);
+    }
+
+    Ok(())
+}
+
+/// Load the vanilla `whitelist.json` or `banned-players.json` files of an access config,
+/// relative to the config directory
+#[tracing::instrument(name = "config::load_player_lists", skip(access))]
+async fn load_player_lists(
+    config_directory: &Path,
+    access: &mut AccessConfig,
+) -> Result<(), TracedError<io::Error>> {
+    for file in access.files_mut().into_iter().flatten() {
+        let entries = serde_json::from_str::<Vec<PlayerEntry>>(
+            &fs::read_to_string(config_directory.join(&file.path))
+                .instrument(trace_span!("fs::read_to_string"))
+                .await
+                .map_err(InstrumentError::in_current_span)?,
+        )
+        .map_err(io::Error::other)
+        .map_err(InstrumentError::in_current_span)?;
+
+        file.entries = entries.into();
+    }
+
+    Ok(())
+}
+
 #[tracing::instrument(name = "config::load")]
 pub async fn load(path: &Path) -> Result<Config, TracedError<io::Error>> {
     let current_directory = std::env::current_dir().map_err(InstrumentError::in_current_span)?;
@@ -59,41 +192,294 @@ pub async fn load(path: &Path) -> Result<Config, TracedError<io::Error>> {
         .parent()
         .expect("at this point, path should have a parent");
 
-    let raw = load_toml::<GenericConfig<Raw>>(&config_file).await?;
+    let mut raw = load_toml::<GenericConfig<Raw>>(&config_file).await?;
+
+    let options = raw.static_servers.options_mut().chain(
+        raw.pattern_servers
+            .iter_mut()
+            .map(|server| &mut server.options),
+    );
+    let mut loaded = HashMap::new();
+    for options in options {
+        check_forwarding(options)?;
+        load_status_favicon(config_directory, options).await?;
+        load_server_responses(config_directory, options, &mut loaded).await?;
+        if !options.access.is_empty() {
+            load_player_lists(config_directory, &mut Arc::make_mut(options).access).await?;
+        }
+    }
+    load_player_lists(config_directory, &mut raw.access).await?;
 
     Ok(Config {
         ui: raw.ui,
         static_servers: raw.static_servers,
+        pattern_servers: raw.pattern_servers,
+        hostname_suffix_separators: raw.hostname_suffix_separators,
+        default_upstream: raw.default_upstream,
         proxy: raw.proxy,
+        discovery: raw.discovery,
+        sleep: raw.sleep,
+        health_check: raw.health_check,
+        maintenance: raw.maintenance,
+        access: raw.access,
         placeholder_server: PlaceholderServerConfig {
             responses: PlaceholderServerResponses {
-                offline: match &raw.placeholder_server.responses.offline {
-                    Some(path) => {
-                        let config_file = config_directory
-                            .join(path)
-                            .canonicalize()
-                            .map_err(InstrumentError::in_current_span)?;
-                        let config_directory =
-                            config_file.parent().expect("path should have a parent");
-
-                        Some(load_favicon(config_directory, load_toml(&config_file).await?).await?)
-                    }
-                    None => None,
-                },
-                no_mapping: match &raw.placeholder_server.responses.no_mapping {
-                    Some(path) => {
-                        let config_file = config_directory
-                            .join(path)
-                            .canonicalize()
-                            .map_err(InstrumentError::in_current_span)?;
-                        let config_directory =
-                            config_file.parent().expect("path should have a parent");
-
-                        Some(load_favicon(config_directory, load_toml(&config_file).await?).await?)
-                    }
-                    None => None,
-                },
+                offline: load_response(config_directory, &raw.placeholder_server.responses.offline)
+                    .await?,
+                no_mapping: load_response(
+                    config_directory,
+                    &raw.placeholder_server.responses.no_mapping,
+                )
+                .await?,
+                starting: load_response(
+                    config_directory,
+                    &raw.placeholder_server.responses.starting,
+                )
+                .await?,
+                sleeping: load_response(
+                    config_directory,
+                    &raw.placeholder_server.responses.sleeping,
+                )
+                .await?,
+                maintenance: load_response(
+                    config_directory,
+                    &raw.placeholder_server.responses.maintenance,
+                )
+                .await?,
             },
+            limbo: raw.placeholder_server.limbo,
         },
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, sync::Arc};
+
+    use mcproxy_model::Hostname;
+    use tokio::io;
+    use tracing_error::TracedError;
+
+    use super::{
+        load,
+        schema::{AccessRefusal, Config},
+    };
+    use crate::proto::packet::RawTextComponent;
+
+    /// A directory of config files, removed once dropped so that failing tests clean up too
+    struct ConfigDirectory(PathBuf);
+
+    impl ConfigDirectory {
+        /// Write each file to a new directory, by its path relative to it
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let directory = ConfigDirectory(
+                std::env::temp_dir().join(format!("mcproxy-{name}-{}", std::process::id())),
+            );
+
+            for (path, contents) in files {
+                let path = directory.0.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+
+            directory
+        }
+
+        async fn load(&self) -> Result<Config, TracedError<io::Error>> {
+            load(&self.0.join("config.toml")).await
+        }
+    }
+
+    impl Drop for ConfigDirectory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn server_responses() {
+        let directory = ConfigDirectory::new(
+            "config",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                        [placeholder_server.responses]
+                        [proxy]
+                        listen_address = "127.0.0.1:25565"
+                        [static_servers]
+                        "a.example.com" = { upstream = "127.0.0.1:25570", responses = { offline = "responses/down.toml" } }
+                        "b.example.com" = { upstream = "127.0.0.1:25571", responses = { offline = "./responses/../responses/down.toml", maintenance = "responses/down.toml" } }
+                        "c.example.com" = "127.0.0.1:25572"
+                    "#,
+                ),
+                (
+                    "responses/down.toml",
+                    r#"
+                        description = "Down for now"
+                        [version]
+                        name = "down"
+                        protocol = 0
+                    "#,
+                ),
+            ],
+        );
+
+        let config = directory.load().await.unwrap();
+
+        let options = |hostname: &str| {
+            config
+                .static_servers
+                .get_exact(&Hostname::from(hostname))
+                .unwrap()
+                .options
+        };
+        let (a, b) = (options("a.example.com"), options("b.example.com"));
+        let response = |file: &Option<super::schema::ResponseFile>| {
+            file.as_ref().unwrap().response.clone().unwrap()
+        };
+
+        // The same file is only loaded once
+        assert!(Arc::ptr_eq(
+            &response(&a.responses.offline),
+            &response(&b.responses.offline)
+        ));
+        assert!(Arc::ptr_eq(
+            &response(&a.responses.offline),
+            &response(&b.responses.maintenance)
+        ));
+        assert!(options("c.example.com").responses.offline.is_none());
+    }
+
+    #[tokio::test]
+    async fn access_lists() {
+        let directory = ConfigDirectory::new(
+            "access",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                        [placeholder_server.responses]
+                        [proxy]
+                        listen_address = "127.0.0.1:25565"
+                        [access]
+                        deny = ["Herobrine"]
+                        deny_file = "banned-players.json"
+                        [static_servers]
+                        "a.example.com" = { upstream = "127.0.0.1:25570", access = { allow = ["Notch"], allow_file = "whitelist.json", not_allowed_reason = "Members only" } }
+                    "#,
+                ),
+                (
+                    "whitelist.json",
+                    r#"[{"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "name": "jeb_"}]"#,
+                ),
+                (
+                    "banned-players.json",
+                    r#"[
+                        {"uuid": "61699b2e-d327-4a01-9f1e-0ea8c3f06bc6", "name": "Griefer", "created": "2024-01-01 00:00:00 +0000", "source": "Server", "expires": "forever", "reason": "Griefing"},
+                        {"uuid": "c06f8906-4c8a-4911-9c29-ea1dbd1aab82", "name": "Pardoned", "created": "2024-01-01 00:00:00 +0000", "source": "Server", "expires": "2024-01-02 00:00:00 +0000", "reason": "Spam"}
+                    ]"#,
+                ),
+            ],
+        );
+
+        let config = directory.load().await.unwrap();
+
+        let server = config
+            .static_servers
+            .get_exact(&Hostname::from("a.example.com"))
+            .unwrap()
+            .options
+            .clone();
+        let now = std::time::SystemTime::now();
+        let check = |name: &str| {
+            [&config.access, &server.access]
+                .into_iter()
+                .try_for_each(|access| access.check(name, now))
+        };
+
+        assert!(check("notch").is_ok());
+        assert!(check("jeb_").is_ok());
+        assert!(matches!(
+            check("Steve"),
+            Err(AccessRefusal::NotAllowed { .. })
+        ));
+        assert!(matches!(
+            check("herobrine"),
+            Err(AccessRefusal::Denied { .. })
+        ));
+        assert!(matches!(
+            config.access.check("griefer", now),
+            Err(AccessRefusal::Denied { reason: RawTextComponent::String(reason) })
+                if reason.ends_with("Reason: Griefing")
+        ));
+        // Expired bans no longer apply
+        assert!(config.access.check("Pardoned", now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn reject_uuid_only_access() {
+        let config = |access: &str| {
+            format!(
+                r#"
+                    [placeholder_server.responses]
+                    [proxy]
+                    listen_address = "127.0.0.1:25565"
+                    [access]
+                    {access}
+                    [static_servers]
+                "#
+            )
+        };
+
+        // Players can log in with anyone's UUID, so it can not be all that is known about them
+        let directory = ConfigDirectory::new(
+            "uuid-only",
+            &[(
+                "config.toml",
+                &config(r#"deny = ["853c80ef-3c37-49fd-aa49-938b674adae6"]"#),
+            )],
+        );
+        assert!(directory.load().await.is_err());
+
+        let directory = ConfigDirectory::new(
+            "nameless",
+            &[
+                ("config.toml", &config(r#"allow_file = "whitelist.json""#)),
+                (
+                    "whitelist.json",
+                    r#"[{"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5"}]"#,
+                ),
+            ],
+        );
+        assert!(directory.load().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reject_malformed_ban_expiry() {
+        // A typo must not turn into a ban that never ends
+        let directory = ConfigDirectory::new(
+            "malformed-expiry",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                        [placeholder_server.responses]
+                        [proxy]
+                        listen_address = "127.0.0.1:25565"
+                        [access]
+                        deny_file = "banned-players.json"
+                        [static_servers]
+                    "#,
+                ),
+                (
+                    "banned-players.json",
+                    r#"[{"uuid": "61699b2e-d327-4a01-9f1e-0ea8c3f06bc6", "name": "Griefer", "created": "2024-01-01 00:00:00 +0000", "source": "Server", "expires": "2024-13-01 00:00:00 +0000", "reason": "Griefing"}]"#,
+                ),
+            ],
+        );
+
+        let error = directory.load().await.unwrap_err();
+        assert!(format!("{error:?}").contains("Griefer"), "{error:?}");
+    }
+}
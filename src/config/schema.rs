@@ -1,9 +1,33 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    fmt::{self, Display, Formatter},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
+use ipnet::IpNet;
 use mcproxy_model::{Hostname, Upstream};
 use serde::Deserialize;
+use smol_str::SmolStr;
+use uuid::Uuid;
 
 use super::util::{Elaborated, Marker};
+use crate::{
+    proto::{
+        forwarding::PlayerInfoForwarding,
+        limits::ProtocolLimits,
+        packet::{
+            response::{Player, StatusResponse},
+            RawTextComponent,
+        },
+        proxy_protocol::ProxyProtocolVersion,
+    },
+    routing::{
+        balance::BalanceStrategy,
+        pattern::{PatternServer, StaticServers, UpstreamTemplate},
+    },
+};
 
 pub type Config = GenericConfig<Elaborated>;
 
@@ -13,7 +37,33 @@ pub struct GenericConfig<T: Marker> {
     /// The config for the placeholder server
     pub placeholder_server: PlaceholderServerConfig<T>,
     /// The mapping of servers to their addresses
-    pub static_servers: HashMap<Hostname, Upstream>,
+    ///
+    /// Whole labels of a hostname may be replaced by a `*` wildcard matching exactly one label,
+    /// which the upstream host can reference as `{1}`, `{2}`, ... from left to right. Exact
+    /// hostnames always win, then the most specific wildcard, comparing labels from the right.
+    #[cfg_attr(
+        test,
+        schemars(with = "std::collections::HashMap<String, RawServerConfig>")
+    )]
+    pub static_servers: StaticServers,
+    /// Regular expression routing rules, tried in order when nothing in `static_servers` matches
+    #[serde(default)]
+    #[cfg_attr(
+        test,
+        schemars(with = "Vec<crate::routing::pattern::RawPatternServer>")
+    )]
+    pub pattern_servers: Vec<PatternServer>,
+    /// Separators after which anything appended to the handshake address is ignored
+    ///
+    /// Some front ends append a payload to the address, such as `///` used by TCPShield
+    #[serde(default)]
+    pub hostname_suffix_separators: Vec<String>,
+    /// The upstream, or list of upstreams tried in order, for hostnames without any mapping
+    ///
+    /// When unset, those connections are answered with the `no_mapping` placeholder instead
+    #[serde(default, deserialize_with = "super::util::one_or_many")]
+    #[cfg_attr(test, schemars(with = "Option<super::util::OneOrMany<Upstream>>"))]
+    pub default_upstream: Vec<Upstream>,
     /// Setting for the UI Server
     ///
     /// Can not be live-reloaded
@@ -22,6 +72,431 @@ pub struct GenericConfig<T: Marker> {
     ///
     /// Can not be live-reloaded
     pub proxy: ProxyConfig,
+    /// Settings for service discovery
+    ///
+    /// Enabling or disabling discovery can not be live-reloaded
+    pub discovery: Option<DiscoveryConfig>,
+    /// Settings for putting idle servers to sleep
+    ///
+    /// Enabling or disabling sleep can not be live-reloaded
+    pub sleep: Option<SleepConfig>,
+    /// Settings for pinging every known upstream in the background
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    /// Hostnames answered with the maintenance response, which only the bypass list can join
+    ///
+    /// Can also be turned on and off through the UI, which takes precedence until the next restart
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    /// Players allowed or denied to join any hostname
+    ///
+    /// A server's own `access` is checked as well, and both have to let the player in
+    #[serde(default)]
+    pub access: AccessConfig,
+}
+
+/// A server that hostnames are routed to
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "RawServerConfig")]
+pub struct ServerConfig {
+    /// The upstream, or every member of a pool that connections are balanced across
+    pub upstreams: Vec<UpstreamTemplate>,
+    pub options: Arc<ServerOptions>,
+}
+
+impl From<RawServerConfig> for ServerConfig {
+    fn from(value: RawServerConfig) -> Self {
+        match value {
+            RawServerConfig::Upstream(upstream) => ServerConfig {
+                upstreams: vec![upstream],
+                options: Arc::default(),
+            },
+            RawServerConfig::Pool(upstreams) => ServerConfig {
+                upstreams,
+                options: Arc::default(),
+            },
+            RawServerConfig::Detailed { upstream, options } => ServerConfig {
+                upstreams: upstream,
+                options: Arc::new(*options),
+            },
+        }
+    }
+}
+
+impl Display for ServerConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let upstreams = self
+            .upstreams
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let options = self.options.to_string();
+
+        match options.is_empty() {
+            true => write!(f, "{upstreams}"),
+            false => write!(f, "{upstreams} ({options})"),
+        }
+    }
+}
+
+/// A server, given either as just its address, a list of addresses to pool, or as a table
+/// with options
+#[derive(Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum RawServerConfig {
+    Upstream(UpstreamTemplate),
+    Pool(Vec<UpstreamTemplate>),
+    Detailed {
+        /// The upstream to route to, or a list of upstreams to balance connections across
+        #[serde(deserialize_with = "super::util::one_or_many")]
+        #[cfg_attr(test, schemars(with = "super::util::OneOrMany<UpstreamTemplate>"))]
+        upstream: Vec<UpstreamTemplate>,
+        #[serde(flatten)]
+        options: Box<ServerOptions>,
+    },
+}
+
+/// Options for how connections to a server are handled
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ServerOptions {
+    /// Whether clients transferred here from another server (1.20.5+) are proxied
+    pub accept_transfers: bool,
+    /// Disconnect reason shown to refused transfers, instead of a generic message
+    pub transfer_refused_reason: Option<RawTextComponent>,
+    /// Version of the PROXY protocol header to send the upstream, carrying the client's address
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    /// How to forward the player's address to the upstream on login
+    ///
+    /// Nothing authenticates players on the way, so the upstream sees offline mode UUIDs
+    pub forwarding: Option<PlayerInfoForwarding>,
+    /// Command, and its arguments, run to put the upstream to sleep once it is idle
+    pub stop_command: Option<Vec<String>>,
+    /// Command, and its arguments, run to wake the upstream when a player joins while it sleeps
+    pub start_command: Option<Vec<String>>,
+    /// How connections are spread across the upstreams of a pool
+    pub balance: BalanceStrategy,
+    /// Milliseconds to wait for a connection to an upstream before giving up on it
+    pub connect_timeout: u64,
+    /// How many more times to try connecting to an upstream after the first attempt fails
+    pub connect_retries: u32,
+    /// Milliseconds to wait before the first retry, doubling for every retry after it
+    pub retry_backoff: u64,
+    /// Upstreams tried in order once every attempt at the server's own upstreams failed
+    #[serde(deserialize_with = "super::util::one_or_many")]
+    #[cfg_attr(test, schemars(with = "Option<super::util::OneOrMany<Upstream>>"))]
+    pub fallback: Vec<Upstream>,
+    /// Answer pings from the proxy, with the upstream's status changed by these overrides
+    pub status: Option<StatusOverrides>,
+    /// Placeholder responses used instead of the global ones in `placeholder_server.responses`
+    pub responses: ServerResponses,
+    /// Players allowed or denied to join this server, on top of the global `access`
+    pub access: AccessConfig,
+}
+
+impl ServerOptions {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout)
+    }
+
+    /// How long to wait before the given retry, counting from zero
+    pub fn retry_backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff).saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            accept_transfers: true,
+            transfer_refused_reason: None,
+            proxy_protocol: None,
+            forwarding: None,
+            stop_command: None,
+            start_command: None,
+            balance: BalanceStrategy::default(),
+            connect_timeout: 5000,
+            connect_retries: 0,
+            retry_backoff: 250,
+            fallback: Vec::new(),
+            status: None,
+            responses: ServerResponses::default(),
+            access: AccessConfig::default(),
+        }
+    }
+}
+
+/// Lists the options that differ from their defaults
+impl Display for ServerOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let ServerOptions {
+            accept_transfers,
+            transfer_refused_reason: _,
+            proxy_protocol,
+            forwarding,
+            stop_command,
+            start_command: _,
+            balance,
+            connect_timeout: _,
+            connect_retries,
+            retry_backoff: _,
+            fallback,
+            status,
+            responses,
+            access,
+        } = self;
+
+        let mut options = Vec::new();
+
+        if !accept_transfers {
+            options.push("refuses transfers".to_string());
+        }
+        if let Some(version) = proxy_protocol {
+            options.push(format!("proxy protocol {version}"));
+        }
+        if let Some(forwarding) = forwarding {
+            options.push(format!("{forwarding} forwarding"));
+        }
+        if stop_command.is_some() {
+            options.push("sleeps when idle".to_string());
+        }
+        if *balance != BalanceStrategy::default() {
+            options.push(format!("balanced by {balance}"));
+        }
+        if *connect_retries > 0 {
+            options.push(format!("{connect_retries} connect retries"));
+        }
+        if !fallback.is_empty() {
+            let fallback = fallback
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            options.push(format!("falls back to {fallback}"));
+        }
+        if status.is_some() {
+            options.push("overrides status".to_string());
+        }
+        let ServerResponses {
+            offline,
+            starting,
+            sleeping,
+            maintenance,
+        } = responses;
+        for (name, response) in [
+            ("offline", offline),
+            ("starting", starting),
+            ("sleeping", sleeping),
+            ("maintenance", maintenance),
+        ] {
+            if let Some(response) = response {
+                options.push(format!("{name} response {}", response.path.display()));
+            }
+        }
+        if !access.is_empty() {
+            options.push("restricts access".to_string());
+        }
+
+        f.write_str(&options.join(", "))
+    }
+}
+
+/// The placeholder responses of a single server, each given as the path to its config file
+/// relative to the config file
+///
+/// When the server is starting, sleeping or under maintenance, its own response for that is
+/// used, then its own `offline` response, and only then the global responses.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ServerResponses {
+    #[cfg_attr(test, schemars(with = "Option<PathBuf>"))]
+    pub offline: Option<ResponseFile>,
+    #[cfg_attr(test, schemars(with = "Option<PathBuf>"))]
+    pub starting: Option<ResponseFile>,
+    #[cfg_attr(test, schemars(with = "Option<PathBuf>"))]
+    pub sleeping: Option<ResponseFile>,
+    #[cfg_attr(test, schemars(with = "Option<PathBuf>"))]
+    pub maintenance: Option<ResponseFile>,
+}
+
+impl ServerResponses {
+    pub fn files_mut(&mut self) -> [&mut Option<ResponseFile>; 4] {
+        [
+            &mut self.offline,
+            &mut self.starting,
+            &mut self.sleeping,
+            &mut self.maintenance,
+        ]
+    }
+}
+
+/// A placeholder response, loaded from its file along with the config
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "PathBuf")]
+pub struct ResponseFile {
+    pub path: PathBuf,
+    /// Only missing until the config has finished loading
+    pub response: Option<Arc<StatusResponse>>,
+}
+
+impl From<PathBuf> for ResponseFile {
+    fn from(path: PathBuf) -> Self {
+        ResponseFile {
+            path,
+            response: None,
+        }
+    }
+}
+
+impl ResponseFile {
+    pub fn response(&self) -> Option<&StatusResponse> {
+        self.response.as_deref()
+    }
+}
+
+/// Parts of an upstream's status replaced before it is sent to the client
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct StatusOverrides {
+    /// Message of the day shown in the server list
+    pub description: Option<RawTextComponent>,
+    /// Path to the PNG icon, relative to the config file
+    pub favicon: Option<String>,
+    /// Version shown to clients whose protocol version the upstream does not support
+    pub version_name: Option<SmolStr>,
+    /// Lines shown when hovering over the player count, instead of the players online
+    pub sample_players: Option<Vec<SmolStr>>,
+}
+
+impl StatusOverrides {
+    /// The upstream's status, with every override applied
+    pub fn apply(&self, status: &StatusResponse) -> StatusResponse {
+        let mut status = status.clone();
+
+        if let Some(description) = &self.description {
+            status.description = description.clone();
+        }
+        if let Some(favicon) = &self.favicon {
+            status.favicon = Some(favicon.clone());
+        }
+        if let Some(version_name) = &self.version_name {
+            status.version.name = version_name.clone();
+        }
+        if let (Some(sample_players), Some(players)) = (&self.sample_players, &mut status.players) {
+            players.sample = sample_players
+                .iter()
+                .map(|name| Player {
+                    name: name.clone(),
+                    id: Uuid::nil(),
+                })
+                .collect();
+        }
+
+        status
+    }
+}
+
+/// Accepted without the `discovery` feature so the same config loads either way, but only read
+/// with it
+#[derive(Deserialize, Debug, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[cfg_attr(not(feature = "discovery"), allow(dead_code))]
+pub struct DiscoveryConfig {
+    /// Which source wins when a hostname is both statically mapped and discovered
+    #[serde(default)]
+    pub precedence: RoutePrecedence,
+    /// Start a stopped server when a player tries to join it
+    #[serde(default)]
+    pub wake_on_connect: bool,
+    /// Stop discovered servers once they are idle, as configured in `sleep`
+    #[serde(default)]
+    pub sleep_when_idle: bool,
+    /// How connections are spread across discovered servers sharing a hostname
+    #[serde(default)]
+    pub balance: BalanceStrategy,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct SleepConfig {
+    /// Seconds a server must have no players online before it is put to sleep
+    #[serde(default = "SleepConfig::default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Seconds between checks of how many players are online
+    #[serde(default = "SleepConfig::default_check_interval")]
+    pub check_interval: u64,
+}
+
+impl SleepConfig {
+    fn default_idle_timeout() -> u64 {
+        600
+    }
+
+    fn default_check_interval() -> u64 {
+        60
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout)
+    }
+
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_secs(self.check_interval)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Seconds between pings of every upstream
+    pub interval: u64,
+    /// Milliseconds to wait for an upstream to answer a ping before it counts as unhealthy
+    pub timeout: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            interval: 10,
+            timeout: 3000,
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RoutePrecedence {
+    /// Prefer the mapping in `static_servers`
+    #[default]
+    Static,
+    /// Prefer the mapping found through service discovery
+    Discovered,
+}
+
+impl Display for RoutePrecedence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutePrecedence::Static => f.write_str("static"),
+            RoutePrecedence::Discovered => f.write_str("discovered"),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -29,13 +504,134 @@ pub struct GenericConfig<T: Marker> {
 pub struct ProxyConfig {
     /// Address to bind the Minecraft proxy to
     pub listen_address: SocketAddr,
+    /// Networks of load balancers whose connections begin with a PROXY protocol header
+    ///
+    /// The client address from the header is used in place of the load balancer's.
+    /// Connections from anywhere else are never expected to send one.
+    #[serde(default)]
+    #[cfg_attr(test, schemars(with = "Vec<String>"))]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Limits on what clients and upstreams may send, past which they are disconnected
+    #[serde(default)]
+    pub limits: ProtocolLimits,
+    /// Limits on how often clients may connect, past which they are disconnected right away
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+/// Connection limits, all of which are off unless configured
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// How often a single address may connect
+    pub per_ip: Option<TokenBucketConfig>,
+    /// How often all addresses in a network may connect together
+    pub per_network: Option<NetworkRateLimitConfig>,
+    /// Most connections that may be waiting on their handshake at once, from all clients together
+    pub max_handshaking: Option<usize>,
+    /// Temporarily ban addresses that keep going over their rate limits
+    pub ban: Option<BanConfig>,
+}
+
+/// A token bucket, which allows bursts of connections and refills at a steady rate
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct TokenBucketConfig {
+    /// Connections that may be made at once
+    pub burst: u32,
+    /// Connections that may be made every second after a burst
+    pub per_second: f64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct NetworkRateLimitConfig {
+    #[serde(flatten)]
+    pub bucket: TokenBucketConfig,
+    /// Prefix length of the networks IPv4 addresses are grouped in
+    #[serde(default = "NetworkRateLimitConfig::default_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    /// Prefix length of the networks IPv6 addresses are grouped in
+    #[serde(default = "NetworkRateLimitConfig::default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+}
+
+impl NetworkRateLimitConfig {
+    fn default_ipv4_prefix() -> u8 {
+        24
+    }
+
+    fn default_ipv6_prefix() -> u8 {
+        48
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct BanConfig {
+    /// Times an address may go over its rate limits within the window before it is banned
+    pub strikes: u32,
+    /// Seconds over which strikes are counted
+    #[serde(default = "BanConfig::default_window")]
+    pub window: u64,
+    /// Seconds a ban lasts
+    #[serde(default = "BanConfig::default_duration")]
+    pub duration: u64,
+}
+
+impl BanConfig {
+    fn default_window() -> u64 {
+        60
+    }
+
+    fn default_duration() -> u64 {
+        600
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration)
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct PlaceholderServerConfig<T: Marker> {
     /// The responses config files
+    ///
+    /// Their descriptions, version names and player sample names may reference `{hostname}`,
+    /// `{upstream}`, `{client_version}`, `{last_seen_online}`, `{players_online_network}` and
+    /// `{wake_eta}`, which are filled in for every connection.
     pub responses: PlaceholderServerResponses<T>,
+    /// Hold players logging in to a starting server in a void world, instead of disconnecting
+    /// them, and transfer them once it is reachable
+    ///
+    /// Only 1.20.5 to 1.21.1 clients can be held, everyone else gets the `starting` response.
+    pub limbo: Option<LimboConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct LimboConfig {
+    /// Chat message shown to players in limbo, defaults to the `starting` response's description
+    pub message: Option<RawTextComponent>,
+    /// Seconds to wait for the server before disconnecting the player
+    #[serde(default = "LimboConfig::default_timeout")]
+    pub timeout: u64,
+}
+
+impl LimboConfig {
+    fn default_timeout() -> u64 {
+        300
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -45,6 +641,281 @@ pub struct PlaceholderServerResponses<T: Marker> {
     pub offline: Option<T::PointerType>,
     /// Response for server when no mapping exists
     pub no_mapping: Option<T::PointerType>,
+    /// Response for server when it was stopped and is starting up
+    ///
+    /// Its description is also the disconnect message for players who started it.
+    pub starting: Option<T::PointerType>,
+    /// Response for server when it was put to sleep for being idle
+    ///
+    /// Its description is also the disconnect message for players who can not wake it.
+    pub sleeping: Option<T::PointerType>,
+    /// Response for server while it is under maintenance
+    pub maintenance: Option<T::PointerType>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Put every hostname under maintenance
+    pub enabled: bool,
+    /// Hostnames under maintenance
+    pub hostnames: Vec<Hostname>,
+    /// Usernames of the players who can still join, ignoring case
+    pub bypass: Vec<String>,
+    /// Disconnect reason shown to everyone else, defaults to the maintenance response's description
+    pub reason: Option<RawTextComponent>,
+    /// Scheduled periods of maintenance
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+impl MaintenanceConfig {
+    /// Whether the config puts the hostname under maintenance at the given time
+    pub fn is_active(&self, hostname: &Hostname, now: SystemTime) -> bool {
+        self.enabled
+            || self.hostnames.contains(hostname)
+            || self.windows.iter().any(|window| {
+                (window.start..window.end).contains(&now)
+                    && (window.hostnames.is_empty() || window.hostnames.contains(hostname))
+            })
+    }
+
+    pub fn bypasses(&self, username: &str) -> bool {
+        self.bypass
+            .iter()
+            .any(|bypass| bypass.eq_ignore_ascii_case(username))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct MaintenanceWindow {
+    /// When maintenance starts, as a date and time with an offset like `2024-06-01T02:00:00Z`
+    #[serde(deserialize_with = "super::util::offset_datetime")]
+    #[cfg_attr(test, schemars(with = "String"))]
+    pub start: SystemTime,
+    /// When maintenance ends
+    #[serde(deserialize_with = "super::util::offset_datetime")]
+    #[cfg_attr(test, schemars(with = "String"))]
+    pub end: SystemTime,
+    /// Hostnames under maintenance during the window, every hostname if empty
+    #[serde(default)]
+    pub hostnames: Vec<Hostname>,
+}
+
+/// Players allowed or denied to log in, checked against their Login Start before it is proxied
+///
+/// The UUID a client sends is not verified by anyone until the upstream authenticates it, so
+/// entries are matched on their name, ignoring case, and can not be only a UUID.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct AccessConfig {
+    /// Names of the only players who may join, everyone may if this and `allow_file` are empty
+    pub allow: Vec<PlayerEntry>,
+    /// Path to a vanilla `whitelist.json` of players who may join, relative to the config file
+    #[cfg_attr(test, schemars(with = "Option<PathBuf>"))]
+    pub allow_file: Option<PlayerListFile>,
+    /// Names of players who may not join
+    pub deny: Vec<PlayerEntry>,
+    /// Path to a vanilla `banned-players.json` of players who may not join, relative to the
+    /// config file
+    #[cfg_attr(test, schemars(with = "Option<PathBuf>"))]
+    pub deny_file: Option<PlayerListFile>,
+    /// Disconnect reason shown to players missing from the allowlist
+    pub not_allowed_reason: Option<RawTextComponent>,
+    /// Disconnect reason shown to denied players, defaults to the reason they were banned for
+    pub denied_reason: Option<RawTextComponent>,
+}
+
+impl AccessConfig {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty()
+            && self.allow_file.is_none()
+            && self.deny.is_empty()
+            && self.deny_file.is_none()
+    }
+
+    pub fn files_mut(&mut self) -> [&mut Option<PlayerListFile>; 2] {
+        [&mut self.allow_file, &mut self.deny_file]
+    }
+
+    fn allowlist(&self) -> impl Iterator<Item = &PlayerEntry> {
+        self.allow
+            .iter()
+            .chain(self.allow_file.iter().flat_map(PlayerListFile::entries))
+    }
+
+    fn denylist(&self) -> impl Iterator<Item = &PlayerEntry> {
+        self.deny
+            .iter()
+            .chain(self.deny_file.iter().flat_map(PlayerListFile::entries))
+    }
+
+    /// Refuse the player if they are denied, or missing from a non-empty allowlist
+    pub fn check(&self, name: &str, now: SystemTime) -> Result<(), AccessRefusal> {
+        if let Some(entry) = self
+            .denylist()
+            .find(|entry| entry.matches(name) && !entry.has_expired(now))
+        {
+            return Err(AccessRefusal::Denied {
+                reason: self.denied_reason.clone().unwrap_or_else(|| {
+                    let reason = entry.reason.as_deref().unwrap_or("Banned by an operator.");
+                    RawTextComponent::String(format!(
+                        "You are banned from this server.\nReason: {reason}"
+                    ))
+                }),
+            });
+        }
+
+        let mut allowlist = self.allowlist().peekable();
+        if allowlist.peek().is_some() && !allowlist.any(|entry| entry.matches(name)) {
+            return Err(AccessRefusal::NotAllowed {
+                reason: self.not_allowed_reason.clone().unwrap_or_else(|| {
+                    RawTextComponent::String("You are not white-listed on this server!".to_string())
+                }),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a player was refused, with the disconnect reason to show them
+#[derive(Debug, Clone)]
+pub enum AccessRefusal {
+    Denied { reason: RawTextComponent },
+    NotAllowed { reason: RawTextComponent },
+}
+
+impl AccessRefusal {
+    pub fn reason(&self) -> &RawTextComponent {
+        match self {
+            AccessRefusal::Denied { reason } | AccessRefusal::NotAllowed { reason } => reason,
+        }
+    }
+}
+
+/// A player on an allowlist or denylist, as written in the config or a vanilla list file
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(try_from = "RawPlayerEntry")]
+pub struct PlayerEntry {
+    pub name: String,
+    /// When a ban ends
+    #[cfg_attr(test, schemars(skip))]
+    pub expires: Option<SystemTime>,
+    /// What a ban was for
+    pub reason: Option<String>,
+}
+
+impl PlayerEntry {
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    fn has_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+impl Display for PlayerEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Either a name in the config, or an entry of a vanilla list file
+#[derive(Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum RawPlayerEntry {
+    Name(String),
+    Vanilla {
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        /// `forever`, or a date and time like `2024-06-01 02:00:00 +0200`
+        expires: Option<String>,
+        reason: Option<String>,
+    },
+}
+
+/// Players can log in with any UUID until the upstream authenticates them, so entries which only
+/// have one would be trivial to get around, or to use against someone else
+impl TryFrom<RawPlayerEntry> for PlayerEntry {
+    type Error = String;
+
+    fn try_from(value: RawPlayerEntry) -> Result<Self, Self::Error> {
+        match value {
+            RawPlayerEntry::Name(name) => match name.parse::<Uuid>() {
+                Ok(uuid) => Err(format!(
+                    "{uuid} is a UUID, which players can fake before they are authenticated, use their name instead"
+                )),
+                Err(_) => Ok(PlayerEntry {
+                    name,
+                    ..Default::default()
+                }),
+            },
+            RawPlayerEntry::Vanilla {
+                name: None,
+                uuid: Some(uuid),
+                ..
+            } => Err(format!(
+                "the entry for {uuid} has no name, and players can fake their UUID before they are authenticated"
+            )),
+            RawPlayerEntry::Vanilla {
+                name: None,
+                uuid: None,
+                ..
+            } => Err("an entry has neither a name nor a UUID".to_string()),
+            RawPlayerEntry::Vanilla {
+                name: Some(name),
+                uuid: _,
+                expires,
+                reason,
+            } => {
+                let expires = match expires.as_deref() {
+                    // Bans that never end are written as `forever`
+                    None | Some("forever") => None,
+                    Some(text) => Some(super::util::vanilla_datetime(text).ok_or_else(|| {
+                        format!(
+                            "the entry for {name} expires {text:?}, which is neither `forever` nor a date and time like `2024-06-01 02:00:00 +0200`"
+                        )
+                    })?),
+                };
+
+                Ok(PlayerEntry {
+                    name,
+                    expires,
+                    reason,
+                })
+            }
+        }
+    }
+}
+
+/// A list of players, loaded from its vanilla JSON file along with the config
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "PathBuf")]
+pub struct PlayerListFile {
+    pub path: PathBuf,
+    /// Only empty until the config has finished loading
+    pub entries: Arc<[PlayerEntry]>,
+}
+
+impl From<PathBuf> for PlayerListFile {
+    fn from(path: PathBuf) -> Self {
+        PlayerListFile {
+            path,
+            entries: Arc::new([]),
+        }
+    }
+}
+
+impl PlayerListFile {
+    pub fn entries(&self) -> impl Iterator<Item = &PlayerEntry> {
+        self.entries.iter()
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, Copy)]
@@ -57,8 +928,11 @@ pub struct UiServerConfig {
 #[cfg(test)]
 mod test {
     use crate::{
-        config::{schema::GenericConfig, util::Raw},
-        proto::packet::response::StatusResponse,
+        config::{
+            schema::{GenericConfig, StatusOverrides},
+            util::Raw,
+        },
+        proto::packet::{response::StatusResponse, RawTextComponent},
     };
 
     fn generate_schema_for<T: ?Sized + schemars::JsonSchema>(filename: &str) {
@@ -70,7 +944,7 @@ mod test {
         std::fs::write(
             file,
             serde_json::to_string_pretty(
-                &schemars::SchemaGenerator::new(schemars::gen::SchemaSettings::draft07())
+                &schemars::SchemaGenerator::new(schemars::generate::SchemaSettings::draft07())
                     .into_root_schema_for::<T>(),
             )
             .unwrap(),
@@ -87,4 +961,42 @@ mod test {
     fn response_schema() {
         generate_schema_for::<StatusResponse>("response.schema.json");
     }
+
+    #[test]
+    fn status_overrides() {
+        let status = serde_json::from_str::<StatusResponse>(
+            r#"{"version":{"name":"Paper 1.21.1","protocol":767},"players":{"max":20,"online":2,"sample":[{"name":"Notch","id":"069a79f4-44e9-4726-a5be-fca90e38aaf5"}]},"description":"A Minecraft Server","favicon":"data:image/png;base64,AAAA"}"#,
+        )
+        .unwrap();
+
+        let unchanged = StatusOverrides::default().apply(&status);
+        assert_eq!(
+            serde_json::to_value(&unchanged).unwrap(),
+            serde_json::to_value(&status).unwrap()
+        );
+
+        let overridden = StatusOverrides {
+            description: Some(RawTextComponent::String("A network".to_string())),
+            favicon: None,
+            version_name: Some("1.21.x".into()),
+            sample_players: Some(vec!["play.example.com".into()]),
+        }
+        .apply(&status);
+        let players = overridden.players.as_ref().unwrap();
+        assert!(
+            matches!(&overridden.description, RawTextComponent::String(description) if description == "A network")
+        );
+        assert_eq!(overridden.favicon, status.favicon);
+        assert_eq!(
+            (
+                overridden.version.name.as_str(),
+                overridden.version.protocol
+            ),
+            ("1.21.x", 767)
+        );
+        assert_eq!((players.online, players.max), (2, 20));
+        assert_eq!(players.sample.len(), 1);
+        assert_eq!(players.sample[0].name, "play.example.com");
+        assert!(players.sample[0].id.is_nil());
+    }
 }
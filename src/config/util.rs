@@ -1,6 +1,14 @@
-use std::{fmt::Debug, path::PathBuf};
+use std::{
+    fmt::Debug,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{
+    de::{DeserializeOwned, Error},
+    Deserialize, Deserializer,
+};
+use toml::value::{Datetime, Offset};
 
 use crate::proto::packet::response::StatusResponse;
 
@@ -31,3 +39,120 @@ impl private::Sealed for Elaborated {}
 impl Marker for Elaborated {
     type PointerType = StatusResponse;
 }
+
+/// Either a single value or a list of values
+#[derive(Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// Deserialize a list that may also be written as a single value
+pub fn one_or_many<'d, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'d>,
+    T: Deserialize<'d>,
+{
+    OneOrMany::deserialize(deserializer).map(Vec::from)
+}
+
+/// Days from 1970-01-01 to the given date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Deserialize a TOML date and time with an offset, like `2024-06-01T02:00:00+02:00`
+pub fn offset_datetime<'d, D>(deserializer: D) -> Result<SystemTime, D::Error>
+where
+    D: Deserializer<'d>,
+{
+    let datetime = Datetime::deserialize(deserializer)?;
+    let (Some(date), Some(time), Some(offset)) = (datetime.date, datetime.time, datetime.offset)
+    else {
+        return Err(D::Error::custom(format!(
+            "{datetime} is missing its date, time or offset"
+        )));
+    };
+
+    let offset_minutes = match offset {
+        Offset::Z => 0,
+        Offset::Custom { minutes } => i64::from(minutes),
+    };
+    let seconds = days_from_civil(
+        i64::from(date.year),
+        i64::from(date.month),
+        i64::from(date.day),
+    ) * 86_400
+        + i64::from(time.hour) * 3600
+        + i64::from(time.minute) * 60
+        + i64::from(time.second)
+        - offset_minutes * 60;
+
+    let seconds = u64::try_from(seconds)
+        .map_err(|_| D::Error::custom(format!("{datetime} is before 1970")))?;
+
+    Ok(UNIX_EPOCH + Duration::new(seconds, time.nanosecond))
+}
+
+/// Parse the date and time vanilla servers write to `banned-players.json`, like
+/// `2024-06-01 02:00:00 +0200`
+pub fn vanilla_datetime(text: &str) -> Option<SystemTime> {
+    let mut parts = text.split_whitespace();
+    let (date, time, offset) = (parts.next()?, parts.next()?, parts.next()?);
+
+    let numbers = |text: &str, separator| {
+        let mut numbers = text.split(separator).map(str::parse::<i64>);
+        match (
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+        ) {
+            (Some(Ok(a)), Some(Ok(b)), Some(Ok(c)), None) => Some((a, b, c)),
+            _ => None,
+        }
+    };
+    let (year, month, day) = numbers(date, '-')?;
+    let (hour, minute, second) = numbers(time, ':')?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+
+    let (sign, offset) = match offset.split_at_checked(1)? {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+    let offset = offset
+        .parse::<i64>()
+        .ok()
+        .filter(|offset| offset % 100 < 60)?;
+    let offset_minutes = sign * (offset / 100 * 60 + offset % 100);
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_minutes * 60;
+
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
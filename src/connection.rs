@@ -1,22 +1,63 @@
-use std::{net::SocketAddr, ops::ControlFlow, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    ops::ControlFlow,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use mcproxy_model::Upstream;
-use tokio::{io, net::TcpStream, time::timeout};
-use tracing::Instrument;
-use tracing::{debug, error, field, trace, trace_span, warn, Span};
-use tracing_error::TracedError;
+use mcproxy_model::{Hostname, Upstream};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+use tracing::{debug, error, field, info, trace, warn, Span};
+use tracing_error::{InstrumentError, TracedError};
 
-use crate::proto::packet::{Handshake, NextState};
+use crate::health::UpstreamHealth;
+use crate::maintenance::Maintenance;
+#[cfg(feature = "metrics")]
+use crate::metrics::{
+    HandshakeLabels, LoginRefusedLabels, UnknownUpstreamLabels, UnknownUpstreamOutcome,
+};
+use crate::proto::packet::{
+    legacy::{LegacyPing, LEGACY_PING},
+    limbo,
+    response::StatusResponse,
+    Handshake, NextState, Packet, RawTextComponent,
+};
+#[cfg(feature = "metrics")]
+use crate::rate_limit::Rejection;
+use crate::rate_limit::{HandshakeGuard, RateLimiter};
+use crate::routing::{balance::LoadBalancer, resolve_upstream, Route};
+use crate::sleep::{SleepState, SleepingServers};
+use crate::template::TemplateContext;
+use crate::transfer::LimboTransfers;
 use crate::{
-    config::schema::Config,
+    config::schema::{Config, ResponseFile, ServerOptions},
+    proto::forwarding::forward_login,
     proto::io::{
-        read_handshake,
-        response::{login_response, ping_response},
-        write_packet,
+        legacy::read_legacy_ping,
+        peek_login_start, read_handshake,
+        request::ping_upstream,
+        response::{
+            legacy_ping_response, limbo_response, login_disconnect, login_response, ping_response,
+        },
+        with_next_state, write_packet,
     },
+    proto::limits::ProtocolLimits,
+    proto::proxy_protocol::{read_header, write_header},
 };
 
 const PING_TIMEOUT: Duration = Duration::from_millis(300);
+const FORWARDING_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client gets to send its Login Start, which it does right after the handshake
+const LOGIN_START_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the upstream of players held in limbo is checked
+const LIMBO_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The streams and routing information of a connection ready to be proxied
+pub type RoutedConnection = (TcpStream, TcpStream, Upstream, Handshake);
 
 macro_rules! timeout_break {
     ($timeout:ident, $response:expr) => {
@@ -31,22 +72,163 @@ macro_rules! timeout_break {
     };
 }
 
-#[tracing::instrument(name="routing", skip_all, fields(peer=%peer, address=field::Empty, next_state=field::Empty, upstream=field::Empty))]
+/// Find the real client address of a connection made through a trusted load balancer
+///
+/// Connections from trusted proxies must begin with a PROXY protocol header, whose source
+/// address replaces the peer address. Any other connection keeps its own peer address.
+#[tracing::instrument(skip_all, fields(peer=%peer))]
+pub async fn accept_proxy_header(
+    peer: SocketAddr,
+    config: &Config,
+    client_stream: &mut TcpStream,
+) -> Result<Option<SocketAddr>, TracedError<io::Error>> {
+    let trusted = config
+        .proxy
+        .trusted_proxies
+        .iter()
+        .any(|network| network.contains(&peer.ip().to_canonical()));
+
+    if !trusted {
+        return Ok(Some(peer));
+    }
+
+    let source = match timeout(PING_TIMEOUT, read_header(client_stream)).await {
+        Ok(result) => result?,
+        Err(_) => {
+            debug!("timeout exceeded");
+            return Ok(None);
+        }
+    };
+    trace!(?source, "received PROXY protocol header");
+
+    Ok(Some(source.unwrap_or(peer)))
+}
+
+/// Turn away connections which break the rate limits, before anything is read from them
+///
+/// The returned guard counts the connection as handshaking until it is dropped.
+#[tracing::instrument(skip_all, fields(peer=%peer))]
+pub fn admit_connection(
+    peer: SocketAddr,
+    config: &Config,
+    rate_limiter: &RateLimiter,
+    #[cfg(feature = "metrics")] connection_metrics: &crate::metrics::ConnectionMetrics,
+) -> Option<HandshakeGuard> {
+    let rejection = match rate_limiter.admit(peer.ip(), &config.proxy.rate_limit) {
+        Ok(handshaking) => return Some(handshaking),
+        Err(rejection) => rejection,
+    };
+    debug!(%rejection, "turned away connection");
+
+    #[cfg(feature = "metrics")]
+    match rejection {
+        Rejection::Banned => {
+            connection_metrics.connection_banned.inc();
+        }
+        Rejection::IpRateLimited { banned } => {
+            connection_metrics.connection_ip_rate_limited.inc();
+            if banned {
+                connection_metrics.client_bans.inc();
+            }
+        }
+        Rejection::NetworkRateLimited { banned } => {
+            connection_metrics.connection_network_rate_limited.inc();
+            if banned {
+                connection_metrics.client_bans.inc();
+            }
+        }
+        Rejection::TooManyHandshakes => {
+            connection_metrics.connection_handshakes_limited.inc();
+        }
+    }
+
+    None
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name="routing", skip_all, fields(peer=%peer, address=field::Empty, next_state=field::Empty, upstream=field::Empty, fallback=field::Empty))]
 pub async fn handle_connection(
     peer: SocketAddr,
     config: Arc<Config>,
     mut client_stream: TcpStream,
+    handshaking: HandshakeGuard,
+    #[cfg(feature = "discovery")] discovered_servers: Arc<mcproxy_discovery::DiscoveredServers>,
+    sleeping_servers: Arc<SleepingServers>,
+    upstream_health: Arc<UpstreamHealth>,
+    load_balancer: Arc<LoadBalancer>,
+    maintenance: Arc<Maintenance>,
+    limbo_transfers: Arc<LimboTransfers>,
     #[cfg(feature = "metrics")] connection_metrics: crate::metrics::ConnectionMetrics,
-) -> Result<ControlFlow<(), (TcpStream, TcpStream, Upstream, Handshake)>, TracedError<io::Error>> {
-    // TODO: Handle legacy ping
+) -> Result<ControlFlow<(), RoutedConnection>, TracedError<io::Error>> {
     trace!("new connection");
 
     #[cfg(feature = "metrics")]
     connection_metrics.client_connections.inc();
 
-    // First, the client sends a Handshake packet with its state set to 1.
-    let (handshake, handshake_packet) =
-        timeout_break!(PING_TIMEOUT, read_handshake(&mut client_stream));
+    let mut first_byte = [0u8];
+    let peeked = timeout_break!(PING_TIMEOUT, async {
+        client_stream
+            .peek(&mut first_byte)
+            .await
+            .map_err(InstrumentError::in_current_span)
+    });
+    if peeked == 0 {
+        debug!("connection closed before handshake");
+        return Ok(ControlFlow::Break(()));
+    }
+
+    // Clients older than 1.7 open with a legacy ping instead of a handshake
+    let (mut handshake, mut greeting) = if first_byte[0] == LEGACY_PING {
+        let legacy_ping = timeout_break!(
+            PING_TIMEOUT,
+            read_legacy_ping(&mut client_stream, &config.hostname_suffix_separators)
+        );
+
+        #[cfg(feature = "metrics")]
+        connection_metrics.client_legacy_pings_received.inc();
+
+        (legacy_ping.handshake(), Greeting::LegacyPing(legacy_ping))
+    } else {
+        // First, the client sends a Handshake packet with its state set to 1.
+        let (handshake, handshake_packet) = timeout_break!(
+            PING_TIMEOUT,
+            read_handshake(
+                &mut client_stream,
+                &config.hostname_suffix_separators,
+                &config.proxy.limits,
+            )
+        );
+
+        #[cfg(feature = "metrics")]
+        {
+            connection_metrics.client_handshakes_received.inc();
+            connection_metrics
+                .client_handshakes_received_by_state
+                .get_or_create(&HandshakeLabels {
+                    next_state: (&handshake.next_state).into(),
+                })
+                .inc();
+        }
+
+        (handshake, Greeting::Handshake(handshake_packet))
+    };
+    drop(handshaking);
+
+    // Players sent back by limbo log in again, even to upstreams which refuse transfers
+    if let (NextState::Transfer, Greeting::Handshake(handshake_packet)) =
+        (handshake.next_state, &greeting)
+    {
+        if limbo_transfers.take(peer.ip(), &handshake.address) {
+            debug!("player came back from limbo");
+
+            handshake.next_state = NextState::Login;
+            greeting = Greeting::Handshake(with_next_state(
+                handshake_packet,
+                NextState::Login,
+                &config.proxy.limits,
+            )?);
+        }
+    }
 
     Span::current().record("address", handshake.address.as_ref());
     Span::current().record("next_state", handshake.next_state.to_string());
@@ -60,131 +242,491 @@ pub async fn handle_connection(
             .as_ref()
             .map(|a| a as &dyn tracing::Value)
             .unwrap_or(&field::Empty),
+        legacy_format = match &greeting {
+            Greeting::LegacyPing(legacy_ping) => Some(field::debug(legacy_ping.format)),
+            Greeting::Handshake(_) => None,
+        },
         "handshake received"
     );
 
-    #[cfg(feature = "metrics")]
-    connection_metrics.client_handshakes_received.inc();
-
     // Handle mapping
-    let upstream = config.static_servers.get(&handshake.address).cloned();
-
-    let upstream = match upstream {
-        Some(a) => a,
-        None => {
-            warn!("unknown address");
+    let route = resolve_upstream(
+        &config,
+        #[cfg(feature = "discovery")]
+        &discovered_servers,
+        &handshake.address,
+    );
 
-            #[cfg(feature = "metrics")]
-            connection_metrics.connection_unknown_upstream.inc();
+    let under_maintenance = maintenance.is_active(&config.maintenance, &handshake.address);
+    let access = [
+        Some(&config.access),
+        route.as_ref().map(|route| &route.options.access),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|access| !access.is_empty())
+    .collect::<Vec<_>>();
 
-            match handshake.next_state {
-                NextState::Ping => {
-                    timeout_break!(
-                        PING_TIMEOUT,
-                        ping_response(
-                            &mut client_stream,
-                            config.placeholder_server.responses.no_mapping.as_ref()
-                        )
-                    );
-                    return Ok(ControlFlow::Break(()));
-                }
-                NextState::Login => {
-                    timeout_break!(
-                        PING_TIMEOUT,
-                        login_response(
-                            client_stream,
-                            config
-                                .placeholder_server
-                                .responses
-                                .no_mapping
-                                .as_ref()
-                                .map(|res| &res.description)
-                        )
-                    );
-                    return Ok(ControlFlow::Break(()));
-                }
-                NextState::Transfer => {
-                    error!("unimplemented");
-                    return Ok(ControlFlow::Break(()));
-                }
-                NextState::Unknown(state) => {
-                    warn!(state, "unknown next_state");
+    // Only peeked, so that the upstream still receives the Login Start exactly as it was sent
+    let login_start = match (&handshake.next_state, &greeting) {
+        (NextState::Login | NextState::Transfer, Greeting::Handshake(_))
+            if under_maintenance || !access.is_empty() =>
+        {
+            match timeout(
+                LOGIN_START_TIMEOUT,
+                peek_login_start(&client_stream, &config.proxy.limits),
+            )
+            .await
+            {
+                Ok(login_start) => Some(login_start?),
+                // Players that could be let in are told why they were not, rather than dropped
+                Err(_) => {
+                    debug!("timeout exceeded");
+                    login_disconnect(
+                        client_stream,
+                        &RawTextComponent::String("Timed out".to_string()),
+                    )
+                    .await?;
                     return Ok(ControlFlow::Break(()));
                 }
             }
         }
+        _ => None,
     };
-    Span::current().record("upstream", upstream.to_string());
 
-    let mut server_stream = match TcpStream::connect(upstream.addr())
-        .instrument(trace_span!("connect_upstream"))
-        .await
-    {
-        Ok(stream) => stream,
-        Err(error) => {
-            error!(
-                %error,
-                "could not connect to upstream"
+    // Only players on the bypass list get past maintenance, which also keeps servers asleep
+    if under_maintenance {
+        let bypassed = login_start.as_ref().is_some_and(|login_start| {
+            let bypassed = config.maintenance.bypasses(&login_start.name);
+            debug!(
+                name = login_start.name,
+                bypassed, "login during maintenance"
             );
 
+            bypassed
+        });
+
+        if bypassed {
+            #[cfg(feature = "metrics")]
+            connection_metrics.connection_maintenance_bypassed.inc();
+        } else {
+            #[cfg(feature = "metrics")]
+            connection_metrics.connection_maintenance.inc();
+
+            let context = template_context(
+                #[cfg(feature = "discovery")]
+                &discovered_servers,
+                &sleeping_servers,
+                &upstream_health,
+                &handshake,
+                route.as_ref().map(Route::upstream),
+            );
+            maintenance_placeholder(
+                client_stream,
+                &config,
+                &context,
+                &handshake,
+                &greeting,
+                route.as_ref(),
+            )
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+
+    // Refused players can not wake servers either
+    if let Some(login_start) = &login_start {
+        let now = SystemTime::now();
+        let refused = access
+            .iter()
+            .try_for_each(|access| access.check(&login_start.name, now));
+
+        if let Err(refusal) = refused {
+            info!(name = login_start.name, uuid = ?login_start.uuid, ?refusal, "login refused");
+
             #[cfg(feature = "metrics")]
             connection_metrics
-                .connection_can_not_reach_upstream
-                .get_or_create(&upstream)
+                .connection_login_refused
+                .get_or_create(&LoginRefusedLabels {
+                    reason: (&refusal).into(),
+                })
                 .inc();
 
-            match handshake.next_state {
-                NextState::Ping => {
-                    timeout_break!(
-                        PING_TIMEOUT,
-                        ping_response(
-                            &mut client_stream,
-                            config.placeholder_server.responses.offline.as_ref()
-                        )
-                    );
-                    return Ok(ControlFlow::Break(()));
-                }
-                NextState::Login => {
-                    timeout_break!(
-                        PING_TIMEOUT,
-                        login_response(
-                            client_stream,
-                            config
-                                .placeholder_server
-                                .responses
-                                .offline
-                                .as_ref()
-                                .map(|res| &res.description)
-                        )
-                    );
-                    return Ok(ControlFlow::Break(()));
-                }
-                NextState::Transfer => {
-                    error!("unimplemented");
-                    return Ok(ControlFlow::Break(()));
-                }
-                NextState::Unknown(state) => {
-                    warn!(state, "unknown next_state");
-                    return Ok(ControlFlow::Break(()));
+            timeout(
+                PING_TIMEOUT,
+                login_response(client_stream, Some(refusal.reason()), &config.proxy.limits),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                debug!("timeout exceeded");
+                Ok(())
+            })?;
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+
+    // Stopped servers are started by logging in to them, everyone else is told to wait
+    #[cfg(feature = "discovery")]
+    if let (None, Some(server_id)) = (
+        &route,
+        discovered_servers.get_stopped_by_hostname(&handshake.address),
+    ) {
+        let wake_on_connect = config
+            .discovery
+            .as_ref()
+            .is_some_and(|discovery| discovery.wake_on_connect);
+
+        if wake_on_connect
+            && matches!(handshake.next_state, NextState::Login | NextState::Transfer)
+            && discovered_servers.start(server_id)
+        {
+            info!(%server_id, "starting stopped server");
+
+            #[cfg(feature = "metrics")]
+            connection_metrics.connection_started_server.inc();
+        } else {
+            debug!(%server_id, "server is stopped");
+        }
+
+        unavailable_placeholder(
+            client_stream,
+            peer,
+            &config,
+            &discovered_servers,
+            &sleeping_servers,
+            &upstream_health,
+            &handshake,
+            &greeting,
+            None,
+            &limbo_transfers,
+            #[cfg(feature = "metrics")]
+            &connection_metrics,
+        )
+        .await?;
+        return Ok(ControlFlow::Break(()));
+    }
+
+    // Balanced once, so that the upstream woken up is the one the connection would go to
+    let mut order = route
+        .as_ref()
+        .map(|route| load_balancer.order(&handshake.address, route))
+        .unwrap_or_default();
+
+    // Upstreams put to sleep by their stop command are woken by logging in to them
+    if let Some(route) = &route {
+        let logging_in = match handshake.next_state {
+            NextState::Login => true,
+            NextState::Transfer => route.options.accept_transfers,
+            NextState::Ping | NextState::Unknown(_) => false,
+        };
+        let asleep = |upstream: &Upstream| {
+            matches!(
+                sleeping_servers.state(upstream),
+                Some(SleepState::Asleep(_))
+            )
+        };
+
+        if let (true, Some(start_command)) = (logging_in, &route.options.start_command) {
+            // Players are only held while no member of the pool is awake to take them
+            if order.iter().all(asleep) {
+                let chosen = &order[0];
+                if sleeping_servers.wake(chosen, start_command) {
+                    info!(upstream = %chosen, "waking sleeping upstream");
+
+                    #[cfg(feature = "metrics")]
+                    connection_metrics.connection_started_server.inc();
                 }
+
+                let woken = Route {
+                    upstreams: order,
+                    options: route.options.clone(),
+                };
+                unavailable_placeholder(
+                    client_stream,
+                    peer,
+                    &config,
+                    #[cfg(feature = "discovery")]
+                    &discovered_servers,
+                    &sleeping_servers,
+                    &upstream_health,
+                    &handshake,
+                    &greeting,
+                    Some(&woken),
+                    &limbo_transfers,
+                    #[cfg(feature = "metrics")]
+                    &connection_metrics,
+                )
+                .await?;
+                return Ok(ControlFlow::Break(()));
+            }
+
+            // Members that are awake are tried first, even before the health checks notice
+            order.sort_by_key(|upstream| asleep(upstream));
+        }
+    }
+
+    let routed = route.clone();
+
+    let candidates = match route {
+        Some(route)
+            if matches!(handshake.next_state, NextState::Transfer)
+                && !route.options.accept_transfers =>
+        {
+            warn!(upstream = %route.upstream(), "upstream refuses transfers");
+
+            #[cfg(feature = "metrics")]
+            connection_metrics
+                .connection_transfer_refused
+                .get_or_create(route.upstream())
+                .inc();
+
+            let reason = route
+                .options
+                .transfer_refused_reason
+                .clone()
+                .unwrap_or_else(|| {
+                    RawTextComponent::String("This server does not accept transfers".to_string())
+                });
+            timeout_break!(
+                PING_TIMEOUT,
+                login_response(client_stream, Some(&reason), &config.proxy.limits)
+            );
+            return Ok(ControlFlow::Break(()));
+        }
+        Some(route) => {
+            let fallbacks = route
+                .options
+                .fallback
+                .iter()
+                .map(|upstream| (upstream.clone(), route.options.clone(), true));
+
+            order
+                .into_iter()
+                .map(|upstream| (upstream, route.options.clone(), false))
+                .chain(fallbacks)
+                .collect()
+        }
+        None if !config.default_upstream.is_empty() => {
+            warn!("unknown address, falling back to default upstream");
+
+            #[cfg(feature = "metrics")]
+            connection_metrics
+                .connection_unknown_upstream_by_outcome
+                .get_or_create(&UnknownUpstreamLabels {
+                    outcome: UnknownUpstreamOutcome::Fallback,
+                })
+                .inc();
+
+            config
+                .default_upstream
+                .iter()
+                .cloned()
+                .map(|upstream| (upstream, Arc::default(), false))
+                .collect::<Vec<(Upstream, Arc<ServerOptions>, bool)>>()
+        }
+        None => {
+            warn!("unknown address");
+
+            #[cfg(feature = "metrics")]
+            {
+                connection_metrics.connection_unknown_upstream.inc();
+                connection_metrics
+                    .connection_unknown_upstream_by_outcome
+                    .get_or_create(&UnknownUpstreamLabels {
+                        outcome: UnknownUpstreamOutcome::Rejected,
+                    })
+                    .inc();
+            }
+
+            let context = template_context(
+                #[cfg(feature = "discovery")]
+                &discovered_servers,
+                &sleeping_servers,
+                &upstream_health,
+                &handshake,
+                None,
+            );
+            let response = config
+                .placeholder_server
+                .responses
+                .no_mapping
+                .as_ref()
+                .map(|response| context.render_response(response));
+
+            placeholder_response(
+                client_stream,
+                &handshake,
+                &greeting,
+                response.as_ref(),
+                &config.proxy.limits,
+            )
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        }
+    };
+
+    // Servers with status overrides are pinged by the proxy, never by the client
+    let overrides = routed
+        .as_ref()
+        .and_then(|route| route.options.status.as_ref());
+    if let (NextState::Ping, Some(overrides)) = (&handshake.next_state, overrides) {
+        let Some((upstream, status)) =
+            upstream_status(&config, &upstream_health, &candidates).await
+        else {
+            unavailable_placeholder(
+                client_stream,
+                peer,
+                &config,
+                #[cfg(feature = "discovery")]
+                &discovered_servers,
+                &sleeping_servers,
+                &upstream_health,
+                &handshake,
+                &greeting,
+                routed.as_ref(),
+                &limbo_transfers,
+                #[cfg(feature = "metrics")]
+                &connection_metrics,
+            )
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        };
+        Span::current().record("upstream", upstream.to_string());
+
+        #[cfg(feature = "metrics")]
+        connection_metrics
+            .connection_status_overridden
+            .get_or_create(&upstream)
+            .inc();
+
+        placeholder_response(
+            client_stream,
+            &handshake,
+            &greeting,
+            Some(&overrides.apply(&status)),
+            &config.proxy.limits,
+        )
+        .await?;
+        return Ok(ControlFlow::Break(()));
+    }
+
+    let mut connection = None;
+    for (upstream, options, fallback) in candidates {
+        match connect_upstream(
+            &upstream,
+            &options,
+            #[cfg(feature = "metrics")]
+            &connection_metrics,
+        )
+        .await
+        {
+            Ok(stream) => {
+                upstream_health.record_connection(&upstream, true);
+                connection = Some((stream, upstream, options, fallback));
+                break;
+            }
+            Err(error) => {
+                error!(
+                    %error,
+                    %upstream,
+                    "could not connect to upstream"
+                );
+                upstream_health.record_connection(&upstream, false);
+
+                #[cfg(feature = "metrics")]
+                connection_metrics
+                    .connection_can_not_reach_upstream
+                    .get_or_create(&upstream)
+                    .inc();
             }
         }
+    }
+
+    let Some((mut server_stream, upstream, options, fallback)) = connection else {
+        unavailable_placeholder(
+            client_stream,
+            peer,
+            &config,
+            #[cfg(feature = "discovery")]
+            &discovered_servers,
+            &sleeping_servers,
+            &upstream_health,
+            &handshake,
+            &greeting,
+            routed.as_ref(),
+            &limbo_transfers,
+            #[cfg(feature = "metrics")]
+            &connection_metrics,
+        )
+        .await?;
+        return Ok(ControlFlow::Break(()));
     };
+    Span::current().record("upstream", upstream.to_string());
+    Span::current().record("fallback", fallback);
     trace!("connected to upstream");
 
+    #[cfg(feature = "metrics")]
+    if fallback {
+        connection_metrics
+            .connection_failed_over
+            .get_or_create(&upstream)
+            .inc();
+    }
+
+    #[cfg(feature = "discovery")]
+    discovered_servers.finish_starting(&handshake.address);
+    sleeping_servers.awake(&upstream);
+
     #[cfg(feature = "metrics")]
     connection_metrics
         .connection_established
         .get_or_create(&upstream.clone())
         .inc();
 
+    // The header has to come before anything else, so the upstream sees the client's address
+    if let Some(version) = options.proxy_protocol {
+        let destination = client_stream
+            .local_addr()
+            .map_err(InstrumentError::in_current_span)?;
+
+        write_header(&mut server_stream, version, peer, destination).await?;
+    }
+
     // Forward the handshake to the upstream
-    write_packet(
-        &mut server_stream,
-        handshake_packet.id,
-        &handshake_packet.data,
-    )
-    .await?;
+    match greeting {
+        Greeting::Handshake(handshake_packet) => {
+            match (&options.forwarding, handshake.next_state) {
+                (Some(forwarding), NextState::Login | NextState::Transfer) => {
+                    timeout_break!(
+                        FORWARDING_TIMEOUT,
+                        forward_login(
+                            &mut client_stream,
+                            &mut server_stream,
+                            &handshake,
+                            &handshake_packet,
+                            peer.ip(),
+                            forwarding,
+                            &config.proxy.limits,
+                        )
+                    );
+                }
+                _ => {
+                    write_packet(
+                        &mut server_stream,
+                        handshake_packet.id,
+                        &handshake_packet.data,
+                    )
+                    .await?;
+                }
+            }
+        }
+        Greeting::LegacyPing(legacy_ping) => {
+            server_stream
+                .write_all(&legacy_ping.data)
+                .await
+                .map_err(InstrumentError::in_current_span)?;
+        }
+    }
 
     trace!("passing upstream to proxy");
 
@@ -195,3 +737,415 @@ pub async fn handle_connection(
         handshake,
     )))
 }
+
+/// The status of the first candidate that has one, as of its last health check if that is recent
+/// enough, or as of pinging it now otherwise
+async fn upstream_status(
+    config: &Config,
+    upstream_health: &UpstreamHealth,
+    candidates: &[(Upstream, Arc<ServerOptions>, bool)],
+) -> Option<(Upstream, Arc<StatusResponse>)> {
+    let max_age = config.health_check.interval() * 2;
+
+    for (upstream, options, _) in candidates {
+        let cached = upstream_health.get(upstream).and_then(|check| {
+            check
+                .response
+                .filter(|_| check.healthy && check.checked.elapsed() <= max_age)
+        });
+        if let Some((_, status)) = cached {
+            return Some((upstream.clone(), status));
+        }
+
+        let response = timeout(
+            config.health_check.timeout(),
+            ping_upstream(
+                upstream.clone(),
+                options.proxy_protocol,
+                &config.proxy.limits,
+            ),
+        )
+        .await;
+        match response {
+            Ok(Ok((latency, status))) => {
+                upstream_health.record_ping(upstream.clone(), Some((latency, status.clone())));
+
+                return Some((upstream.clone(), Arc::new(status)));
+            }
+            Ok(Err(error)) => {
+                debug!(%upstream, %error, "could not ping upstream for its status");
+                upstream_health.record_ping(upstream.clone(), None);
+            }
+            Err(_) => {
+                debug!(%upstream, "upstream did not answer ping in time");
+                upstream_health.record_ping(upstream.clone(), None);
+            }
+        }
+    }
+
+    None
+}
+
+/// Connect to an upstream, retrying with backoff as many times as its options allow
+#[tracing::instrument(skip_all, fields(upstream=%upstream))]
+async fn connect_upstream(
+    upstream: &Upstream,
+    options: &ServerOptions,
+    #[cfg(feature = "metrics")] connection_metrics: &crate::metrics::ConnectionMetrics,
+) -> io::Result<TcpStream> {
+    let mut retry = 0;
+
+    loop {
+        let error = match timeout(
+            options.connect_timeout(),
+            TcpStream::connect(upstream.addr()),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(error)) => error,
+            Err(_) => io::Error::new(io::ErrorKind::TimedOut, "connection timed out"),
+        };
+
+        if retry >= options.connect_retries {
+            return Err(error);
+        }
+
+        let backoff = options.retry_backoff(retry);
+        debug!(%error, ?backoff, "retrying connection to upstream");
+
+        #[cfg(feature = "metrics")]
+        connection_metrics
+            .connection_retried
+            .get_or_create(upstream)
+            .inc();
+
+        sleep(backoff).await;
+        retry += 1;
+    }
+}
+
+/// Why a server can not be connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unavailability {
+    Starting,
+    Asleep,
+    Offline,
+    Maintenance,
+}
+
+fn unavailability(
+    #[cfg(feature = "discovery")] discovered_servers: &mcproxy_discovery::DiscoveredServers,
+    sleeping_servers: &SleepingServers,
+    hostname: &Hostname,
+    upstream: Option<&Upstream>,
+) -> Unavailability {
+    let sleep_state = upstream.and_then(|upstream| sleeping_servers.state(upstream));
+
+    #[cfg(feature = "discovery")]
+    let (starting, asleep) = (
+        discovered_servers.starting_since(hostname).is_some(),
+        discovered_servers.sleeping_since(hostname).is_some(),
+    );
+    #[cfg(not(feature = "discovery"))]
+    let (starting, asleep, _) = (false, false, hostname);
+
+    if starting || matches!(sleep_state, Some(SleepState::Waking(_))) {
+        Unavailability::Starting
+    } else if asleep || matches!(sleep_state, Some(SleepState::Asleep(_))) {
+        Unavailability::Asleep
+    } else {
+        Unavailability::Offline
+    }
+}
+
+/// What is substituted into the placeholder responses sent to a client
+fn template_context<'c>(
+    #[cfg(feature = "discovery")] discovered_servers: &mcproxy_discovery::DiscoveredServers,
+    sleeping_servers: &SleepingServers,
+    upstream_health: &UpstreamHealth,
+    handshake: &'c Handshake,
+    upstream: Option<&'c Upstream>,
+) -> TemplateContext<'c> {
+    let wake_eta = upstream.and_then(|upstream| sleeping_servers.wake_eta(upstream));
+    #[cfg(feature = "discovery")]
+    let wake_eta = wake_eta.or_else(|| discovered_servers.start_eta(&handshake.address));
+
+    TemplateContext {
+        hostname: &handshake.address,
+        upstream,
+        protocol_version: handshake.protocol_version,
+        last_seen_online: upstream
+            .and_then(|upstream| upstream_health.get(upstream))
+            .and_then(|check| check.last_success),
+        players_online_network: upstream_health
+            .checks()
+            .iter()
+            .filter_map(|(_, check)| check.players_online())
+            .sum(),
+        wake_eta,
+    }
+}
+
+/// The placeholder for a server that can not be connected to, preferring the server's own
+/// responses to the global ones
+fn unavailable_response<'c>(
+    config: &'c Config,
+    options: Option<&'c ServerOptions>,
+    unavailability: Unavailability,
+) -> Option<&'c StatusResponse> {
+    let global = &config.placeholder_server.responses;
+    let server = options.map(|options| &options.responses);
+
+    let (server_response, global_response) = match unavailability {
+        Unavailability::Starting => (
+            server.and_then(|server| server.starting.as_ref()),
+            global.starting.as_ref(),
+        ),
+        Unavailability::Asleep => (
+            server.and_then(|server| server.sleeping.as_ref()),
+            global.sleeping.as_ref(),
+        ),
+        Unavailability::Maintenance => (
+            server.and_then(|server| server.maintenance.as_ref()),
+            global.maintenance.as_ref(),
+        ),
+        Unavailability::Offline => (None, None),
+    };
+
+    server_response
+        .or(server.and_then(|server| server.offline.as_ref()))
+        .and_then(ResponseFile::response)
+        .or(global_response)
+        .or(global.offline.as_ref())
+}
+
+/// Answer a client whose server is under maintenance, refusing logins with the configured reason
+async fn maintenance_placeholder(
+    client_stream: TcpStream,
+    config: &Config,
+    context: &TemplateContext<'_>,
+    handshake: &Handshake,
+    greeting: &Greeting,
+    route: Option<&Route>,
+) -> Result<(), TracedError<io::Error>> {
+    let response = unavailable_response(
+        config,
+        route.map(|route| &*route.options),
+        Unavailability::Maintenance,
+    )
+    .map(|response| context.render_response(response));
+
+    if !matches!(handshake.next_state, NextState::Login | NextState::Transfer) {
+        return placeholder_response(
+            client_stream,
+            handshake,
+            greeting,
+            response.as_ref(),
+            &config.proxy.limits,
+        )
+        .await;
+    }
+
+    let reason = config
+        .maintenance
+        .reason
+        .as_ref()
+        .map(|reason| reason.map_text(&|text| context.render(text)))
+        .or_else(|| response.map(|response| response.description))
+        .unwrap_or_else(|| RawTextComponent::String("The server is under maintenance".to_string()));
+
+    timeout(
+        PING_TIMEOUT,
+        login_response(client_stream, Some(&reason), &config.proxy.limits),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        debug!("timeout exceeded");
+        Ok(())
+    })
+}
+
+/// Answer a client whose server can not be connected to, depending on whether it is starting
+/// or asleep
+///
+/// Players logging in to a starting server are held in limbo if it is configured, and
+/// transferred back to the address they connected to once the server is reachable.
+#[allow(clippy::too_many_arguments)]
+async fn unavailable_placeholder(
+    client_stream: TcpStream,
+    peer: SocketAddr,
+    config: &Arc<Config>,
+    #[cfg(feature = "discovery")] discovered_servers: &Arc<mcproxy_discovery::DiscoveredServers>,
+    sleeping_servers: &SleepingServers,
+    upstream_health: &Arc<UpstreamHealth>,
+    handshake: &Handshake,
+    greeting: &Greeting,
+    route: Option<&Route>,
+    limbo_transfers: &Arc<LimboTransfers>,
+    #[cfg(feature = "metrics")] connection_metrics: &crate::metrics::ConnectionMetrics,
+) -> Result<(), TracedError<io::Error>> {
+    let unavailability = unavailability(
+        #[cfg(feature = "discovery")]
+        discovered_servers,
+        sleeping_servers,
+        &handshake.address,
+        route.map(Route::upstream),
+    );
+    let context = template_context(
+        #[cfg(feature = "discovery")]
+        discovered_servers,
+        sleeping_servers,
+        upstream_health,
+        handshake,
+        route.map(Route::upstream),
+    );
+    let response = unavailable_response(config, route.map(|route| &*route.options), unavailability)
+        .map(|response| context.render_response(response));
+
+    let limbo = config.placeholder_server.limbo.as_ref().filter(|_| {
+        unavailability == Unavailability::Starting
+            && matches!(greeting, Greeting::Handshake(_))
+            && matches!(handshake.next_state, NextState::Login | NextState::Transfer)
+            && limbo::PROTOCOL_VERSIONS.contains(&handshake.protocol_version)
+    });
+
+    let Some(limbo) = limbo else {
+        return placeholder_response(
+            client_stream,
+            handshake,
+            greeting,
+            response.as_ref(),
+            &config.proxy.limits,
+        )
+        .await;
+    };
+
+    #[cfg(feature = "metrics")]
+    connection_metrics.connection_held_in_limbo.inc();
+
+    let message = limbo
+        .message
+        .as_ref()
+        .map(|message| message.map_text(&|text| context.render(text)))
+        .or_else(|| response.map(|response| response.description))
+        .unwrap_or_else(|| {
+            RawTextComponent::String("The server is starting, please wait".to_string())
+        });
+
+    let upstream_ready = {
+        let ready = upstream_ready(
+            config.clone(),
+            #[cfg(feature = "discovery")]
+            discovered_servers.clone(),
+            upstream_health.clone(),
+            handshake.address.clone(),
+        );
+        let limbo_timeout = limbo.timeout();
+        let (limbo_transfers, hostname) = (limbo_transfers.clone(), handshake.address.clone());
+
+        async move {
+            timeout(limbo_timeout, ready).await.map_err(|_| {
+                RawTextComponent::String("The server did not start in time".to_string())
+            })?;
+
+            // Recorded before the player is transferred, so it is known once it comes back
+            limbo_transfers.issue(peer.ip(), hostname);
+            Ok(())
+        }
+    };
+
+    limbo_response(
+        client_stream,
+        handshake.protocol_version,
+        &message,
+        (handshake.address.as_ref(), handshake.port),
+        upstream_ready,
+        &config.proxy.limits,
+    )
+    .await
+}
+
+/// Wait until a hostname routes to an upstream that the health checks found healthy, any member
+/// of a pool will do
+async fn upstream_ready(
+    config: Arc<Config>,
+    #[cfg(feature = "discovery")] discovered_servers: Arc<mcproxy_discovery::DiscoveredServers>,
+    upstream_health: Arc<UpstreamHealth>,
+    hostname: Hostname,
+) {
+    loop {
+        sleep(LIMBO_CHECK_INTERVAL).await;
+
+        let Some(route) = resolve_upstream(
+            &config,
+            #[cfg(feature = "discovery")]
+            &discovered_servers,
+            &hostname,
+        ) else {
+            continue;
+        };
+
+        // The upstream that could not be connected to is unhealthy until pinged successfully
+        let ready = route.upstreams.iter().any(|upstream| {
+            upstream_health
+                .get(upstream)
+                .is_some_and(|check| check.healthy)
+        });
+
+        if ready {
+            return;
+        }
+
+        trace!("upstream not ready");
+    }
+}
+
+/// The first message sent by the client, to be replayed to the upstream
+enum Greeting {
+    Handshake(Packet),
+    LegacyPing(LegacyPing),
+}
+
+/// Answer the client from the placeholder server, since it can not be proxied
+async fn placeholder_response(
+    mut client_stream: TcpStream,
+    handshake: &Handshake,
+    greeting: &Greeting,
+    response: Option<&StatusResponse>,
+    limits: &ProtocolLimits,
+) -> Result<(), TracedError<io::Error>> {
+    let result = match (&handshake.next_state, greeting) {
+        (NextState::Ping, Greeting::LegacyPing(legacy_ping)) => {
+            timeout(
+                PING_TIMEOUT,
+                legacy_ping_response(&mut client_stream, legacy_ping.format, response),
+            )
+            .await
+        }
+        (NextState::Ping, Greeting::Handshake(_)) => {
+            timeout(
+                PING_TIMEOUT,
+                ping_response(&mut client_stream, response, limits),
+            )
+            .await
+        }
+        // Transferred clients log in just like any other
+        (NextState::Login | NextState::Transfer, _) => {
+            timeout(
+                PING_TIMEOUT,
+                login_response(client_stream, response.map(|res| &res.description), limits),
+            )
+            .await
+        }
+        (NextState::Unknown(state), _) => {
+            warn!(state, "unknown next_state");
+            return Ok(());
+        }
+    };
+
+    result.unwrap_or_else(|_| {
+        debug!("timeout exceeded");
+        Ok(())
+    })
+}
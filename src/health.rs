@@ -0,0 +1,294 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use dashmap::DashMap;
+use mcproxy_model::Upstream;
+use tokio::{
+    sync::watch::Receiver,
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+use tracing::{debug, info};
+
+use crate::{
+    config::schema::Config,
+    proto::{
+        io::request::ping_upstream, packet::response::StatusResponse,
+        proxy_protocol::ProxyProtocolVersion,
+    },
+};
+
+/// What is known about an upstream from pinging it and connecting to it
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    /// Whether the last ping, or connection made by a player, succeeded
+    pub healthy: bool,
+    pub checked: Instant,
+    /// Round trip time and status of the last successful ping
+    pub response: Option<(Duration, Arc<StatusResponse>)>,
+    /// When the last successful ping happened
+    pub last_success: Option<SystemTime>,
+}
+
+impl HealthCheck {
+    /// Players online as of the last ping, if the upstream is healthy
+    pub fn players_online(&self) -> Option<u32> {
+        let (_, status_response) = self.response.as_ref().filter(|_| self.healthy)?;
+
+        Some(
+            status_response
+                .players
+                .as_ref()
+                .map_or(0, |players| players.online),
+        )
+    }
+}
+
+/// The last health check of every known upstream, kept current by [`watch`]
+#[derive(Debug, Default)]
+pub struct UpstreamHealth {
+    checks: DashMap<Upstream, HealthCheck>,
+}
+
+impl UpstreamHealth {
+    pub fn get(&self, upstream: &Upstream) -> Option<HealthCheck> {
+        self.checks.get(upstream).map(|check| check.clone())
+    }
+
+    /// Whether the upstream is not known to be unhealthy
+    pub fn is_healthy(&self, upstream: &Upstream) -> bool {
+        self.checks.get(upstream).is_none_or(|check| check.healthy)
+    }
+
+    /// Record whether a player could connect to an upstream, without waiting for the next ping
+    ///
+    /// Upstreams which are not checked are ignored.
+    pub fn record_connection(&self, upstream: &Upstream, healthy: bool) {
+        if let Some(mut check) = self.checks.get_mut(upstream) {
+            check.healthy = healthy;
+            check.checked = Instant::now();
+        }
+    }
+
+    /// Snapshot of every checked upstream
+    pub fn checks(&self) -> Vec<(Upstream, HealthCheck)> {
+        self.checks
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Record the outcome of pinging an upstream, keeping the last response if it failed
+    pub fn record_ping(&self, upstream: Upstream, response: Option<(Duration, StatusResponse)>) {
+        let checked = Instant::now();
+
+        match response {
+            Some((latency, status_response)) => {
+                self.checks.insert(
+                    upstream,
+                    HealthCheck {
+                        healthy: true,
+                        checked,
+                        response: Some((latency, Arc::new(status_response))),
+                        last_success: Some(SystemTime::now()),
+                    },
+                );
+            }
+            None => {
+                self.checks
+                    .entry(upstream)
+                    .and_modify(|check| {
+                        check.healthy = false;
+                        check.checked = checked;
+                    })
+                    .or_insert(HealthCheck {
+                        healthy: false,
+                        checked,
+                        response: None,
+                        last_success: None,
+                    });
+            }
+        }
+    }
+}
+
+/// Every upstream that can be reached without knowing the connecting hostname, and the PROXY
+/// protocol version it expects
+fn known_upstreams(
+    config: &Config,
+    #[cfg(feature = "discovery")] discovered_servers: &mcproxy_discovery::DiscoveredServers,
+) -> HashMap<Upstream, Option<ProxyProtocolVersion>> {
+    let mut upstreams = HashMap::new();
+
+    for (upstream, options) in config.static_servers.upstreams() {
+        upstreams.insert(upstream.clone(), options.proxy_protocol);
+    }
+
+    // Upstreams referencing captures are only known once a hostname matches
+    for server in &config.pattern_servers {
+        if let Some(upstream) = server.upstream.as_upstream() {
+            upstreams.insert(upstream.clone(), server.options.proxy_protocol);
+        }
+    }
+
+    let options = config
+        .static_servers
+        .entries()
+        .map(|(_, server)| &*server.options)
+        .chain(config.pattern_servers.iter().map(|server| &*server.options));
+    for options in options {
+        for upstream in &options.fallback {
+            upstreams.insert(upstream.clone(), options.proxy_protocol);
+        }
+    }
+
+    for upstream in &config.default_upstream {
+        upstreams.entry(upstream.clone()).or_insert(None);
+    }
+
+    #[cfg(feature = "discovery")]
+    for (_, _, upstream) in discovered_servers.mappings() {
+        upstreams.entry(upstream).or_insert(None);
+    }
+
+    upstreams
+}
+
+/// Periodically ping every known upstream, caching how healthy each one is
+pub async fn watch(
+    config: Receiver<Arc<Config>>,
+    upstream_health: Arc<UpstreamHealth>,
+    #[cfg(feature = "discovery")] discovered_servers: Arc<mcproxy_discovery::DiscoveredServers>,
+) {
+    info!("watching upstream health");
+
+    loop {
+        let config = config.borrow().clone();
+        let health_check = config.health_check;
+        let limits = config.proxy.limits;
+
+        let upstreams = known_upstreams(
+            &config,
+            #[cfg(feature = "discovery")]
+            &discovered_servers,
+        );
+
+        let mut pings = JoinSet::new();
+        for (upstream, proxy_protocol) in upstreams {
+            pings.spawn(async move {
+                let response = match timeout(
+                    health_check.timeout(),
+                    ping_upstream(upstream.clone(), proxy_protocol, &limits),
+                )
+                .await
+                {
+                    Ok(Ok(response)) => Some(response),
+                    Ok(Err(error)) => {
+                        debug!(%upstream, %error, "upstream is unhealthy");
+                        None
+                    }
+                    Err(_) => {
+                        debug!(%upstream, "upstream did not answer ping in time");
+                        None
+                    }
+                };
+
+                (upstream, response)
+            });
+        }
+
+        let mut checked = HashSet::with_capacity(pings.len());
+        while let Some(result) = pings.join_next().await {
+            let (upstream, response) = result.expect("join should not fail");
+
+            checked.insert(upstream.clone());
+            upstream_health.record_ping(upstream, response);
+        }
+
+        // Forget upstreams that are no longer known
+        upstream_health
+            .checks
+            .retain(|upstream, _| checked.contains(upstream));
+
+        sleep(health_check.interval()).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use mcproxy_model::Upstream;
+
+    use super::{known_upstreams, UpstreamHealth};
+    use crate::{config::schema::Config, proto::packet::response::StatusResponse};
+
+    #[test]
+    fn record_health() {
+        let upstream_health = UpstreamHealth::default();
+        let upstream = Upstream::from("127.0.0.1:25565".parse::<std::net::SocketAddr>().unwrap());
+
+        // Upstreams are innocent until proven guilty
+        assert!(upstream_health.is_healthy(&upstream));
+        upstream_health.record_connection(&upstream, false);
+        assert!(upstream_health.is_healthy(&upstream));
+
+        let status_response = serde_json::from_str::<StatusResponse>(
+            r#"{"version":{"name":"1.21.1","protocol":767},"players":{"max":20,"online":3},"description":""}"#,
+        )
+        .unwrap();
+        upstream_health.record_ping(
+            upstream.clone(),
+            Some((Duration::from_millis(5), status_response)),
+        );
+        assert_eq!(
+            upstream_health.get(&upstream).unwrap().players_online(),
+            Some(3)
+        );
+
+        // A failure keeps the last response, but hides its players
+        upstream_health.record_connection(&upstream, false);
+        let check = upstream_health.get(&upstream).unwrap();
+        assert!(!check.healthy);
+        assert!(check.response.is_some());
+        assert!(check.last_success.is_some());
+        assert_eq!(check.players_online(), None);
+
+        upstream_health.record_ping(upstream.clone(), None);
+        assert!(!upstream_health.is_healthy(&upstream));
+    }
+
+    #[test]
+    fn known_pattern_upstreams() {
+        let config = toml::from_str::<Config>(
+            r#"
+                [placeholder_server.responses]
+                [proxy]
+                listen_address = "127.0.0.1:25565"
+                [static_servers]
+                [[pattern_servers]]
+                pattern = 'lobby\..*'
+                upstream = "127.0.0.1:25570"
+                [[pattern_servers]]
+                pattern = '(\w+)\.example\.com'
+                upstream = "{1}.internal:25565"
+            "#,
+        )
+        .unwrap();
+
+        let upstreams = known_upstreams(
+            &config,
+            #[cfg(feature = "discovery")]
+            &mcproxy_discovery::DiscoveredServers::default(),
+        );
+
+        // Only the upstream that does not depend on the hostname can be checked
+        assert_eq!(upstreams.len(), 1);
+        assert!(upstreams.contains_key(&Upstream::from(
+            "127.0.0.1:25570".parse::<std::net::SocketAddr>().unwrap()
+        )));
+    }
+}
@@ -1,17 +1,30 @@
 use config::schema::Config;
-use connection::handle_connection;
+use connection::{accept_proxy_header, admit_connection, handle_connection};
+use health::UpstreamHealth;
+use maintenance::Maintenance;
+use rate_limit::RateLimiter;
+use routing::balance::LoadBalancer;
+use sleep::SleepingServers;
 use std::{ops::ControlFlow, path::PathBuf, sync::Arc};
 use tokio::{net::TcpListener, task};
 use trace::init_tracing_subscriber;
 use tracing::{error, info, trace_span, Instrument};
+use transfer::LimboTransfers;
 
 use crate::proxy_server::ProxyServer;
 
 mod config;
 mod connection;
+mod health;
+mod maintenance;
 mod proto;
 mod proxy_server;
+mod rate_limit;
+mod routing;
+mod sleep;
+mod template;
 mod trace;
+mod transfer;
 
 #[cfg(feature = "metrics")]
 mod metrics;
@@ -44,9 +57,55 @@ async fn main() -> eyre::Result<()> {
     let initial_config: Arc<Config> = Arc::new(config::load(&config_file).await?);
     let (config_sender, config) = tokio::sync::watch::channel(initial_config.clone());
 
+    let upstream_health = Arc::new(UpstreamHealth::default());
+
     #[cfg(feature = "metrics")]
-    let (registry, connection_metrics, active_connection_metrics, proxy_task_monitor) =
-        metrics::create_metrics(config.clone());
+    let (
+        registry,
+        connection_metrics,
+        active_connection_metrics,
+        sleep_metrics,
+        proxy_task_monitor,
+    ) = metrics::create_metrics(upstream_health.clone());
+
+    #[cfg(feature = "discovery")]
+    let discovered_servers = match initial_config.discovery {
+        Some(_) => mcproxy_discovery::begin().await,
+        None => Arc::default(),
+    };
+
+    #[cfg(not(feature = "discovery"))]
+    if initial_config.discovery.is_some() {
+        tracing::warn!(
+            "discovery is configured, but mcproxy was built without the discovery feature"
+        );
+    }
+
+    task::spawn(health::watch(
+        config.clone(),
+        upstream_health.clone(),
+        #[cfg(feature = "discovery")]
+        discovered_servers.clone(),
+    ));
+
+    // Always watched, since sleep can be configured once the config is reloaded
+    let sleeping_servers = Arc::new(SleepingServers::default());
+    task::spawn(sleep::watch(
+        config.clone(),
+        sleeping_servers.clone(),
+        upstream_health.clone(),
+        #[cfg(feature = "discovery")]
+        discovered_servers.clone(),
+        #[cfg(feature = "metrics")]
+        sleep_metrics,
+    ));
+
+    let load_balancer = Arc::new(LoadBalancer::new(upstream_health.clone()));
+    let maintenance = Arc::new(Maintenance::default());
+    let limbo_transfers = Arc::new(LimboTransfers::default());
+
+    let rate_limiter = Arc::new(RateLimiter::default());
+    task::spawn(rate_limit::watch(config.clone(), rate_limiter.clone()));
 
     // let config = task::spawn(config::watch(config_file));
     if let Some(ui_config) = initial_config.ui {
@@ -56,6 +115,12 @@ async fn main() -> eyre::Result<()> {
             config_file,
             config_sender,
             config.clone(),
+            #[cfg(feature = "discovery")]
+            discovered_servers.clone(),
+            sleeping_servers.clone(),
+            upstream_health.clone(),
+            load_balancer.clone(),
+            maintenance.clone(),
             #[cfg(feature = "metrics")]
             registry,
         ));
@@ -82,9 +147,17 @@ async fn main() -> eyre::Result<()> {
         let stream = listener.accept().await;
 
         match stream {
-            Ok((client_stream, _address)) => {
+            Ok((mut client_stream, _address)) => {
                 // Clone pointers to the address map and server responses
                 let config = config.borrow().clone();
+                #[cfg(feature = "discovery")]
+                let discovered_servers = discovered_servers.clone();
+                let sleeping_servers = sleeping_servers.clone();
+                let upstream_health = upstream_health.clone();
+                let load_balancer = load_balancer.clone();
+                let maintenance = maintenance.clone();
+                let limbo_transfers = limbo_transfers.clone();
+                let rate_limiter = rate_limiter.clone();
                 #[cfg(feature = "metrics")]
                 let (connection_metrics, active_connection_metrics) = (
                     connection_metrics.clone(),
@@ -96,11 +169,39 @@ async fn main() -> eyre::Result<()> {
 
                 // Fork off the connection handling
                 let task = async move {
+                    // Behind a load balancer, the real client address comes from its PROXY header
+                    let peer = match accept_proxy_header(peer, &config, &mut client_stream).await {
+                        Ok(Some(peer)) => peer,
+                        Ok(None) => return,
+                        Err(e) => {
+                            error!("Error in handling connection: {}", e);
+                            return;
+                        }
+                    };
+
+                    let Some(handshaking) = admit_connection(
+                        peer,
+                        &config,
+                        &rate_limiter,
+                        #[cfg(feature = "metrics")]
+                        &connection_metrics,
+                    ) else {
+                        return;
+                    };
+
                     // Handle the connection
                     match handle_connection(
                         peer,
                         config,
                         client_stream,
+                        handshaking,
+                        #[cfg(feature = "discovery")]
+                        discovered_servers,
+                        sleeping_servers,
+                        upstream_health,
+                        load_balancer.clone(),
+                        maintenance,
+                        limbo_transfers,
                         #[cfg(feature = "metrics")]
                         connection_metrics,
                     )
@@ -112,6 +213,8 @@ async fn main() -> eyre::Result<()> {
                             upstream,
                             handshake,
                         ))) => {
+                            let _connection = load_balancer.connect(upstream.clone());
+
                             #[cfg(feature = "metrics")]
                             active_connection_metrics
                                 .active_server_connections
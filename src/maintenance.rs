@@ -0,0 +1,104 @@
+use std::{sync::Mutex, time::SystemTime};
+
+use dashmap::DashMap;
+use mcproxy_model::Hostname;
+
+use crate::config::schema::MaintenanceConfig;
+
+/// Maintenance turned on or off at runtime, taking precedence over the config
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    /// Applies to every hostname without an override of its own
+    global: Mutex<Option<bool>>,
+    hostnames: DashMap<Hostname, bool>,
+}
+
+impl Maintenance {
+    /// Turn maintenance on or off for a hostname, or every hostname, until it is cleared by
+    /// setting it to `None`
+    #[cfg(any(feature = "ui", test))]
+    pub fn set(&self, hostname: Option<Hostname>, enabled: Option<bool>) {
+        match (hostname, enabled) {
+            (Some(hostname), Some(enabled)) => {
+                self.hostnames.insert(hostname, enabled);
+            }
+            (Some(hostname), None) => {
+                self.hostnames.remove(&hostname);
+            }
+            (None, enabled) => {
+                *self.global.lock().expect("lock should not be poisoned") = enabled;
+            }
+        }
+    }
+
+    /// Whether the hostname is under maintenance, going by the overrides and then the config
+    pub fn is_active(&self, config: &MaintenanceConfig, hostname: &Hostname) -> bool {
+        if let Some(enabled) = self.hostnames.get(hostname) {
+            return *enabled;
+        }
+
+        let global = *self.global.lock().expect("lock should not be poisoned");
+        global.unwrap_or_else(|| config.is_active(hostname, SystemTime::now()))
+    }
+
+    /// Snapshot of the global override, and of every hostname's
+    #[cfg(feature = "ui")]
+    pub fn overrides(&self) -> (Option<bool>, Vec<(Hostname, bool)>) {
+        let global = *self.global.lock().expect("lock should not be poisoned");
+        let mut hostnames = self
+            .hostnames
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect::<Vec<_>>();
+        hostnames.sort();
+
+        (global, hostnames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use mcproxy_model::Hostname;
+
+    use super::Maintenance;
+    use crate::config::schema::{MaintenanceConfig, MaintenanceWindow};
+
+    #[test]
+    fn overrides() {
+        let now = SystemTime::now();
+        let config = MaintenanceConfig {
+            hostnames: vec![Hostname::from("a.example.com")],
+            bypass: vec!["Notch".to_string()],
+            windows: vec![MaintenanceWindow {
+                start: now - Duration::from_secs(60),
+                end: now + Duration::from_secs(3600),
+                hostnames: vec![Hostname::from("b.example.com")],
+            }],
+            ..Default::default()
+        };
+        let (a, b, c) = (
+            Hostname::from("a.example.com"),
+            Hostname::from("b.example.com"),
+            Hostname::from("c.example.com"),
+        );
+
+        let maintenance = Maintenance::default();
+        assert!(maintenance.is_active(&config, &a));
+        assert!(maintenance.is_active(&config, &b));
+        assert!(!maintenance.is_active(&config, &c));
+        assert!(!config.is_active(&b, now + Duration::from_secs(7200)));
+        assert!(config.bypasses("notch") && !config.bypasses("Steve"));
+
+        maintenance.set(None, Some(true));
+        maintenance.set(Some(a.clone()), Some(false));
+        assert!(!maintenance.is_active(&config, &a));
+        assert!(maintenance.is_active(&config, &c));
+
+        maintenance.set(None, None);
+        maintenance.set(Some(a.clone()), None);
+        assert!(maintenance.is_active(&config, &a));
+        assert!(!maintenance.is_active(&config, &c));
+    }
+}
@@ -1,33 +1,24 @@
 use std::{
-    collections::HashSet,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Instant, SystemTime},
 };
 
-use mcproxy_model::Upstream;
 use prometheus_client::collector::Collector;
-use tokio::{net::TcpStream, runtime::Handle, sync::watch::Receiver, task::JoinSet};
-use tracing::debug;
 
-use crate::{
-    config::schema::Config,
-    proto::{io::request::server_list_ping, packet::response::StatusResponse},
-};
+use crate::health::UpstreamHealth;
 
+/// Exposes the cached health checks of every upstream
+///
+/// Scrapes only read the snapshot kept current by [`crate::health::watch`], so they never wait
+/// on an upstream and add no load to them.
 #[derive(Debug)]
 pub struct MinecraftCollector {
-    tokio_runtime: Handle,
-
-    // TODO: replace with only pointer to the upstream listing
-    config: Receiver<Arc<Config>>,
+    upstream_health: Arc<UpstreamHealth>,
 }
 
 impl MinecraftCollector {
-    pub fn new(tokio_runtime: Handle, config: Receiver<Arc<Config>>) -> Self {
-        MinecraftCollector {
-            config,
-            tokio_runtime,
-        }
+    pub fn new(upstream_health: Arc<UpstreamHealth>) -> Self {
+        MinecraftCollector { upstream_health }
     }
 }
 
@@ -36,153 +27,148 @@ impl Collector for MinecraftCollector {
         &self,
         mut encoder: prometheus_client::encoding::DescriptorEncoder,
     ) -> Result<(), std::fmt::Error> {
-        let mut futures = JoinSet::<(Upstream, Option<(Duration, StatusResponse)>)>::new();
-
-        let upstreams: HashSet<_> = self
-            .config
-            .borrow()
-            .static_servers
-            .values()
-            .cloned()
-            .collect();
-
-        for upstream in upstreams {
-            futures.spawn(async move {
-                let stream = match TcpStream::connect(upstream.addr()).await {
-                    Ok(stream) => stream,
-                    Err(error) => {
-                        debug!(%upstream, %error, "failed to connect to upstream for ping");
-
-                        return (upstream, None);
-                    }
-                };
-
-                let (response_time, status_response) =
-                    match server_list_ping(stream, upstream.clone()).await {
-                        Ok(response) => response,
-                        Err(error) => {
-                            debug!(%upstream, %error, "failed to ping upstream");
-
-                            return (upstream, None);
-                        }
-                    };
-
-                (upstream, Some((response_time, status_response)))
-            });
+        let scrape_start = Instant::now();
+        let upstream_responses = self.upstream_health.checks();
+
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream_healthy",
+                "if the server is healthy (1) or not (0)",
+                None,
+                prometheus_client::metrics::MetricType::Gauge,
+            )?;
+
+            for (upstream, check) in &upstream_responses {
+                let healthy = check.healthy;
+
+                metric_encoder
+                    .encode_family(upstream)?
+                    .encode_gauge(&u32::from(healthy))?;
+            }
         }
 
-        let scrape_start = Instant::now();
-        self.tokio_runtime.block_on(async {
-            let mut upstream_responses = Vec::with_capacity(futures.len());
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream_check_age",
+                "time since the server was last pinged or connected to",
+                Some(&prometheus_client::registry::Unit::Seconds),
+                prometheus_client::metrics::MetricType::Gauge,
+            )?;
+
+            for (upstream, check) in &upstream_responses {
+                metric_encoder
+                    .encode_family(upstream)?
+                    .encode_gauge(&check.checked.elapsed().as_secs_f64())?;
+            }
+        }
 
-            while let Some(result) = futures.join_next().await {
-                let (upstream, metrics) = result.expect("join should not fail");
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream_last_success_timestamp",
+                "unix time of the last successful ping of the server",
+                Some(&prometheus_client::registry::Unit::Seconds),
+                prometheus_client::metrics::MetricType::Gauge,
+            )?;
+
+            for (upstream, check) in &upstream_responses {
+                let Some(timestamp) = check
+                    .last_success
+                    .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                else {
+                    continue;
+                };
 
-                upstream_responses.push((upstream, metrics));
+                metric_encoder
+                    .encode_family(upstream)?
+                    .encode_gauge(&timestamp.as_secs_f64())?;
             }
+        }
 
-            let upstream_responses = upstream_responses; // Immutable;
-
-            {
-                let mut metric_encoder = encoder.encode_descriptor(
-                    "mcproxy_upstream_healthy",
-                    "if the server is healthy (1) or not (0)",
-                    None,
-                    prometheus_client::metrics::MetricType::Gauge,
-                )?;
+        let healthy_upstream_responses =
+            upstream_responses.iter().filter_map(|(upstream, check)| {
+                let (response_time, status_response) =
+                    check.response.as_ref().filter(|_| check.healthy)?;
 
-                for (upstream, metrics) in &upstream_responses {
-                    let healthy = metrics.is_some();
+                Some((upstream, (response_time, status_response)))
+            });
 
-                    metric_encoder
-                        .encode_family(upstream)?
-                        .encode_gauge(&u32::from(healthy))?;
-                }
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream",
+                "metric for tracking upstream info",
+                None,
+                prometheus_client::metrics::MetricType::Info,
+            )?;
+
+            for (upstream, (_, status_response)) in healthy_upstream_responses.clone() {
+                // TODO: merge label set?
+                metric_encoder.encode_info(&[
+                    ("minecraft_version", status_response.version.name.as_ref()),
+                    ("host", upstream.host.as_ref()),
+                    ("port", &upstream.port.to_string()),
+                ])?;
             }
+        }
 
-            let healthy_upstream_responses =
-                upstream_responses.iter().filter_map(|(upstream, metrics)| {
-                    metrics.as_ref().map(|metrics| (upstream, metrics))
-                });
-
-            {
-                let mut metric_encoder = encoder.encode_descriptor(
-                    "mcproxy_upstream",
-                    "metric for tracking upstream info",
-                    None,
-                    prometheus_client::metrics::MetricType::Info,
-                )?;
-
-                for (upstream, (_, status_response)) in healthy_upstream_responses.clone() {
-                    // TODO: merge label set?
-                    metric_encoder.encode_info(&[
-                        ("minecraft_version", status_response.version.name.as_ref()),
-                        ("host", upstream.host.as_ref()),
-                        ("port", &upstream.port.to_string()),
-                    ])?;
-                }
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream_response_time",
+                "round trip time to to the server",
+                None,
+                prometheus_client::metrics::MetricType::Gauge,
+            )?;
+
+            for (upstream, (response_time, _)) in healthy_upstream_responses.clone() {
+                metric_encoder
+                    .encode_family(upstream)?
+                    .encode_gauge(&response_time.as_secs_f64())?;
             }
+        }
 
-            {
-                let mut metric_encoder = encoder.encode_descriptor(
-                    "mcproxy_upstream_response_time",
-                    "round trip time to to the server",
-                    None,
-                    prometheus_client::metrics::MetricType::Gauge,
-                )?;
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream_players_online",
+                "current online players",
+                None,
+                prometheus_client::metrics::MetricType::Gauge,
+            )?;
 
-                for (upstream, (response_time, _)) in healthy_upstream_responses.clone() {
+            for (upstream, (_, status_response)) in healthy_upstream_responses.clone() {
+                if let Some(players) = &status_response.players {
                     metric_encoder
                         .encode_family(upstream)?
-                        .encode_gauge(&response_time.as_secs_f64())?;
+                        .encode_gauge(&players.online)?;
                 }
             }
+        }
 
-            {
-                let mut metric_encoder = encoder.encode_descriptor(
-                    "mcproxy_upstream_players_online",
-                    "current online players",
-                    None,
-                    prometheus_client::metrics::MetricType::Gauge,
-                )?;
-
-                for (upstream, (_, status_response)) in healthy_upstream_responses.clone() {
-                    if let Some(players) = &status_response.players {
-                        metric_encoder
-                            .encode_family(upstream)?
-                            .encode_gauge(&players.online)?;
-                    }
-                }
-            }
+        {
+            let mut metric_encoder = encoder.encode_descriptor(
+                "mcproxy_upstream_players_max",
+                "configured maximum allowed players",
+                None,
+                prometheus_client::metrics::MetricType::Gauge,
+            )?;
 
-            {
-                let mut metric_encoder = encoder.encode_descriptor(
-                    "mcproxy_upstream_players_max",
-                    "configured maximum allowed players",
-                    None,
-                    prometheus_client::metrics::MetricType::Gauge,
-                )?;
-
-                for (upstream, (_, status_response)) in healthy_upstream_responses.clone() {
-                    if let Some(players) = &status_response.players {
-                        metric_encoder
-                            .encode_family(upstream)?
-                            .encode_gauge(&players.max)?;
-                    }
+            for (upstream, (_, status_response)) in healthy_upstream_responses.clone() {
+                if let Some(players) = &status_response.players {
+                    metric_encoder
+                        .encode_family(upstream)?
+                        .encode_gauge(&players.max)?;
                 }
             }
+        }
 
-            let duration = scrape_start.elapsed();
-            encoder
-                .encode_descriptor(
-                    "mcproxy_upstream_collect_duration",
-                    "length of time to process current scrape",
-                    Some(&prometheus_client::registry::Unit::Seconds),
-                    prometheus_client::metrics::MetricType::Gauge,
-                )?
-                .encode_gauge(&duration.as_secs_f64())?;
-
-            Ok(())
-        })
+        let duration = scrape_start.elapsed();
+        encoder
+            .encode_descriptor(
+                "mcproxy_upstream_collect_duration",
+                "length of time to process current scrape",
+                Some(&prometheus_client::registry::Unit::Seconds),
+                prometheus_client::metrics::MetricType::Gauge,
+            )?
+            .encode_gauge(&duration.as_secs_f64())?;
+
+        Ok(())
     }
 }
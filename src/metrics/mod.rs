@@ -1,14 +1,16 @@
 use std::{fmt::Debug, sync::Arc};
 
-use crate::{config::schema::Config, metrics::tokio_collector::runtime::TokioRuntimeCollector};
+use crate::{
+    config::schema::AccessRefusal, health::UpstreamHealth,
+    metrics::tokio_collector::runtime::TokioRuntimeCollector, proto::packet::NextState,
+};
 use mcproxy_model::Upstream;
 use minecraft_collector::MinecraftCollector;
 use prometheus_client::{
-    encoding::EncodeLabelSet,
+    encoding::{EncodeLabelSet, EncodeLabelValue},
     metrics::{counter::Counter, family::Family, gauge::Gauge, info::Info},
     registry::Registry,
 };
-use tokio::sync::watch::Receiver;
 use tokio_collector::task::TokioTaskCollector;
 use tokio_metrics::TaskMonitor;
 
@@ -25,26 +27,110 @@ pub struct BuildInfo {
     pub repo_url: &'static str,
 }
 
+#[derive(EncodeLabelSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HandshakeLabels {
+    pub next_state: HandshakeNextState,
+}
+
+#[derive(EncodeLabelValue, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandshakeNextState {
+    Ping,
+    Login,
+    /// The client was transferred from another server (1.20.5+)
+    Transfer,
+    Unknown,
+}
+
+impl From<&NextState> for HandshakeNextState {
+    fn from(next_state: &NextState) -> Self {
+        match next_state {
+            NextState::Ping => HandshakeNextState::Ping,
+            NextState::Login => HandshakeNextState::Login,
+            NextState::Transfer => HandshakeNextState::Transfer,
+            NextState::Unknown(_) => HandshakeNextState::Unknown,
+        }
+    }
+}
+
+#[derive(EncodeLabelSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnknownUpstreamLabels {
+    pub outcome: UnknownUpstreamOutcome,
+}
+
+#[derive(EncodeLabelValue, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnknownUpstreamOutcome {
+    /// The connection was routed to the default upstream
+    Fallback,
+    /// The connection was answered by the placeholder server
+    Rejected,
+}
+
+#[derive(EncodeLabelSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LoginRefusedLabels {
+    pub reason: LoginRefusedReason,
+}
+
+#[derive(EncodeLabelValue, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoginRefusedReason {
+    /// The player is on a denylist
+    Denied,
+    /// The player is missing from an allowlist
+    NotAllowed,
+}
+
+impl From<&AccessRefusal> for LoginRefusedReason {
+    fn from(refusal: &AccessRefusal) -> Self {
+        match refusal {
+            AccessRefusal::Denied { .. } => LoginRefusedReason::Denied,
+            AccessRefusal::NotAllowed { .. } => LoginRefusedReason::NotAllowed,
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct ConnectionMetrics {
     pub client_connections: Counter,
     pub client_handshakes_received: Counter,
+    pub client_handshakes_received_by_state: Family<HandshakeLabels, Counter>,
+    pub client_legacy_pings_received: Counter,
+    pub connection_ip_rate_limited: Counter,
+    pub connection_network_rate_limited: Counter,
+    pub connection_handshakes_limited: Counter,
+    pub connection_banned: Counter,
+    pub client_bans: Counter,
     pub connection_unknown_upstream: Counter,
+    pub connection_unknown_upstream_by_outcome: Family<UnknownUpstreamLabels, Counter>,
     pub connection_can_not_reach_upstream: Family<Upstream, Counter>,
+    pub connection_retried: Family<Upstream, Counter>,
+    pub connection_failed_over: Family<Upstream, Counter>,
+    pub connection_transfer_refused: Family<Upstream, Counter>,
+    pub connection_status_overridden: Family<Upstream, Counter>,
+    pub connection_started_server: Counter,
+    pub connection_held_in_limbo: Counter,
+    pub connection_maintenance: Counter,
+    pub connection_maintenance_bypassed: Counter,
+    pub connection_login_refused: Family<LoginRefusedLabels, Counter>,
     pub connection_established: Family<Upstream, Counter>,
 }
 
+#[derive(Default, Clone)]
+pub struct SleepMetrics {
+    pub servers_put_to_sleep: Counter,
+    pub servers_sleeping: Gauge,
+}
+
 #[derive(Default, Clone)]
 pub struct ActiveConnectionMetrics {
     pub active_server_connections: Family<Upstream, Gauge>,
 }
 
 pub fn create_metrics(
-    config: Receiver<Arc<Config>>,
+    upstream_health: Arc<UpstreamHealth>,
 ) -> (
     Registry,
     ConnectionMetrics,
     ActiveConnectionMetrics,
+    SleepMetrics,
     TaskMonitor,
 ) {
     let mut registry = Registry::default();
@@ -72,16 +158,103 @@ pub fn create_metrics(
         "amount of handshakes received from minecraft clients",
         connection_metrics.client_handshakes_received.clone(),
     );
+    registry.register(
+        "client_handshakes_received_by_state",
+        "amount of handshakes received from minecraft clients, by their next state",
+        connection_metrics
+            .client_handshakes_received_by_state
+            .clone(),
+    );
+    registry.register(
+        "client_legacy_pings_received",
+        "amount of legacy (pre-1.7) server list pings received from minecraft clients",
+        connection_metrics.client_legacy_pings_received.clone(),
+    );
+    registry.register(
+        "connection_ip_rate_limited",
+        "amount of connections turned away for going over the rate limit of their address",
+        connection_metrics.connection_ip_rate_limited.clone(),
+    );
+    registry.register(
+        "connection_network_rate_limited",
+        "amount of connections turned away for going over the rate limit of their network",
+        connection_metrics.connection_network_rate_limited.clone(),
+    );
+    registry.register(
+        "connection_handshakes_limited",
+        "amount of connections turned away because too many others were handshaking",
+        connection_metrics.connection_handshakes_limited.clone(),
+    );
+    registry.register(
+        "connection_banned",
+        "amount of connections turned away because their address was banned",
+        connection_metrics.connection_banned.clone(),
+    );
+    registry.register(
+        "client_bans",
+        "amount of times an address was banned for repeatedly going over its rate limits",
+        connection_metrics.client_bans.clone(),
+    );
     registry.register(
         "connection_unknown_upstream",
         "amount of connections that were rejected due to an unknown upstream",
         connection_metrics.connection_unknown_upstream.clone(),
     );
+    registry.register(
+        "connection_unknown_upstream_by_outcome",
+        "amount of connections with an unknown upstream, by whether they fell back or were rejected",
+        connection_metrics.connection_unknown_upstream_by_outcome.clone(),
+    );
     registry.register(
         "connection_can_not_reach_upstream",
         "amount of connections that were rejected due to an unreachable upstream",
         connection_metrics.connection_can_not_reach_upstream.clone(),
     );
+    registry.register(
+        "connection_retried",
+        "amount of times connecting to an upstream was retried after failing",
+        connection_metrics.connection_retried.clone(),
+    );
+    registry.register(
+        "connection_failed_over",
+        "amount of connections served by a fallback upstream, by the upstream serving them",
+        connection_metrics.connection_failed_over.clone(),
+    );
+    registry.register(
+        "connection_transfer_refused",
+        "amount of transferred clients that were disconnected because the upstream refuses transfers",
+        connection_metrics.connection_transfer_refused.clone(),
+    );
+    registry.register(
+        "connection_status_overridden",
+        "amount of pings answered by the proxy with an upstream's status and its overrides, by the upstream",
+        connection_metrics.connection_status_overridden.clone(),
+    );
+    registry.register(
+        "connection_started_server",
+        "amount of connections that started a stopped server",
+        connection_metrics.connection_started_server.clone(),
+    );
+    registry.register(
+        "connection_held_in_limbo",
+        "amount of players held in limbo while their server started",
+        connection_metrics.connection_held_in_limbo.clone(),
+    );
+    registry.register(
+        "connection_maintenance",
+        "amount of connections answered with the maintenance placeholder",
+        connection_metrics.connection_maintenance.clone(),
+    );
+    registry.register(
+        "connection_maintenance_bypassed",
+        "amount of players on the bypass list proxied during maintenance",
+        connection_metrics.connection_maintenance_bypassed.clone(),
+    );
+    registry.register(
+        "connection_login_refused",
+        "amount of logins refused by the allowlists and denylists, by whether the player was denied or not allowed",
+        connection_metrics.connection_login_refused.clone(),
+    );
     registry.register(
         "connection_established",
         "amount of connections that fully established to an upstream",
@@ -95,6 +268,18 @@ pub fn create_metrics(
         active_connection_metrics.active_server_connections.clone(),
     );
 
+    let sleep_metrics = SleepMetrics::default();
+    registry.register(
+        "servers_put_to_sleep",
+        "amount of times an idle server was put to sleep",
+        sleep_metrics.servers_put_to_sleep.clone(),
+    );
+    registry.register(
+        "servers_sleeping",
+        "amount of servers put to sleep that have not started again",
+        sleep_metrics.servers_sleeping.clone(),
+    );
+
     // Tokio Runtime Metrics
     registry.register_collector(Box::new(TokioRuntimeCollector::new()));
 
@@ -105,15 +290,13 @@ pub fn create_metrics(
     )));
 
     // Minecraft Upstream Metrics
-    registry.register_collector(Box::new(MinecraftCollector::new(
-        tokio::runtime::Handle::current(),
-        config,
-    )));
+    registry.register_collector(Box::new(MinecraftCollector::new(upstream_health)));
 
     (
         registry,
         connection_metrics,
         active_connection_metrics,
+        sleep_metrics,
         proxy_task_monitor,
     )
 }
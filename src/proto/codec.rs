@@ -0,0 +1,510 @@
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::proto::{error::ProtocolError, nbt::Tag, packet::RawTextComponent, string, var_int};
+
+/// A value which can be written in the protocol's binary format
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+/// A value which can be read from the protocol's binary format
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError>;
+}
+
+/// The id a packet is sent with, which is only unique within its state and direction
+pub trait PacketId {
+    const ID: i32;
+}
+
+/// Encode a value into a new buffer
+pub fn encode(value: &impl Encode) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf);
+
+    buf
+}
+
+/// The data of a packet being decoded, along with the limits it is held to
+#[derive(Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    max_string_length: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], max_string_length: usize) -> Self {
+        Reader {
+            data,
+            max_string_length,
+        }
+    }
+
+    /// Take the next `length` bytes, failing if the data ends before them
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], ProtocolError> {
+        if length > self.data.len() {
+            return Err(ProtocolError::Truncated);
+        }
+
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Ok(taken)
+    }
+
+    /// Take the next `N` bytes as an array
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        Ok(self
+            .take(N)?
+            .try_into()
+            .expect("exactly N bytes were taken"))
+    }
+
+    /// Take every byte left
+    pub fn take_remaining(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Finish decoding, failing if there was more data than the packet has fields for
+    pub fn finish(self) -> Result<(), ProtocolError> {
+        match self.data.len() {
+            0 => Ok(()),
+            length => Err(ProtocolError::TrailingBytes(length)),
+        }
+    }
+}
+
+/// A variable length integer, taking one to five bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VarInt(pub i32);
+
+impl Encode for VarInt {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(var_int::write(self.0));
+    }
+}
+
+impl Decode for VarInt {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        let mut result = 0;
+
+        for position in 0..5 {
+            let [byte] = reader.take_array()?;
+            result |= i32::from(byte & 0b0111_1111) << (7 * position);
+
+            if byte & 0b1000_0000 == 0 {
+                return Ok(VarInt(result));
+            }
+        }
+
+        Err(ProtocolError::VarIntTooLong)
+    }
+}
+
+/// A variable length long, taking one to ten bytes
+///
+/// None of the packets the proxy reads or writes has one yet, only play packets do.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VarLong(pub i64);
+
+impl Encode for VarLong {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        // Negative values are written as their two's complement, which always takes ten bytes
+        let mut value = self.0 as u64;
+
+        loop {
+            let byte = (value & 0b0111_1111) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                buf.push(byte);
+                break;
+            }
+
+            buf.push(byte | 0b1000_0000);
+        }
+    }
+}
+
+impl Decode for VarLong {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        let mut result = 0;
+
+        for position in 0..10 {
+            let [byte] = reader.take_array()?;
+            result |= i64::from(byte & 0b0111_1111) << (7 * position);
+
+            if byte & 0b1000_0000 == 0 {
+                return Ok(VarLong(result));
+            }
+        }
+
+        Err(ProtocolError::VarIntTooLong)
+    }
+}
+
+macro_rules! big_endian {
+    ($($number:ty),*) => {$(
+        impl Encode for $number {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend(self.to_be_bytes());
+            }
+        }
+
+        impl Decode for $number {
+            fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+                Ok(<$number>::from_be_bytes(reader.take_array()?))
+            }
+        }
+    )*};
+}
+
+big_endian!(u8, i8, u16, i16, i32, i64, f32, f64);
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(u8::from(*self));
+    }
+}
+
+impl Decode for bool {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        Ok(u8::decode(reader)? != 0)
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(string::write(self));
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_str().encode(buf);
+    }
+}
+
+impl Decode for String {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        let max_length = reader.max_string_length;
+
+        let VarInt(length) = VarInt::decode(reader)?;
+        let length = usize::try_from(length).map_err(|_| ProtocolError::InvalidLength(length))?;
+
+        // Every UTF-16 code unit takes at most three bytes in UTF 8
+        if length > max_length.saturating_mul(3) {
+            return Err(ProtocolError::StringTooLong {
+                length: length / 3,
+                max: max_length,
+            });
+        }
+
+        // Never repaired, since names and addresses are matched against the config
+        let string = String::from_utf8(reader.take(length)?.to_vec())
+            .map_err(|_| ProtocolError::InvalidUtf8)?;
+
+        let length = string.encode_utf16().count();
+        if length > max_length {
+            return Err(ProtocolError::StringTooLong {
+                length,
+                max: max_length,
+            });
+        }
+
+        Ok(string)
+    }
+}
+
+impl Encode for Uuid {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for Uuid {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        Ok(Uuid::from_bytes(reader.take_array()?))
+    }
+}
+
+/// Optional fields are prefixed with whether they are present
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.is_some().encode(buf);
+
+        if let Some(value) = self {
+            value.encode(buf);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        match bool::decode(reader)? {
+            true => Ok(Some(T::decode(reader)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+/// Arrays are prefixed with their length
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        VarInt(
+            self.len()
+                .try_into()
+                .expect("arrays should be shorter than i32::MAX"),
+        )
+        .encode(buf);
+
+        for element in self {
+            element.encode(buf);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_slice().encode(buf);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        let VarInt(length) = VarInt::decode(reader)?;
+        let length = usize::try_from(length).map_err(|_| ProtocolError::InvalidLength(length))?;
+
+        // The length is untrusted, so only as much is reserved as the data could possibly hold
+        let mut elements = Vec::with_capacity(length.min(reader.remaining()));
+        for _ in 0..length {
+            elements.push(T::decode(reader)?);
+        }
+
+        Ok(elements)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (**self).encode(buf);
+    }
+}
+
+/// Every byte left in the packet, for fields whose length is implied by the packet's
+#[derive(Debug, Clone, Default)]
+pub struct RemainingBytes(pub Vec<u8>);
+
+impl Encode for RemainingBytes {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for RemainingBytes {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        Ok(RemainingBytes(reader.take_remaining().to_vec()))
+    }
+}
+
+/// A value sent as a string of JSON
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> Encode for Json<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        serde_json::to_string(&self.0)
+            .expect("protocol values should serialize")
+            .encode(buf);
+    }
+}
+
+impl<T: DeserializeOwned> Decode for Json<T> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        serde_json::from_str(&String::decode(reader)?)
+            .map(Json)
+            .map_err(ProtocolError::InvalidJson)
+    }
+}
+
+/// A value sent as network NBT
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nbt<T>(pub T);
+
+impl Encode for Nbt<Tag> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+    }
+}
+
+impl Decode for Nbt<Tag> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        Tag::decode(reader).map(Nbt)
+    }
+}
+
+impl Encode for Nbt<RawTextComponent> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        Tag::from_text_component(&self.0).encode(buf);
+    }
+}
+
+impl Decode for Nbt<RawTextComponent> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        Tag::decode(reader)?.to_text_component().map(Nbt)
+    }
+}
+
+/// Declare a struct made of encodable fields, which are encoded and decoded in order
+///
+/// The codec traits to implement are listed after the name, and packets have their id in
+/// parentheses before them:
+///
+/// ```ignore
+/// packet! {
+///     #[derive(Debug)]
+///     pub struct PingRequest(0x01): Encode, Decode {
+///         pub payload: i64,
+///     }
+/// }
+/// ```
+macro_rules! packet {
+    (@impls $name:ident [] $fields:tt) => {};
+    (@impls $name:ident [$codec:ident $($rest:ident)*] $fields:tt) => {
+        packet!(@$codec $name $fields);
+        packet!(@impls $name [$($rest)*] $fields);
+    };
+    (@Encode $name:ident { $($field:ident)* }) => {
+        impl $crate::proto::codec::Encode for $name {
+            fn encode(&self, _buf: &mut Vec<u8>) {
+                $($crate::proto::codec::Encode::encode(&self.$field, _buf);)*
+            }
+        }
+    };
+    (@Decode $name:ident { $($field:ident)* }) => {
+        impl $crate::proto::codec::Decode for $name {
+            fn decode(
+                _reader: &mut $crate::proto::codec::Reader<'_>,
+            ) -> Result<Self, $crate::proto::error::ProtocolError> {
+                Ok($name {
+                    $($field: $crate::proto::codec::Decode::decode(_reader)?,)*
+                })
+            }
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident($id:expr): $($codec:ident),+ {
+            $($(#[$field_attr:meta])* pub $field:ident: $type:ty),* $(,)?
+        }
+    ) => {
+        packet! {
+            $(#[$attr])*
+            pub struct $name: $($codec),+ {
+                $($(#[$field_attr])* pub $field: $type),*
+            }
+        }
+
+        impl $crate::proto::codec::PacketId for $name {
+            const ID: i32 = $id;
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident: $($codec:ident),+ {
+            $($(#[$field_attr:meta])* pub $field:ident: $type:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $name {
+            $($(#[$field_attr])* pub $field: $type,)*
+        }
+
+        packet!(@impls $name [$($codec)+] { $($field)* });
+    };
+}
+
+pub(crate) use packet;
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::{encode, Decode, Nbt, Reader, VarInt, VarLong};
+    use crate::proto::{error::ProtocolError, nbt::Tag};
+
+    packet! {
+        #[derive(Debug, PartialEq)]
+        pub struct Everything(0x2A): Encode, Decode {
+            pub var_int: VarInt,
+            pub var_long: VarLong,
+            pub string: String,
+            pub uuid: Uuid,
+            pub flag: bool,
+            pub optional: Option<u16>,
+            pub array: Vec<i64>,
+            pub nbt: Nbt<Tag>,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let everything = Everything {
+            var_int: VarInt(-1),
+            var_long: VarLong(i64::MIN),
+            string: "§ẞ🦀".to_string(),
+            uuid: Uuid::from_u128(0x0123456789ABCDEF),
+            flag: true,
+            optional: Some(25565),
+            array: vec![1, -2, 3],
+            nbt: Nbt(Tag::Compound(vec![("name".to_string(), Tag::Short(1))])),
+        };
+
+        let data = encode(&everything);
+        assert_eq!(data[..5], [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        assert_eq!(
+            data[5..15],
+            [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]
+        );
+
+        let mut reader = Reader::new(&data, 4);
+        assert_eq!(Everything::decode(&mut reader).unwrap(), everything);
+        reader.finish().unwrap();
+
+        // The crab takes two UTF-16 code units, one more than allowed here
+        let mut reader = Reader::new(&data, 3);
+        assert!(matches!(
+            Everything::decode(&mut reader),
+            Err(ProtocolError::StringTooLong { length: 4, max: 3 })
+        ));
+
+        let mut reader = Reader::new(&data[..data.len() - 1], 4);
+        assert!(matches!(
+            Everything::decode(&mut reader),
+            Err(ProtocolError::Truncated)
+        ));
+
+        // A huge array length with nothing behind it
+        let mut reader = Reader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0x07], 4);
+        assert!(matches!(
+            Vec::<bool>::decode(&mut reader),
+            Err(ProtocolError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        // A lone continuation byte between otherwise valid text
+        let mut reader = Reader::new(&[0x03, b'a', 0x80, b'b'], 16);
+        assert!(matches!(
+            String::decode(&mut reader),
+            Err(ProtocolError::InvalidUtf8)
+        ));
+    }
+}
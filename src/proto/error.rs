@@ -0,0 +1,84 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use tokio::io;
+use tracing_error::{InstrumentError, TracedError};
+
+/// Data from a peer that does not follow the protocol, or breaks the configured limits
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// A VarInt continued past its fifth byte
+    VarIntTooLong,
+    /// A packet or string length that is negative, or too short for what it must contain
+    InvalidLength(i32),
+    /// A packet longer than allowed in the current state
+    PacketTooLong { length: usize, max: usize },
+    /// A string longer than allowed, in UTF-16 code units like the protocol counts them
+    StringTooLong { length: usize, max: usize },
+    /// A string which is not valid UTF-8
+    InvalidUtf8,
+    /// A different packet than the one the current state expects
+    UnexpectedPacket { expected: i32, id: i32 },
+    /// A handshake address with more parts than a hostname and a Forge marker
+    MalformedAddress,
+    /// A legacy ping in none of the known formats
+    MalformedLegacyPing,
+    /// A packet which ended before all of its fields
+    Truncated,
+    /// A packet with data left over after all of its fields
+    TrailingBytes(usize),
+    /// A JSON field, such as a status response, which is not valid JSON
+    InvalidJson(serde_json::Error),
+    /// NBT with an unknown tag type, invalid modified UTF-8, or nested deeper than vanilla allows
+    MalformedNbt,
+}
+
+impl ProtocolError {
+    pub fn in_current_span(self) -> TracedError<io::Error> {
+        io::Error::from(self).in_current_span()
+    }
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::VarIntTooLong => f.write_str("VarInt is too big"),
+            ProtocolError::InvalidLength(length) => write!(f, "invalid length {length}"),
+            ProtocolError::PacketTooLong { length, max } => {
+                write!(f, "packet of {length} bytes is longer than {max} bytes")
+            }
+            ProtocolError::StringTooLong { length, max } => {
+                write!(f, "string of {length} characters is longer than {max}")
+            }
+            ProtocolError::InvalidUtf8 => f.write_str("string is not valid UTF-8"),
+            ProtocolError::UnexpectedPacket { expected, id } => {
+                write!(f, "expected packet {expected:#04x}, got {id:#04x}")
+            }
+            ProtocolError::MalformedAddress => f.write_str("malformed handshake address"),
+            ProtocolError::MalformedLegacyPing => f.write_str("malformed legacy ping"),
+            ProtocolError::Truncated => f.write_str("packet ended early"),
+            ProtocolError::TrailingBytes(length) => {
+                write!(f, "packet has {length} bytes left over")
+            }
+            ProtocolError::InvalidJson(error) => write!(f, "invalid JSON: {error}"),
+            ProtocolError::MalformedNbt => f.write_str("malformed NBT"),
+        }
+    }
+}
+
+impl Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProtocolError::InvalidJson(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProtocolError> for io::Error {
+    fn from(error: ProtocolError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
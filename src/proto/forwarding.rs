@@ -0,0 +1,298 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+};
+
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio::{io, net::TcpStream};
+use tracing::{debug, trace};
+use tracing_error::TracedError;
+use uuid::{Builder, Uuid};
+
+use super::{
+    codec::{encode, packet, PacketId, RemainingBytes, VarInt},
+    io::{
+        decode_packet, read_login_start, read_packet, write_handshake_with_address, write_packet,
+        write_typed_packet,
+    },
+    limits::ProtocolLimits,
+    packet::{
+        login::{clientbound::LoginPluginRequest, clientbound::Property, serverbound},
+        Handshake, Packet,
+    },
+    string,
+};
+
+/// The login plugin channel Velocity modern forwarding is requested on
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+
+/// The version of Velocity modern forwarding sent, which carries no chat signing key
+const VELOCITY_FORWARDING_VERSION: i32 = 1;
+
+/// Properties BungeeCord adds to the forwarded profile of Forge clients, whose marker can not be
+/// kept in the handshake address along with the player's info
+const FORGE_CLIENT_PROPERTY: &str = "forgeClient";
+const FORGE_EXTRA_DATA_PROPERTY: &str = "extraData";
+
+/// How to tell the upstream about the player behind the proxy
+///
+/// The proxy never authenticates players, and sends the upstream an offline mode UUID for
+/// whatever name the client logs in with. An upstream trusting the forwarded info lets anyone
+/// join as any player, so forwarding has to be enabled with `allow_unauthenticated = true`.
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PlayerInfoForwarding {
+    /// BungeeCord legacy forwarding in the handshake address, for `bungeecord: true` in spigot.yml
+    Bungeecord {
+        /// BungeeGuard token sent along with the player's info, if the upstream checks one
+        secret: Option<String>,
+        /// Acknowledges that players are forwarded without being authenticated
+        #[serde(default)]
+        allow_unauthenticated: bool,
+    },
+    /// Velocity modern forwarding in a signed login plugin message
+    Velocity {
+        /// The forwarding secret shared with the upstream
+        secret: String,
+        /// Acknowledges that players are forwarded without being authenticated
+        #[serde(default)]
+        allow_unauthenticated: bool,
+    },
+}
+
+impl PlayerInfoForwarding {
+    /// Whether the config acknowledges that the forwarded players are not authenticated
+    pub fn allows_unauthenticated(&self) -> bool {
+        match self {
+            PlayerInfoForwarding::Bungeecord {
+                allow_unauthenticated,
+                ..
+            }
+            | PlayerInfoForwarding::Velocity {
+                allow_unauthenticated,
+                ..
+            } => *allow_unauthenticated,
+        }
+    }
+}
+
+impl Display for PlayerInfoForwarding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerInfoForwarding::Bungeecord { .. } => f.write_str("bungeecord"),
+            PlayerInfoForwarding::Velocity { .. } => f.write_str("velocity"),
+        }
+    }
+}
+
+/// The UUID an offline mode server gives a player, as nothing here authenticates them
+pub fn offline_uuid(name: &str) -> Uuid {
+    let hash = Md5::digest(format!("OfflinePlayer:{name}"));
+
+    Builder::from_md5_bytes(hash.into()).into_uuid()
+}
+
+/// The handshake address carrying BungeeCord legacy forwarding
+///
+/// Like BungeeCord, the Forge marker of the address moves into the forwarded properties, with
+/// its `\0` separators replaced by `\1`.
+pub fn bungeecord_address(
+    handshake: &Handshake,
+    client: IpAddr,
+    uuid: Uuid,
+    secret: Option<&str>,
+) -> String {
+    let mut address = format!("{}\0{client}\0{}", handshake.address, uuid.simple());
+
+    let mut properties = Vec::new();
+    if let Some(secret) = secret {
+        properties.push(serde_json::json!({
+            "name": "bungeeguard-token",
+            "value": secret,
+            "signature": "",
+        }));
+    }
+    if let Some(forge_version) = &handshake.address_forge_version {
+        properties.push(serde_json::json!({
+            "name": FORGE_CLIENT_PROPERTY,
+            "value": "true",
+            "signature": null,
+        }));
+        properties.push(serde_json::json!({
+            "name": FORGE_EXTRA_DATA_PROPERTY,
+            "value": format!("\u{1}{forge_version}\u{1}"),
+            "signature": "",
+        }));
+    }
+
+    if !properties.is_empty() {
+        address.push('\0');
+        address.push_str(&serde_json::Value::Array(properties).to_string());
+    }
+
+    address
+}
+
+packet! {
+    /// The player info Velocity modern forwarding signs
+    #[derive(Debug)]
+    pub struct VelocityPlayerInfo: Encode {
+        pub version: VarInt,
+        pub address: String,
+        pub uuid: Uuid,
+        pub name: String,
+        pub properties: Vec<Property>,
+    }
+}
+
+/// The signed player info sent in answer to Velocity's login plugin request
+pub fn velocity_player_info(secret: &str, client: IpAddr, uuid: Uuid, name: &str) -> Vec<u8> {
+    let payload = encode(&VelocityPlayerInfo {
+        version: VarInt(VELOCITY_FORWARDING_VERSION),
+        address: client.to_string(),
+        uuid,
+        name: name.to_string(),
+        // No profile properties, since the player was never authenticated
+        properties: Vec::new(),
+    });
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(&payload);
+
+    let mut player_info = mac.finalize().into_bytes().to_vec();
+    player_info.extend(payload);
+    player_info
+}
+
+/// Replay the handshake and Login Start to the upstream, forwarding the player's info
+///
+/// With Velocity forwarding, the upstream's first packet is answered if it is the forwarding
+/// request, and otherwise passed on to the client untouched.
+#[tracing::instrument(skip_all, fields(%forwarding), err)]
+pub async fn forward_login(
+    client_stream: &mut TcpStream,
+    server_stream: &mut TcpStream,
+    handshake: &Handshake,
+    handshake_packet: &Packet,
+    client: IpAddr,
+    forwarding: &PlayerInfoForwarding,
+    limits: &ProtocolLimits,
+) -> Result<(), TracedError<io::Error>> {
+    let (serverbound::LoginStart { name, .. }, login_start) =
+        read_login_start(client_stream, limits).await?;
+    let uuid = offline_uuid(&name);
+    trace!(name, %uuid, "forwarding player info");
+
+    match forwarding {
+        PlayerInfoForwarding::Bungeecord { secret, .. } => {
+            let address = bungeecord_address(handshake, client, uuid, secret.as_deref());
+
+            write_handshake_with_address(server_stream, handshake, &address).await?;
+            write_packet(server_stream, login_start.id, &login_start.data).await?;
+        }
+        PlayerInfoForwarding::Velocity { secret, .. } => {
+            write_packet(server_stream, handshake_packet.id, &handshake_packet.data).await?;
+            write_packet(server_stream, login_start.id, &login_start.data).await?;
+
+            let request = read_packet(server_stream, limits.upstream_packet_length).await?;
+
+            let message_id = if request.id == LoginPluginRequest::ID {
+                let LoginPluginRequest {
+                    message_id,
+                    channel,
+                    ..
+                } = decode_packet(&request, string::MAX_LENGTH)?;
+
+                (channel == VELOCITY_CHANNEL).then_some(message_id)
+            } else {
+                None
+            };
+
+            match message_id {
+                Some(message_id) => {
+                    write_typed_packet(
+                        server_stream,
+                        &serverbound::LoginPluginResponse {
+                            message_id,
+                            successful: true,
+                            data: RemainingBytes(velocity_player_info(secret, client, uuid, &name)),
+                        },
+                    )
+                    .await?;
+                }
+                None => {
+                    debug!(
+                        packet_id = request.id,
+                        "upstream did not request velocity forwarding"
+                    );
+
+                    write_packet(client_stream, request.id, &request.data).await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use hmac::{Hmac, Mac};
+    use mcproxy_model::Hostname;
+    use sha2::Sha256;
+
+    use super::{bungeecord_address, offline_uuid, velocity_player_info};
+    use crate::proto::packet::{Handshake, NextState};
+
+    #[test]
+    fn player_info() {
+        let uuid = offline_uuid("Notch");
+        assert_eq!(uuid.to_string(), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+
+        let handshake = Handshake {
+            protocol_version: 767,
+            address: Hostname::from("play.example.com"),
+            address_forge_version: None,
+            port: 25565,
+            next_state: NextState::Login,
+        };
+        assert_eq!(
+            bungeecord_address(
+                &handshake,
+                "203.0.113.7".parse().unwrap(),
+                uuid,
+                Some("token")
+            ),
+            "play.example.com\x00203.0.113.7\x00b50ad385829d3141a2167e7d7539ba7f\x00\
+             [{\"name\":\"bungeeguard-token\",\"signature\":\"\",\"value\":\"token\"}]"
+        );
+
+        // Forge clients keep their marker in the properties
+        let handshake = Handshake {
+            address_forge_version: Some("FML2".into()),
+            ..handshake
+        };
+        assert_eq!(
+            bungeecord_address(&handshake, "203.0.113.7".parse().unwrap(), uuid, None),
+            "play.example.com\x00203.0.113.7\x00b50ad385829d3141a2167e7d7539ba7f\x00\
+             [{\"name\":\"forgeClient\",\"signature\":null,\"value\":\"true\"},\
+             {\"name\":\"extraData\",\"signature\":\"\",\"value\":\"\\u0001FML2\\u0001\"}]"
+        );
+
+        let player_info =
+            velocity_player_info("secret", "203.0.113.7".parse().unwrap(), uuid, "Notch");
+        let (signature, payload) = player_info.split_at(32);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(payload);
+        mac.verify_slice(signature).unwrap();
+        assert_eq!(payload[0], 1);
+        assert_eq!(&payload[1..13], b"\x0b203.0.113.7");
+        assert_eq!(&payload[13..29], uuid.as_bytes());
+        assert_eq!(&payload[29..], b"\x05Notch\x00");
+    }
+}
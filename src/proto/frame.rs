@@ -0,0 +1,140 @@
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::proto::{
+    codec::{encode, Decode, Reader, VarInt},
+    error::ProtocolError,
+    packet::Packet,
+};
+
+/// Longest a frame's length prefix can be
+const MAX_HEADER_LENGTH: usize = 5;
+
+/// The first frame in a buffer, which may not have been received in full
+#[derive(Debug)]
+pub enum Frame {
+    /// The whole frame, which took up `length` bytes of the buffer
+    Complete { packet: Packet, length: usize },
+    /// Only part of the frame, which needs at least `needed` more bytes
+    Incomplete { needed: usize },
+}
+
+/// Decode the first frame in `buf` without consuming it
+///
+/// Nothing is read past the frame, so whatever follows it can be forwarded as is. The length
+/// prefix is checked against `max_length` before the rest of the frame has to arrive.
+pub fn peek_frame(buf: &[u8], max_length: usize) -> Result<Frame, ProtocolError> {
+    let mut reader = Reader::new(buf, 0);
+    let length = match VarInt::decode(&mut reader) {
+        Ok(VarInt(length)) => length,
+        Err(ProtocolError::Truncated) => return Ok(Frame::Incomplete { needed: 1 }),
+        Err(error) => return Err(error),
+    };
+    let header_length = buf.len() - reader.remaining();
+
+    let data_length = usize::try_from(length).map_err(|_| ProtocolError::InvalidLength(length))?;
+    if data_length > max_length {
+        return Err(ProtocolError::PacketTooLong {
+            length: data_length,
+            max: max_length,
+        });
+    }
+
+    if reader.remaining() < data_length {
+        return Ok(Frame::Incomplete {
+            needed: data_length - reader.remaining(),
+        });
+    }
+
+    let mut reader = Reader::new(reader.take(data_length)?, 0);
+    // Every packet has an id, so an empty frame is as invalid as any other too short for one
+    let id = VarInt::decode(&mut reader).map_err(|_| ProtocolError::InvalidLength(length))?;
+
+    Ok(Frame::Complete {
+        packet: Packet {
+            length,
+            id: id.0,
+            data: reader.take_remaining().to_vec(),
+        },
+        length: header_length + data_length,
+    })
+}
+
+/// Decode the first frame in `buf`, consuming it only if it was complete
+pub fn decode_frame(buf: &mut BytesMut, max_length: usize) -> Result<Frame, ProtocolError> {
+    let frame = peek_frame(buf, max_length)?;
+
+    if let Frame::Complete { length, .. } = frame {
+        buf.advance(length);
+    }
+
+    Ok(frame)
+}
+
+/// Append a frame holding a packet with the given id and data to `buf`
+pub fn encode_frame(buf: &mut BytesMut, id: i32, data: &[u8]) -> Packet {
+    let id_bytes = encode(&VarInt(id));
+    let length = (id_bytes.len() + data.len())
+        .try_into()
+        .expect("packets should be shorter than i32::MAX");
+
+    buf.reserve(MAX_HEADER_LENGTH + id_bytes.len() + data.len());
+    buf.put_slice(&encode(&VarInt(length)));
+    buf.put_slice(&id_bytes);
+    buf.put_slice(data);
+
+    Packet {
+        length,
+        id,
+        data: data.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+
+    use super::{decode_frame, encode_frame, Frame};
+    use crate::proto::error::ProtocolError;
+
+    #[test]
+    fn incomplete_frames() {
+        let mut frames = BytesMut::new();
+        encode_frame(&mut frames, 0x00, &[0xAB; 200]);
+        encode_frame(&mut frames, 0x01, b"ping");
+        let encoded = frames.clone();
+
+        // Fed a byte at a time, a frame is only taken once all of it is there
+        let mut buf = BytesMut::new();
+        let mut packets = Vec::new();
+        for byte in encoded.iter() {
+            buf.extend_from_slice(&[*byte]);
+
+            match decode_frame(&mut buf, 256).unwrap() {
+                Frame::Complete { packet, .. } => packets.push(packet),
+                Frame::Incomplete { needed } => assert!(needed >= 1 && !buf.is_empty()),
+            }
+        }
+
+        assert!(buf.is_empty());
+        assert_eq!(packets.len(), 2);
+        assert_eq!((packets[0].id, packets[0].data.len()), (0x00, 200));
+        assert_eq!(
+            (packets[1].id, packets[1].data.as_slice()),
+            (0x01, &b"ping"[..])
+        );
+
+        // The length is checked as soon as it arrives
+        let mut buf = BytesMut::from(&encoded[..2]);
+        assert!(matches!(
+            decode_frame(&mut buf, 100),
+            Err(ProtocolError::PacketTooLong {
+                length: 201,
+                max: 100
+            })
+        ));
+        assert_eq!(buf.len(), 2);
+
+        let mut buf = BytesMut::from(&[0x00][..]);
+        assert!(decode_frame(&mut buf, 100).is_err());
+    }
+}
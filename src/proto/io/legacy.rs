@@ -0,0 +1,224 @@
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing_error::{InstrumentError, InstrumentResult, TracedError};
+
+use super::parse_address;
+use crate::proto::{
+    codec::{Decode, Reader},
+    error::ProtocolError,
+    packet::{
+        legacy::{LegacyPing, LegacyPingFormat, LEGACY_KICK, LEGACY_PING},
+        response::StatusResponse,
+        ElaboratedTextComponent,
+    },
+};
+
+/// Read exactly `len` bytes, keeping a copy of them in `data`
+async fn read_recorded<'d>(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+    data: &'d mut Vec<u8>,
+    len: usize,
+) -> Result<&'d [u8], TracedError<io::Error>> {
+    let start = data.len();
+    data.resize(start + len, 0);
+    stream
+        .read_exact(&mut data[start..])
+        .await
+        .in_current_span()?;
+
+    Ok(&data[start..])
+}
+
+fn read_utf16(bytes: &[u8]) -> String {
+    char::decode_utf16(
+        bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+    )
+    .map(|char| char.unwrap_or(char::REPLACEMENT_CHARACTER))
+    .collect()
+}
+
+/// Read a legacy server list ping, including the leading `0xFE`
+///
+/// Clients send the whole ping at once without waiting for the server, so, like the vanilla
+/// server, the format is decided by how much of it arrives in the first read.
+#[tracing::instrument(skip_all, err)]
+pub async fn read_legacy_ping(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+    suffix_separators: &[String],
+) -> Result<LegacyPing, TracedError<io::Error>> {
+    let mut data = vec![0u8; 3];
+    let read = stream.read(&mut data).await.in_current_span()?;
+    data.truncate(read);
+
+    let format = match data.as_slice() {
+        [LEGACY_PING] => LegacyPingFormat::Beta,
+        [LEGACY_PING, 0x01] => LegacyPingFormat::V1_4,
+        [LEGACY_PING, 0x01, 0xFA] => LegacyPingFormat::V1_6,
+        _ => return Err(ProtocolError::MalformedLegacyPing.in_current_span()),
+    };
+
+    if format != LegacyPingFormat::V1_6 {
+        return Ok(LegacyPing {
+            format,
+            protocol_version: None,
+            address: None,
+            address_forge_version: None,
+            port: 0,
+            data,
+        });
+    }
+
+    // The MC|PingHost plugin message follows, with strings prefixed by their length in UTF-16 code units
+    let channel_length = read_recorded(stream, &mut data, 2).await?;
+    let channel_length = u16::from_be_bytes([channel_length[0], channel_length[1]]);
+    read_recorded(stream, &mut data, usize::from(channel_length) * 2).await?;
+
+    let payload_length = read_recorded(stream, &mut data, 2).await?;
+    let payload_length = u16::from_be_bytes([payload_length[0], payload_length[1]]);
+    let payload = read_recorded(stream, &mut data, usize::from(payload_length)).await?;
+
+    let (protocol_version, (address, address_forge_version), port) = (|| {
+        let mut payload = Reader::new(payload, 0);
+
+        let protocol_version = u8::decode(&mut payload)?;
+        let address_length = u16::decode(&mut payload)?;
+        let address = read_utf16(payload.take(usize::from(address_length) * 2)?);
+        let port = i32::decode(&mut payload)?;
+
+        // Routed like the address of a modern handshake
+        Ok((
+            protocol_version,
+            parse_address(&address, suffix_separators)?,
+            port,
+        ))
+    })()
+    .map_err(ProtocolError::in_current_span)?;
+
+    Ok(LegacyPing {
+        format,
+        protocol_version: Some(protocol_version),
+        address: Some(address),
+        address_forge_version,
+        port: port as u16,
+        data,
+    })
+}
+
+/// Answer a legacy server list ping with the given status, in the format the client expects
+#[tracing::instrument(skip(stream, response), err)]
+pub async fn write_legacy_status_response(
+    stream: &mut (dyn AsyncWrite + Unpin + Send),
+    format: LegacyPingFormat,
+    response: &StatusResponse,
+) -> Result<(), TracedError<io::Error>> {
+    let description = ElaboratedTextComponent::from_text_component(response.description.clone());
+    let (online, max) = response
+        .players
+        .as_ref()
+        .map_or((0, 0), |players| (players.online, players.max));
+
+    let response = match format {
+        // The fields are separated by `§`, so the description can not contain formatting codes
+        LegacyPingFormat::Beta => format!(
+            "{}§{online}§{max}",
+            ElaboratedTextComponent::plain_text(&description).replace('§', "")
+        ),
+        LegacyPingFormat::V1_4 | LegacyPingFormat::V1_6 => format!(
+            "§1\0{}\0{}\0{}\0{online}\0{max}",
+            response.version.protocol,
+            response.version.name,
+            ElaboratedTextComponent::encode_formatting_codes(&description)
+        ),
+    };
+
+    let response = response.encode_utf16().collect::<Vec<_>>();
+    let length = u16::try_from(response.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "legacy status response too long",
+        )
+        .in_current_span()
+    })?;
+
+    let mut packet = Vec::with_capacity(3 + response.len() * 2);
+    packet.push(LEGACY_KICK);
+    packet.extend_from_slice(&length.to_be_bytes());
+    packet.extend(response.into_iter().flat_map(u16::to_be_bytes));
+
+    stream
+        .write_all(&packet)
+        .await
+        .map_err(InstrumentError::in_current_span)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proto::packet::{
+        legacy::LegacyPingFormat,
+        response::{Players, StatusResponse, Version},
+        RawTextComponent,
+    };
+
+    use super::{read_legacy_ping, read_utf16, write_legacy_status_response};
+
+    fn utf16(string: &str) -> Vec<u8> {
+        string.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    #[tokio::test]
+    async fn read_v1_6_ping() {
+        let mut payload = vec![78];
+        payload.extend_from_slice(&31u16.to_be_bytes());
+        payload.extend(utf16("Play.Example.com///1.2.3.4\0FML\0"));
+        payload.extend_from_slice(&25565i32.to_be_bytes());
+
+        let mut ping = vec![0xFE, 0x01, 0xFA];
+        ping.extend_from_slice(&11u16.to_be_bytes());
+        ping.extend(utf16("MC|PingHost"));
+        ping.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        ping.extend(payload);
+
+        let legacy_ping = read_legacy_ping(&mut ping.as_slice(), &["///".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(legacy_ping.format, LegacyPingFormat::V1_6);
+        assert_eq!(legacy_ping.protocol_version, Some(78));
+        assert_eq!(legacy_ping.address.unwrap().as_ref(), "play.example.com");
+        assert_eq!(legacy_ping.address_forge_version.as_deref(), Some("FML"));
+        assert_eq!(legacy_ping.port, 25565);
+        assert_eq!(legacy_ping.data, ping);
+    }
+
+    #[tokio::test]
+    async fn write_v1_4_response() {
+        let response = StatusResponse {
+            version: Version {
+                name: "1.21".into(),
+                protocol: 767,
+            },
+            players: Some(Players {
+                max: 20,
+                online: 3,
+                sample: vec![],
+            }),
+            description: serde_json::from_str::<RawTextComponent>(
+                r#"[{"text": "Hello", "color": "red"}, {"text": " world", "bold": true}]"#,
+            )
+            .unwrap(),
+            favicon: None,
+        };
+
+        let mut output = Vec::new();
+        write_legacy_status_response(&mut output, LegacyPingFormat::V1_4, &response)
+            .await
+            .unwrap();
+
+        assert_eq!(output[0], 0xFF);
+        assert_eq!(
+            read_utf16(&output[3..]),
+            "§1\x00767\x001.21\x00§cHello§r§c§l world\x003\x0020"
+        );
+    }
+}
@@ -1,20 +1,34 @@
+use bytes::BytesMut;
 use mcproxy_model::Hostname;
 use smol_str::SmolStr;
-use std::convert::TryInto;
-use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tracing::Span;
+use std::time::Duration;
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time::sleep,
+};
 use tracing_error::{InstrumentResult, TracedError};
 
-use crate::proto::{packet::NextState, string};
-
-use super::{
-    packet::{response::StatusResponse, Handshake, Packet},
-    var_int,
+use crate::proto::{
+    codec::{encode, Decode, Encode, Json, PacketId, Reader, VarInt},
+    error::ProtocolError,
+    frame::{decode_frame, encode_frame, peek_frame, Frame},
+    limits::ProtocolLimits,
+    packet::{handshaking, login, status, NextState},
+    string,
 };
 
+use super::packet::{response::StatusResponse, Handshake, Packet};
+
+pub mod legacy;
 pub mod request;
 pub mod response;
 
+/// Enough for the Login Start of any vanilla client
+const PEEK_BUFFER_LENGTH: usize = 128;
+/// How long to wait for the rest of a packet that was peeked in part
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
 /// Write a packet and output its data
 #[tracing::instrument(skip(stream, data), fields(len=data.len()))]
 pub async fn write_packet(
@@ -22,155 +36,232 @@ pub async fn write_packet(
     id: i32,
     data: &[u8],
 ) -> Result<Packet, TracedError<io::Error>> {
-    let ser_id = var_int::write(id);
-    let length = (data.len() + ser_id.len()).try_into().unwrap();
+    let mut buf = BytesMut::new();
+    let packet = encode_frame(&mut buf, id, data);
 
-    stream
-        .write_all(&var_int::write(length))
-        .await
-        .in_current_span()?;
-    stream.write_all(&ser_id).await.in_current_span()?;
-    stream.write_all(data).await.in_current_span()?;
+    stream.write_all(&buf).await.in_current_span()?;
 
-    Ok(Packet {
-        id,
-        length,
-        data: Vec::from(data),
-    })
+    Ok(packet)
 }
 
-#[tracing::instrument(skip(stream, vectors), fields(len=tracing::field::Empty))]
-pub async fn write_packet_vectored(
+/// Encode a packet and write it with its id
+pub async fn write_typed_packet<P: PacketId + Encode>(
     stream: &mut (dyn AsyncWrite + Unpin + Send),
-    id: i32,
-    vectors: &[&[u8]],
+    packet: &P,
 ) -> Result<Packet, TracedError<io::Error>> {
-    let data_length: usize = vectors.iter().map(|v| v.len()).sum();
-    Span::current().record("len", data_length);
-
-    let ser_id = var_int::write(id);
-    let length = (data_length + ser_id.len()).try_into().unwrap();
-
-    stream.write_all(&var_int::write(length)).await?;
-    stream.write_all(&ser_id).await?;
-    for vector in vectors {
-        stream.write_all(vector).await?;
-    }
-
-    Ok(Packet {
-        id,
-        length,
-        data: Vec::new(), // FIXME: why are we re-allocating??
-    })
+    write_packet(stream, P::ID, &encode(packet)).await
 }
 
-/// Read a packet and output its data
+/// Read a packet of at most `max_length` bytes and output its data
+///
+/// Only the bytes of the packet are read, anything after it is left in the stream.
 #[tracing::instrument(skip(stream))]
 pub async fn read_packet(
     stream: &mut (dyn AsyncRead + Unpin + Send),
+    max_length: usize,
 ) -> Result<Packet, TracedError<io::Error>> {
-    let length = var_int::read(stream).await?.value;
-    let id = var_int::read(stream).await?;
+    let mut buf = BytesMut::new();
+
+    loop {
+        match decode_frame(&mut buf, max_length).map_err(ProtocolError::in_current_span)? {
+            Frame::Complete { packet, .. } => return Ok(packet),
+            Frame::Incomplete { needed } => {
+                let start = buf.len();
+                buf.resize(start + needed, 0);
+                stream
+                    .read_exact(&mut buf[start..])
+                    .await
+                    .in_current_span()?;
+            }
+        }
+    }
+}
 
-    let mut data = vec![0u8; (length - id.length).try_into().unwrap()];
-    stream.read_exact(&mut data).await.in_current_span()?;
+/// Decode the data of a packet, which must have the packet's id and no data left over
+pub fn decode_packet<P: PacketId + Decode>(
+    packet: &Packet,
+    max_string_length: usize,
+) -> Result<P, TracedError<io::Error>> {
+    expect_packet(packet, P::ID)?;
 
-    Ok(Packet {
-        length,
-        id: id.value,
-        data,
-    })
+    let mut reader = Reader::new(&packet.data, max_string_length);
+    let decoded = P::decode(&mut reader).map_err(ProtocolError::in_current_span)?;
+    reader.finish().map_err(ProtocolError::in_current_span)?;
+
+    Ok(decoded)
+}
+
+/// Read a packet of at most `max_length` bytes and decode it
+pub async fn read_typed_packet<P: PacketId + Decode>(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+    max_length: usize,
+    max_string_length: usize,
+) -> Result<P, TracedError<io::Error>> {
+    let packet = read_packet(stream, max_length).await?;
+
+    decode_packet(&packet, max_string_length)
 }
 
 /// Read the handshake packet in and return the data from it
-#[tracing::instrument(skip(stream))]
+///
+/// Anything following one of the `suffix_separators` in the address is ignored
+#[tracing::instrument(skip(stream, limits))]
 pub async fn read_handshake(
     stream: &mut (dyn AsyncRead + Unpin + Send),
+    suffix_separators: &[String],
+    limits: &ProtocolLimits,
 ) -> Result<(Handshake, Packet), TracedError<io::Error>> {
-    let packet = read_packet(stream).await?;
+    let packet = read_packet(stream, limits.handshake_packet_length).await?;
+    let handshake: handshaking::serverbound::Handshake =
+        decode_packet(&packet, limits.string_length)?;
 
-    assert_eq!(packet.id, 0x00); // FIXME: should I panic?
-
-    let mut data_buf = packet.data.as_slice();
-
-    // Get the protocol version
-    let protocol_version = var_int::read(&mut data_buf).await?.value;
-    let address = string::read(&mut data_buf).await?;
-    let port = data_buf.read_u16().await.in_current_span()?;
-    let next_state = var_int::read(&mut data_buf).await?.value;
-
-    let mut parts = address.split_terminator('\0');
-    let address = parts.next().expect("first part should always exist");
-    let address_forge = parts.next(); // https://wiki.vg/Minecraft_Forge_Handshake#Changes_to_Handshake_packet
-    assert_eq!(parts.next(), None);
+    let (address, address_forge_version) = parse_address(&handshake.address, suffix_separators)
+        .map_err(ProtocolError::in_current_span)?;
 
     Ok((
         Handshake {
-            protocol_version,
-            address: Hostname::from(address),
-            address_forge_version: address_forge.map(SmolStr::from),
-            port,
-            next_state: NextState::from(next_state),
+            protocol_version: handshake.protocol_version.0,
+            address,
+            address_forge_version,
+            port: handshake.port,
+            next_state: NextState::from(handshake.next_state.0),
         },
         packet,
     ))
 }
 
+/// Split the Forge version off the address a client connected to, and strip everything after
+/// any of the suffix separators
+pub fn parse_address(
+    address: &str,
+    suffix_separators: &[String],
+) -> Result<(Hostname, Option<SmolStr>), ProtocolError> {
+    let mut parts = address.split_terminator('\0');
+    // An empty address has no parts at all
+    let address = parts.next().unwrap_or_default();
+    let address_forge = parts.next(); // https://wiki.vg/Minecraft_Forge_Handshake#Changes_to_Handshake_packet
+    if parts.next().is_some() {
+        return Err(ProtocolError::MalformedAddress);
+    }
+
+    let address = suffix_separators
+        .iter()
+        .fold(address, |address, separator| {
+            address
+                .split_once(separator.as_str())
+                .map_or(address, |(address, _suffix)| address)
+        });
+
+    Ok((Hostname::from(address), address_forge.map(SmolStr::from)))
+}
+
+/// Re-encode a handshake packet with a different next state, keeping everything else as the
+/// client sent it
+pub fn with_next_state(
+    packet: &Packet,
+    next_state: NextState,
+    limits: &ProtocolLimits,
+) -> Result<Packet, TracedError<io::Error>> {
+    let handshake = handshaking::serverbound::Handshake {
+        next_state: VarInt(next_state.into()),
+        ..decode_packet(packet, limits.string_length)?
+    };
+
+    Ok(encode_frame(
+        &mut BytesMut::new(),
+        packet.id,
+        &encode(&handshake),
+    ))
+}
+
 #[tracing::instrument(skip(stream))]
 pub async fn write_handshake(
     stream: &mut (dyn AsyncWrite + Unpin + Send),
     handshake: Handshake,
 ) -> Result<Packet, TracedError<io::Error>> {
-    let address = if let Some(forge_version) = handshake.address_forge_version {
-        &[handshake.address.as_ref(), "\0", &forge_version, "\0"].concat()
+    let address = if let Some(forge_version) = &handshake.address_forge_version {
+        &[handshake.address.as_ref(), "\0", forge_version, "\0"].concat()
     } else {
         handshake.address.as_ref()
     };
 
-    let protocol_version = var_int::write(handshake.protocol_version);
-    let address = string::write(address);
-    let port = u16::to_be_bytes(handshake.port);
-    let next_state = var_int::write(handshake.next_state.into());
+    write_handshake_with_address(stream, &handshake, address).await
+}
 
-    write_packet_vectored(
+/// Write a handshake packet with the address field replaced verbatim
+///
+/// Used for addresses which are not hostnames, such as those carrying BungeeCord forwarding
+#[tracing::instrument(skip(stream, address))]
+pub async fn write_handshake_with_address(
+    stream: &mut (dyn AsyncWrite + Unpin + Send),
+    handshake: &Handshake,
+    address: &str,
+) -> Result<Packet, TracedError<io::Error>> {
+    write_typed_packet(
         stream,
-        0x00,
-        &[&protocol_version, &address, &port[..], &next_state],
+        &handshaking::serverbound::Handshake {
+            protocol_version: VarInt(handshake.protocol_version),
+            address: address.to_string(),
+            port: handshake.port,
+            next_state: VarInt(handshake.next_state.into()),
+        },
     )
     .await
 }
 
-#[tracing::instrument(skip(stream))]
-pub async fn read_status_request(
+/// Read the Login Start packet, returning it along with the packet it was decoded from
+#[tracing::instrument(skip(stream, limits))]
+pub async fn read_login_start(
     stream: &mut (dyn AsyncRead + Unpin + Send),
-) -> Result<(), TracedError<io::Error>> {
-    let packet = read_packet(stream).await?;
-
-    assert_eq!(packet.id, 0x00, "unexpected packet id");
-    assert_eq!(packet.length, 1, "unexpected packet length");
+    limits: &ProtocolLimits,
+) -> Result<(login::serverbound::LoginStart, Packet), TracedError<io::Error>> {
+    let packet = read_packet(stream, limits.login_packet_length).await?;
+    let login_start = decode_packet(&packet, limits.string_length)?;
 
-    Ok(())
+    Ok((login_start, packet))
 }
 
-#[tracing::instrument(skip(stream))]
-pub async fn write_status_request(
-    stream: &mut (dyn AsyncWrite + Unpin + Send),
-) -> Result<Packet, TracedError<io::Error>> {
-    write_packet(stream, 0x00, &[]).await
+/// Decode the Login Start packet without consuming it, leaving it for whoever reads the stream
+/// next, such as the upstream it is proxied to
+///
+/// Keeps waiting for the rest of the packet, so callers should bound it with a timeout.
+#[tracing::instrument(skip_all)]
+pub async fn peek_login_start(
+    stream: &TcpStream,
+    limits: &ProtocolLimits,
+) -> Result<login::serverbound::LoginStart, TracedError<io::Error>> {
+    let mut buf = vec![0; PEEK_BUFFER_LENGTH];
+
+    loop {
+        let peeked = stream.peek(&mut buf).await.in_current_span()?;
+        if peeked == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof)).in_current_span();
+        }
+
+        match peek_frame(&buf[..peeked], limits.login_packet_length)
+            .map_err(ProtocolError::in_current_span)?
+        {
+            Frame::Complete { packet, .. } => {
+                return decode_packet(&packet, limits.string_length);
+            }
+            // Peeking only shows as much as fits the buffer
+            Frame::Incomplete { needed } if peeked == buf.len() => {
+                buf.resize(peeked + needed, 0);
+            }
+            // Peeking again returns right away until more arrives, so wait for it a little
+            Frame::Incomplete { .. } => sleep(PEEK_INTERVAL).await,
+        }
+    }
 }
 
 #[tracing::instrument(skip(stream))]
 pub async fn read_status_response(
     stream: &mut (dyn AsyncRead + Unpin + Send),
+    max_length: usize,
 ) -> Result<StatusResponse, TracedError<io::Error>> {
-    let packet = read_packet(stream).await?;
-
-    assert_eq!(packet.id, 0x00);
-
-    let mut data_buf = packet.data.as_slice();
-
-    let response = string::read(&mut data_buf).await?;
-    let response = serde_json::from_str(&response).unwrap();
+    let status::clientbound::StatusResponse {
+        response: Json(response),
+    } = read_typed_packet(stream, max_length, string::MAX_LENGTH).await?;
 
     Ok(response)
 }
@@ -180,28 +271,129 @@ pub async fn write_status_response(
     stream: &mut (dyn AsyncWrite + Unpin + Send),
     response: &StatusResponse,
 ) -> Result<Packet, TracedError<io::Error>> {
-    let response = string::write(&serde_json::to_string(response).unwrap());
+    write_typed_packet(
+        stream,
+        &status::clientbound::StatusResponse {
+            response: Json(response.clone()),
+        },
+    )
+    .await
+}
 
-    write_packet(stream, 0x00, &response).await
+/// Fail unless the packet has the id the current state expects
+fn expect_packet(packet: &Packet, expected: i32) -> Result<(), TracedError<io::Error>> {
+    match packet.id == expected {
+        true => Ok(()),
+        false => Err(ProtocolError::UnexpectedPacket {
+            expected,
+            id: packet.id,
+        }
+        .in_current_span()),
+    }
 }
 
-#[tracing::instrument(skip(stream), err)]
-pub async fn read_ping_pong(
-    stream: &mut (dyn AsyncRead + Unpin + Send),
-) -> Result<i64, TracedError<io::Error>> {
-    let packet = read_packet(stream).await?;
-    assert_eq!(packet.id, 0x01);
-    let mut data_buf = packet.data.as_slice();
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
 
-    let payload = data_buf.read_i64().await.in_current_span()?;
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        time::sleep,
+    };
 
-    Ok(payload)
-}
+    use crate::proto::{limits::ProtocolLimits, string, var_int};
 
-#[tracing::instrument(skip(stream), err)]
-pub async fn write_ping_pong(
-    stream: &mut (dyn AsyncWrite + Unpin + Send),
-    payload: i64,
-) -> Result<Packet, TracedError<io::Error>> {
-    write_packet(stream, 0x01, &payload.to_be_bytes()).await
+    use super::{peek_login_start, read_handshake, read_login_start, read_packet};
+
+    fn handshake_packet(address: &str) -> Vec<u8> {
+        let mut data = vec![0x00];
+        data.extend(var_int::write(767));
+        data.extend(string::write(address));
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        data.extend(var_int::write(1));
+
+        let mut packet = var_int::write(data.len() as i32);
+        packet.extend(data);
+        packet
+    }
+
+    #[tokio::test]
+    async fn reject_malicious_packets() {
+        let limits = ProtocolLimits::default();
+
+        let (handshake, _) = read_handshake(&mut handshake_packet("").as_slice(), &[], &limits)
+            .await
+            .unwrap();
+        assert_eq!(handshake.address.as_ref(), "");
+
+        // A length prefix of 2 GiB, with nothing behind it
+        let mut huge = &var_int::write(i32::MAX)[..];
+        assert!(read_packet(&mut huge, limits.login_packet_length)
+            .await
+            .is_err());
+
+        let mut negative = &var_int::write(-1)[..];
+        assert!(read_packet(&mut negative, limits.login_packet_length)
+            .await
+            .is_err());
+
+        let mut overlong = &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01][..];
+        assert!(read_packet(&mut overlong, limits.login_packet_length)
+            .await
+            .is_err());
+
+        // A status request where Login Start is expected
+        let mut status_request = &[0x01, 0x01][..];
+        assert!(read_login_start(&mut status_request, &limits)
+            .await
+            .is_err());
+
+        assert!(
+            read_handshake(&mut handshake_packet("a\0b\0c\0d").as_slice(), &[], &limits)
+                .await
+                .is_err()
+        );
+
+        // Hostnames are limited to 255 characters by vanilla
+        let limits = ProtocolLimits {
+            string_length: 255,
+            ..limits
+        };
+        let long_address = "a".repeat(256);
+        assert!(read_handshake(
+            &mut handshake_packet(&long_address).as_slice(),
+            &[],
+            &limits
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn peek_login_start_in_parts() {
+        let limits = ProtocolLimits::default();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let mut data = vec![0x00];
+        data.extend(string::write("Notch"));
+        let mut packet = var_int::write(data.len() as i32);
+        packet.extend(data);
+
+        client.write_all(&packet[..3]).await.unwrap();
+        let (login_start, ()) = tokio::join!(peek_login_start(&server, &limits), async {
+            sleep(Duration::from_millis(50)).await;
+            client.write_all(&packet[3..]).await.unwrap();
+        });
+        assert_eq!(login_start.unwrap().name, "Notch");
+
+        // Peeking left the packet for the upstream
+        let (login_start, _) = read_login_start(&mut server, &limits).await.unwrap();
+        assert_eq!(login_start.name, "Notch");
+    }
 }
@@ -10,17 +10,21 @@ use tokio::{
 };
 use tracing_error::{InstrumentError, TracedError};
 
-use crate::proto::packet::{response::StatusResponse, Handshake, NextState};
-
-use super::{
-    read_ping_pong, read_status_response, write_handshake, write_ping_pong, write_status_request,
+use crate::proto::{
+    limits::ProtocolLimits,
+    packet::{response::StatusResponse, status, Handshake, NextState},
+    proxy_protocol::{write_header, ProxyProtocolVersion},
+    string,
 };
 
-#[tracing::instrument(skip(client_stream))]
+use super::{read_status_response, read_typed_packet, write_handshake, write_typed_packet};
+
+#[tracing::instrument(skip(client_stream, limits))]
 // TODO: provide context in error where the problem occurred....
 pub async fn server_list_ping(
     mut client_stream: TcpStream,
     upstream: Upstream,
+    limits: &ProtocolLimits,
 ) -> Result<(Duration, StatusResponse), TracedError<io::Error>> {
     let (read, write) = client_stream.split();
     let (mut read, mut write) = (BufReader::new(read), BufWriter::new(write));
@@ -39,24 +43,32 @@ pub async fn server_list_ping(
     .await?;
 
     // The client follows up with a Status Request packet. This packet has no fields. The client is also able to skip this part entirely and send a Ping Request instead.
-    write_status_request(&mut write).await?;
+    write_typed_packet(&mut write, &status::serverbound::StatusRequest {}).await?;
 
     // If the process is continued, the client will now send a Ping Request packet containing some payload which is not important.
     let sent_payload = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap_or(Duration::from_secs(42))
         .as_secs() as i64;
-    write_ping_pong(&mut write, sent_payload).await?;
+    write_typed_packet(
+        &mut write,
+        &status::serverbound::PingRequest {
+            payload: sent_payload,
+        },
+    )
+    .await?;
     let ping_sent = Instant::now();
 
     // Send written packets
     write.flush().await?;
 
     // The server should respond with a Status Response packet.
-    let response = read_status_response(&mut read).await?;
+    let response = read_status_response(&mut read, limits.upstream_packet_length).await?;
 
     // The server will respond with the Pong Response packet and then close the connection.
-    let received_payload = read_ping_pong(&mut read).await?;
+    let status::clientbound::PongResponse {
+        payload: received_payload,
+    } = read_typed_packet(&mut read, limits.upstream_packet_length, string::MAX_LENGTH).await?;
     let ping = ping_sent.elapsed();
 
     if sent_payload != received_payload {
@@ -71,3 +83,28 @@ pub async fn server_list_ping(
 
     Ok((ping, response))
 }
+
+/// Ping the upstream, introducing the proxy itself as the client if it expects a PROXY header
+#[tracing::instrument(skip(limits))]
+pub async fn ping_upstream(
+    upstream: Upstream,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    limits: &ProtocolLimits,
+) -> Result<(Duration, StatusResponse), TracedError<io::Error>> {
+    let mut stream = TcpStream::connect(upstream.addr())
+        .await
+        .map_err(InstrumentError::in_current_span)?;
+
+    if let Some(version) = proxy_protocol {
+        let source = stream
+            .local_addr()
+            .map_err(InstrumentError::in_current_span)?;
+        let destination = stream
+            .peer_addr()
+            .map_err(InstrumentError::in_current_span)?;
+
+        write_header(&mut stream, version, source, destination).await?;
+    }
+
+    server_list_ping(stream, upstream, limits).await
+}
@@ -1,26 +1,40 @@
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt, BufStream},
+    io::{self, AsyncRead, AsyncWriteExt, BufReader, BufStream, BufWriter},
     net::TcpStream,
+    time::{interval, timeout},
 };
+use tracing::{debug, trace};
 use tracing_error::{InstrumentError, TracedError};
 
 use crate::proto::{
-    io::write_packet,
-    packet::{response::StatusResponse, RawTextComponent},
-    string,
+    codec::{Decode, Json, Nbt, PacketId, VarInt},
+    io::{decode_packet, legacy::write_legacy_status_response, write_typed_packet},
+    limits::ProtocolLimits,
+    packet::{
+        legacy::LegacyPingFormat,
+        limbo::{self, clientbound, serverbound},
+        login,
+        response::StatusResponse,
+        status, RawTextComponent,
+    },
 };
 
-use super::{
-    read_packet, read_ping_pong, read_status_request, write_ping_pong, write_status_response,
-};
+use super::{read_packet, read_typed_packet, write_status_response};
 
 #[tracing::instrument(skip_all)]
 pub async fn ping_response(
     stream: &mut TcpStream,
     response: Option<&StatusResponse>,
+    limits: &ProtocolLimits,
 ) -> Result<(), TracedError<io::Error>> {
     // The client follows up with a Status Request packet. This packet has no fields. The client is also able to skip this part entirely and send a Ping Request instead.
-    read_status_request(stream).await?;
+    let status::serverbound::StatusRequest {} =
+        read_typed_packet(stream, limits.status_packet_length, limits.string_length).await?;
 
     if let Some(response) = response {
         // The server should respond with a Status Response packet.
@@ -28,9 +42,29 @@ pub async fn ping_response(
     }
 
     // If the process is continued, the client will now send a Ping Request packet containing some payload which is not important.
-    let payload = read_ping_pong(stream).await?;
+    let status::serverbound::PingRequest { payload } =
+        read_typed_packet(stream, limits.status_packet_length, limits.string_length).await?;
     // The server will respond with the Pong Response packet and then close the connection.
-    write_ping_pong(stream, payload).await?;
+    write_typed_packet(stream, &status::clientbound::PongResponse { payload }).await?;
+
+    stream
+        .shutdown()
+        .await
+        .map_err(InstrumentError::in_current_span)?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn legacy_ping_response(
+    stream: &mut TcpStream,
+    format: LegacyPingFormat,
+    response: Option<&StatusResponse>,
+) -> Result<(), TracedError<io::Error>> {
+    // Legacy clients only receive a status, there is no ping to answer afterwards
+    if let Some(response) = response {
+        write_legacy_status_response(stream, format, response).await?;
+    }
 
     stream
         .shutdown()
@@ -44,26 +78,25 @@ pub async fn ping_response(
 pub async fn login_response(
     stream: TcpStream,
     response: Option<&RawTextComponent>,
+    limits: &ProtocolLimits,
 ) -> Result<(), TracedError<io::Error>> {
     let mut stream = BufStream::new(stream);
 
-    // TODO: put this in a struct
-    let packet = read_packet(&mut stream).await?;
-    assert_eq!(packet.id, 0x00);
-
-    let mut data_buf = packet.data.as_slice();
-    // TODO: no need for these to be async
-    let name = string::read(&mut data_buf).await?;
-    let uuid = data_buf.read_u128().await?;
+    let login::serverbound::LoginStart { name, uuid } = read_typed_packet(
+        &mut stream,
+        limits.login_packet_length,
+        limits.string_length,
+    )
+    .await?;
 
-    println!("{name}: {uuid:x?}");
+    debug!(name, ?uuid, "login refused");
 
     if let Some(response) = response {
-        // TODO: I can totally mechanize the construction of packets, maybe look into that?
-        write_packet(
+        write_typed_packet(
             &mut stream,
-            0x00,
-            &string::write(&serde_json::to_string(&response).unwrap()),
+            &login::clientbound::Disconnect {
+                reason: Json(response.clone()),
+            },
         )
         .await?;
     }
@@ -75,3 +108,224 @@ pub async fn login_response(
 
     Ok(())
 }
+
+/// Disconnect a client that is logging in, without waiting for its Login Start
+#[tracing::instrument(skip_all)]
+pub async fn login_disconnect(
+    mut stream: TcpStream,
+    reason: &RawTextComponent,
+) -> Result<(), TracedError<io::Error>> {
+    write_typed_packet(
+        &mut stream,
+        &login::clientbound::Disconnect {
+            reason: Json(reason.clone()),
+        },
+    )
+    .await?;
+
+    stream
+        .shutdown()
+        .await
+        .map_err(InstrumentError::in_current_span)?;
+
+    Ok(())
+}
+
+/// How long the client gets to finish logging in and configuring in limbo
+const LIMBO_JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the client is sent a keep alive in limbo, well within its 30 second timeout
+const LIMBO_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Let the client join a void world, and transfer it once `upstream_ready` finishes
+///
+/// The client is disconnected with the reason `upstream_ready` fails with instead, or with
+/// `message` if it can not load the world. Only versions in [`limbo::PROTOCOL_VERSIONS`]
+/// are supported.
+#[tracing::instrument(skip_all, fields(protocol_version))]
+pub async fn limbo_response(
+    stream: TcpStream,
+    protocol_version: i32,
+    message: &RawTextComponent,
+    transfer_to: (&str, u16),
+    upstream_ready: impl Future<Output = Result<(), RawTextComponent>>,
+    limits: &ProtocolLimits,
+) -> Result<(), TracedError<io::Error>> {
+    let (max_length, max_string_length) = (limits.login_packet_length, limits.string_length);
+    let (reader, writer) = stream.into_split();
+    let (mut reader, mut writer) = (BufReader::new(reader), BufWriter::new(writer));
+
+    let joined = timeout(LIMBO_JOIN_TIMEOUT, async {
+        // Login
+        let login::serverbound::LoginStart { name, uuid } =
+            read_packet_with_id(&mut reader, max_length, max_string_length).await?;
+        let uuid = uuid.unwrap_or_default();
+        debug!(name, %uuid, "holding player in limbo");
+
+        write_typed_packet(
+            &mut writer,
+            &login::clientbound::LoginSuccess {
+                uuid,
+                name,
+                // No profile properties
+                properties: Vec::new(),
+                strict_error_handling: false,
+            },
+        )
+        .await?;
+        writer
+            .flush()
+            .await
+            .map_err(InstrumentError::in_current_span)?;
+        let login::serverbound::LoginAcknowledged {} =
+            read_packet_with_id(&mut reader, max_length, max_string_length).await?;
+
+        // Configuration
+        write_typed_packet(&mut writer, &limbo::known_packs(protocol_version)).await?;
+        writer
+            .flush()
+            .await
+            .map_err(InstrumentError::in_current_span)?;
+        let known_packs: serverbound::ConfigurationKnownPacks =
+            read_packet_with_id(&mut reader, max_length, max_string_length).await?;
+
+        // Registry entries are only named, so without the vanilla pack there is nothing to load
+        if !limbo::has_core_pack(&known_packs) {
+            debug!("client does not know the vanilla data pack");
+
+            write_typed_packet(
+                &mut writer,
+                &clientbound::ConfigurationDisconnect {
+                    reason: Nbt(message.clone()),
+                },
+            )
+            .await?;
+            writer
+                .flush()
+                .await
+                .map_err(InstrumentError::in_current_span)?;
+
+            return Ok(false);
+        }
+
+        for (registry, entries) in limbo::registries(protocol_version) {
+            write_typed_packet(&mut writer, &limbo::registry_data(registry, entries)).await?;
+        }
+        write_typed_packet(&mut writer, &limbo::feature_flags()).await?;
+        write_typed_packet(&mut writer, &clientbound::FinishConfiguration {}).await?;
+        writer
+            .flush()
+            .await
+            .map_err(InstrumentError::in_current_span)?;
+        let serverbound::AcknowledgeFinishConfiguration {} =
+            read_packet_with_id(&mut reader, max_length, max_string_length).await?;
+
+        // Play
+        write_typed_packet(&mut writer, &limbo::login_play()).await?;
+        write_typed_packet(&mut writer, &limbo::game_event_start_waiting()).await?;
+        write_typed_packet(&mut writer, &limbo::synchronize_player_position()).await?;
+        write_typed_packet(
+            &mut writer,
+            &clientbound::SystemChatMessage {
+                content: Nbt(message.clone()),
+                overlay: false,
+            },
+        )
+        .await?;
+        writer
+            .flush()
+            .await
+            .map_err(InstrumentError::in_current_span)?;
+
+        Ok::<_, TracedError<io::Error>>(true)
+    })
+    .await;
+
+    match joined {
+        Ok(Ok(true)) => trace!("player joined limbo"),
+        Ok(Ok(false)) => return Ok(()),
+        Ok(Err(error)) => return Err(error),
+        Err(_) => {
+            debug!("timeout exceeded joining limbo");
+            return Ok(());
+        }
+    }
+
+    // Nothing the client sends matters, but it has to be read for the client to keep sending
+    let ignore_client = async {
+        loop {
+            read_packet(&mut reader, max_length).await?;
+        }
+    };
+    tokio::pin!(ignore_client, upstream_ready);
+
+    let mut keep_alive = interval(LIMBO_KEEP_ALIVE_INTERVAL);
+
+    let result: Result<(), TracedError<io::Error>> = loop {
+        tokio::select! {
+            result = &mut ignore_client => {
+                debug!("player left limbo");
+                break result;
+            }
+            _ = keep_alive.tick() => {
+                let id = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_millis() as i64);
+
+                write_typed_packet(&mut writer, &clientbound::KeepAlive { id }).await?;
+                writer
+                    .flush()
+                    .await
+                    .map_err(InstrumentError::in_current_span)?;
+            }
+            ready = &mut upstream_ready => {
+                match ready {
+                    Ok(()) => {
+                        let (host, port) = transfer_to;
+                        debug!(host, port, "transferring player out of limbo");
+
+                        let transfer = clientbound::Transfer {
+                            host: host.to_string(),
+                            port: VarInt(port.into()),
+                        };
+                        write_typed_packet(&mut writer, &transfer).await?;
+                    }
+                    Err(reason) => {
+                        debug!("giving up on upstream");
+
+                        let disconnect = clientbound::PlayDisconnect {
+                            reason: Nbt(reason),
+                        };
+                        write_typed_packet(&mut writer, &disconnect).await?;
+                    }
+                }
+
+                break Ok(());
+            }
+        }
+    };
+    result?;
+
+    writer
+        .shutdown()
+        .await
+        .map_err(InstrumentError::in_current_span)?;
+
+    Ok(())
+}
+
+/// Read packets until one with the id of `P`, skipping the ones limbo does not care about
+async fn read_packet_with_id<P: PacketId + Decode>(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+    max_length: usize,
+    max_string_length: usize,
+) -> Result<P, TracedError<io::Error>> {
+    loop {
+        let packet = read_packet(stream, max_length).await?;
+
+        if packet.id == P::ID {
+            return decode_packet(&packet, max_string_length);
+        }
+
+        trace!(id = packet.id, "ignoring packet");
+    }
+}
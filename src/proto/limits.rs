@@ -0,0 +1,36 @@
+use serde::Deserialize;
+
+use crate::proto::string;
+
+/// How much a peer may send before it is disconnected
+///
+/// Packet lengths are in bytes, string lengths in characters. Clients get a limit for each state
+/// the proxy reads packets in, everything after that is proxied without being looked at.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ProtocolLimits {
+    /// Longest packet a client may send while handshaking
+    pub handshake_packet_length: usize,
+    /// Longest packet a client may send while asking for the server status
+    pub status_packet_length: usize,
+    /// Longest packet a client may send while logging in, or while held in limbo
+    pub login_packet_length: usize,
+    /// Longest packet accepted from an upstream
+    pub upstream_packet_length: usize,
+    /// Longest string a client may send
+    pub string_length: usize,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        ProtocolLimits {
+            handshake_packet_length: 4096,
+            status_packet_length: 256,
+            login_packet_length: 65536,
+            // Largest length a three byte VarInt can hold, like vanilla
+            upstream_packet_length: 2097151,
+            string_length: string::MAX_LENGTH,
+        }
+    }
+}
@@ -1,4 +1,11 @@
+pub mod codec;
+pub mod error;
+pub mod forwarding;
+pub mod frame;
 pub mod io;
+pub mod limits;
+pub mod nbt;
 pub mod packet;
+pub mod proxy_protocol;
 pub mod string;
 pub mod var_int;
@@ -0,0 +1,430 @@
+use serde_json::{Map, Number, Value};
+
+use crate::proto::{
+    codec::{Decode, Encode, Reader},
+    error::ProtocolError,
+    packet::RawTextComponent,
+};
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// How deeply lists and compounds may nest, like vanilla allows
+const MAX_DEPTH: usize = 512;
+
+/// A tag of network NBT, which replaced JSON in play packets in 1.20.3
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// Elements which must all be the same type of tag
+    List(Vec<Tag>),
+    /// Named tags, in the order they are written
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => TAG_BYTE,
+            Tag::Short(_) => TAG_SHORT,
+            Tag::Int(_) => TAG_INT,
+            Tag::Long(_) => TAG_LONG,
+            Tag::Float(_) => TAG_FLOAT,
+            Tag::Double(_) => TAG_DOUBLE,
+            Tag::ByteArray(_) => TAG_BYTE_ARRAY,
+            Tag::String(_) => TAG_STRING,
+            Tag::List(_) => TAG_LIST,
+            Tag::Compound(_) => TAG_COMPOUND,
+            Tag::IntArray(_) => TAG_INT_ARRAY,
+            Tag::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+
+    fn encode_payload(&self, buf: &mut Vec<u8>) {
+        match self {
+            Tag::Byte(value) => value.encode(buf),
+            Tag::Short(value) => value.encode(buf),
+            Tag::Int(value) => value.encode(buf),
+            Tag::Long(value) => value.encode(buf),
+            Tag::Float(value) => value.encode(buf),
+            Tag::Double(value) => value.encode(buf),
+            Tag::ByteArray(values) => encode_array(buf, values),
+            Tag::String(string) => encode_string(buf, string),
+            Tag::List(elements) => {
+                buf.push(elements.first().map_or(TAG_END, Tag::id));
+                encode_length(buf, elements.len());
+
+                for element in elements {
+                    element.encode_payload(buf);
+                }
+            }
+            Tag::Compound(entries) => {
+                for (name, tag) in entries {
+                    buf.push(tag.id());
+                    encode_string(buf, name);
+                    tag.encode_payload(buf);
+                }
+
+                buf.push(TAG_END);
+            }
+            Tag::IntArray(values) => encode_array(buf, values),
+            Tag::LongArray(values) => encode_array(buf, values),
+        }
+    }
+
+    fn decode_payload(id: u8, reader: &mut Reader<'_>, depth: usize) -> Result<Tag, ProtocolError> {
+        if depth > MAX_DEPTH {
+            return Err(ProtocolError::MalformedNbt);
+        }
+
+        Ok(match id {
+            TAG_BYTE => Tag::Byte(i8::decode(reader)?),
+            TAG_SHORT => Tag::Short(i16::decode(reader)?),
+            TAG_INT => Tag::Int(i32::decode(reader)?),
+            TAG_LONG => Tag::Long(i64::decode(reader)?),
+            TAG_FLOAT => Tag::Float(f32::decode(reader)?),
+            TAG_DOUBLE => Tag::Double(f64::decode(reader)?),
+            TAG_BYTE_ARRAY => Tag::ByteArray(decode_array(reader)?),
+            TAG_STRING => Tag::String(decode_string(reader)?),
+            TAG_LIST => {
+                let element_id = u8::decode(reader)?;
+                let length = decode_length(reader)?;
+
+                // End tags take no bytes, so only an empty list may claim to hold them
+                if element_id == TAG_END && length > 0 {
+                    return Err(ProtocolError::MalformedNbt);
+                }
+
+                // The length is untrusted, so only as much is reserved as the data could hold
+                let mut elements = Vec::with_capacity(length.min(reader.remaining()));
+                for _ in 0..length {
+                    elements.push(Tag::decode_payload(element_id, reader, depth + 1)?);
+                }
+
+                Tag::List(elements)
+            }
+            TAG_COMPOUND => {
+                let mut entries = Vec::new();
+
+                loop {
+                    let id = u8::decode(reader)?;
+                    if id == TAG_END {
+                        break;
+                    }
+
+                    let name = decode_string(reader)?;
+                    entries.push((name, Tag::decode_payload(id, reader, depth + 1)?));
+                }
+
+                Tag::Compound(entries)
+            }
+            TAG_INT_ARRAY => Tag::IntArray(decode_array(reader)?),
+            TAG_LONG_ARRAY => Tag::LongArray(decode_array(reader)?),
+            _ => return Err(ProtocolError::MalformedNbt),
+        })
+    }
+
+    /// The NBT vanilla sends for a text component, with JSON values mapped to the tags it would
+    /// use for them
+    pub fn from_text_component(component: &RawTextComponent) -> Tag {
+        let value = serde_json::to_value(component).expect("text components should serialize");
+
+        from_json(&value).unwrap_or_else(|| Tag::Compound(Vec::new()))
+    }
+
+    /// Read a text component back from the NBT it was sent as
+    pub fn to_text_component(&self) -> Result<RawTextComponent, ProtocolError> {
+        serde_json::from_value(to_json(self)).map_err(|_| ProtocolError::MalformedNbt)
+    }
+}
+
+/// Network NBT has a nameless root tag
+impl Encode for Tag {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.id());
+        self.encode_payload(buf);
+    }
+}
+
+impl Decode for Tag {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+        let id = u8::decode(reader)?;
+
+        Tag::decode_payload(id, reader, 0)
+    }
+}
+
+/// `null` has no tag, so it is left out of compounds
+fn from_json(value: &Value) -> Option<Tag> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(value) => Tag::Byte(i8::from(*value)),
+        Value::Number(number) => match (number.as_i64(), number.as_f64()) {
+            (Some(value), _) => match i32::try_from(value) {
+                Ok(value) => Tag::Int(value),
+                Err(_) => Tag::Long(value),
+            },
+            (None, value) => Tag::Double(value.unwrap_or_default()),
+        },
+        Value::String(string) => Tag::String(string.clone()),
+        Value::Array(elements) => {
+            let elements = elements.iter().filter_map(from_json).collect::<Vec<_>>();
+            let homogeneous = elements.windows(2).all(|pair| pair[0].id() == pair[1].id());
+
+            match homogeneous {
+                true => Tag::List(elements),
+                // NBT lists hold a single tag type, so mixed lists have their elements wrapped
+                false => Tag::List(elements.into_iter().map(wrap_element).collect()),
+            }
+        }
+        Value::Object(object) => Tag::Compound(
+            object
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), from_json(value)?)))
+                .collect(),
+        ),
+    })
+}
+
+fn wrap_element(element: Tag) -> Tag {
+    match element {
+        Tag::Compound(_) => element,
+        // Plain strings in a component list are text components of their own
+        Tag::String(_) => Tag::Compound(vec![("text".to_string(), element)]),
+        _ => Tag::Compound(vec![(String::new(), element)]),
+    }
+}
+
+/// Undo the wrapping of elements that are not compounds themselves
+fn unwrap_element(element: &Tag) -> &Tag {
+    match element {
+        Tag::Compound(entries) => match &entries[..] {
+            [(name, tag)] if name.is_empty() => tag,
+            _ => element,
+        },
+        _ => element,
+    }
+}
+
+fn to_json(tag: &Tag) -> Value {
+    match tag {
+        // Text components have no numbers small enough to be bytes, only booleans
+        Tag::Byte(value) => Value::Bool(*value != 0),
+        Tag::Short(value) => Value::from(*value),
+        Tag::Int(value) => Value::from(*value),
+        Tag::Long(value) => Value::from(*value),
+        Tag::Float(value) => Number::from_f64(f64::from(*value)).map_or(Value::Null, Value::Number),
+        Tag::Double(value) => Number::from_f64(*value).map_or(Value::Null, Value::Number),
+        Tag::ByteArray(values) => Value::from_iter(values.iter().copied()),
+        Tag::String(string) => Value::String(string.clone()),
+        Tag::List(elements) => Value::Array(
+            elements
+                .iter()
+                .map(|element| to_json(unwrap_element(element)))
+                .collect(),
+        ),
+        Tag::Compound(entries) => Value::Object(Map::from_iter(
+            entries
+                .iter()
+                .map(|(name, tag)| (name.clone(), to_json(tag))),
+        )),
+        Tag::IntArray(values) => Value::from_iter(values.iter().copied()),
+        Tag::LongArray(values) => Value::from_iter(values.iter().copied()),
+    }
+}
+
+/// Lengths are signed ints, unlike the VarInts used everywhere else
+fn encode_length(buf: &mut Vec<u8>, length: usize) {
+    i32::try_from(length)
+        .expect("NBT lists should be shorter than i32::MAX")
+        .encode(buf);
+}
+
+fn decode_length(reader: &mut Reader<'_>) -> Result<usize, ProtocolError> {
+    let length = i32::decode(reader)?;
+
+    usize::try_from(length).map_err(|_| ProtocolError::InvalidLength(length))
+}
+
+fn encode_array<T: Encode>(buf: &mut Vec<u8>, values: &[T]) {
+    encode_length(buf, values.len());
+
+    for value in values {
+        value.encode(buf);
+    }
+}
+
+fn decode_array<T: Decode>(reader: &mut Reader<'_>) -> Result<Vec<T>, ProtocolError> {
+    let length = decode_length(reader)?;
+
+    let mut values = Vec::with_capacity(length.min(reader.remaining()));
+    for _ in 0..length {
+        values.push(T::decode(reader)?);
+    }
+
+    Ok(values)
+}
+
+/// Strings are modified UTF-8: nulls and supplementary characters are encoded like in CESU-8
+fn encode_string(buf: &mut Vec<u8>, string: &str) {
+    let mut encoded = Vec::with_capacity(string.len());
+
+    for unit in string.encode_utf16() {
+        match unit {
+            0x0001..=0x007F => encoded.push(unit as u8),
+            0x0000 | 0x0080..=0x07FF => {
+                encoded.push(0xC0 | (unit >> 6) as u8);
+                encoded.push(0x80 | (unit & 0x3F) as u8);
+            }
+            _ => {
+                encoded.push(0xE0 | (unit >> 12) as u8);
+                encoded.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                encoded.push(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+    }
+
+    let length = u16::try_from(encoded.len()).unwrap_or(u16::MAX);
+    buf.extend(length.to_be_bytes());
+    buf.extend(&encoded[..usize::from(length)]);
+}
+
+fn decode_string(reader: &mut Reader<'_>) -> Result<String, ProtocolError> {
+    let length = u16::decode(reader)?;
+    let mut bytes = reader.take(usize::from(length))?.iter().copied();
+
+    let mut units = Vec::with_capacity(usize::from(length));
+    while let Some(first) = bytes.next() {
+        let mut continuation = || match bytes.next() {
+            Some(byte) if byte & 0xC0 == 0x80 => Ok(u16::from(byte & 0x3F)),
+            _ => Err(ProtocolError::MalformedNbt),
+        };
+
+        units.push(match first {
+            0x01..=0x7F => u16::from(first),
+            0xC0..=0xDF => u16::from(first & 0x1F) << 6 | continuation()?,
+            0xE0..=0xEF => u16::from(first & 0x0F) << 12 | continuation()? << 6 | continuation()?,
+            _ => return Err(ProtocolError::MalformedNbt),
+        });
+    }
+
+    // Java strings may hold unpaired surrogates, which Rust strings can not
+    Ok(char::decode_utf16(units)
+        .map(|char| char.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tag;
+    use crate::proto::{
+        codec::{encode, Decode, Reader},
+        packet::RawTextComponent,
+    };
+
+    #[test]
+    fn text_component() {
+        let plain = RawTextComponent::String("Hi\0".to_string());
+        assert_eq!(
+            encode(&Tag::from_text_component(&plain)),
+            b"\x08\x00\x04Hi\xC0\x80"
+        );
+
+        let component: RawTextComponent =
+            serde_json::from_str(r#"{"text": "a", "bold": true, "extra": ["b", {"text": "c"}]}"#)
+                .unwrap();
+        let data = encode(&Tag::from_text_component(&component));
+        assert_eq!(
+            data,
+            [
+                &b"\x0a"[..],
+                b"\x01\x00\x04bold\x01",
+                b"\x09\x00\x05extra\x0a\x00\x00\x00\x02",
+                b"\x08\x00\x04text\x00\x01b\x00",
+                b"\x08\x00\x04text\x00\x01c\x00",
+                b"\x08\x00\x04text\x00\x01a",
+                b"\x00",
+            ]
+            .concat()
+        );
+
+        let mut reader = Reader::new(&data, 0);
+        let read_back = Tag::decode(&mut reader)
+            .unwrap()
+            .to_text_component()
+            .unwrap();
+        reader.finish().unwrap();
+        assert_eq!(
+            serde_json::to_value(read_back).unwrap(),
+            serde_json::json!({"text": "a", "bold": true, "extra": [{"text": "b"}, {"text": "c"}]})
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let tag = Tag::Compound(vec![
+            ("byte".to_string(), Tag::Byte(-1)),
+            ("short".to_string(), Tag::Short(300)),
+            ("float".to_string(), Tag::Float(0.5)),
+            ("bytes".to_string(), Tag::ByteArray(vec![1, -2])),
+            ("string".to_string(), Tag::String("ẞ🦀\0".to_string())),
+            ("empty".to_string(), Tag::List(Vec::new())),
+            (
+                "nested".to_string(),
+                Tag::List(vec![Tag::Compound(vec![(
+                    "longs".to_string(),
+                    Tag::LongArray(vec![i64::MIN]),
+                )])]),
+            ),
+            ("ints".to_string(), Tag::IntArray(vec![7])),
+        ]);
+
+        let data = encode(&tag);
+        let mut reader = Reader::new(&data, 0);
+        assert_eq!(Tag::decode(&mut reader).unwrap(), tag);
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn reject_malicious_nbt() {
+        // An unknown tag type
+        assert!(Tag::decode(&mut Reader::new(b"\x0d", 0)).is_err());
+
+        // A list claiming two billion end tags, which would take no bytes to read
+        assert!(Tag::decode(&mut Reader::new(b"\x09\x00\x7f\xff\xff\xff", 0)).is_err());
+
+        // A list claiming more elements than there are bytes
+        assert!(Tag::decode(&mut Reader::new(b"\x09\x01\x7f\xff\xff\xff\x00", 0)).is_err());
+
+        // Lists nested deeper than vanilla allows
+        let nested = (0..1024)
+            .flat_map(|_| *b"\x09\x00\x00\x00\x01")
+            .collect::<Vec<_>>();
+        let mut deep = vec![0x09];
+        deep.extend(nested);
+        assert!(Tag::decode(&mut Reader::new(&deep, 0)).is_err());
+
+        // A continuation byte without a leading byte
+        assert!(Tag::decode(&mut Reader::new(b"\x08\x00\x01\x80", 0)).is_err());
+    }
+}
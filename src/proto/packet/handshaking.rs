@@ -0,0 +1,16 @@
+/// Packets the client sends
+pub mod serverbound {
+    use crate::proto::codec::{packet, VarInt};
+
+    packet! {
+        /// The first packet of every connection, telling the server which state to switch to
+        #[derive(Debug)]
+        pub struct Handshake(0x00): Encode, Decode {
+            pub protocol_version: VarInt,
+            /// The address the client connected to, which mods and proxies append their data to
+            pub address: String,
+            pub port: u16,
+            pub next_state: VarInt,
+        }
+    }
+}
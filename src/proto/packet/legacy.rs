@@ -0,0 +1,49 @@
+use mcproxy_model::Hostname;
+use smol_str::SmolStr;
+
+use super::{Handshake, NextState};
+
+/// The first byte of a server list ping sent by clients older than 1.7
+pub const LEGACY_PING: u8 = 0xFE;
+
+/// The packet id used by legacy servers to answer a ping or to disconnect a client
+pub const LEGACY_KICK: u8 = 0xFF;
+
+/// The client versions which sent different server list pings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyPingFormat {
+    /// Beta 1.8 to 1.3, which send `0xFE`
+    Beta,
+    /// 1.4 and 1.5, which send `0xFE 0x01`
+    V1_4,
+    /// 1.6, which send `0xFE 0x01 0xFA` followed by a `MC|PingHost` plugin message
+    V1_6,
+}
+
+/// A server list ping sent by clients older than 1.7
+#[derive(Debug)]
+pub struct LegacyPing {
+    pub format: LegacyPingFormat,
+    /// Legacy protocol number, only sent by 1.6 clients
+    pub protocol_version: Option<u8>,
+    /// Address the client connected to, only sent by 1.6 clients
+    pub address: Option<Hostname>,
+    /// Forge version appended to the address, like in a modern handshake
+    pub address_forge_version: Option<SmolStr>,
+    pub port: u16,
+    /// The ping exactly as the client sent it, to be replayed to the upstream
+    pub data: Vec<u8>,
+}
+
+impl LegacyPing {
+    /// The equivalent modern handshake, using an empty address if the client did not send one
+    pub fn handshake(&self) -> Handshake {
+        Handshake {
+            protocol_version: self.protocol_version.map_or(-1, i32::from),
+            address: self.address.clone().unwrap_or_else(|| Hostname::from("")),
+            address_forge_version: self.address_forge_version.clone(),
+            port: self.port,
+            next_state: NextState::Ping,
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::ops::RangeInclusive;
+
+use crate::proto::codec::VarInt;
+
+use self::clientbound::KnownPack;
+
+/// The protocol versions limbo can hold players in, 1.20.5 through 1.21.1
+///
+/// Transfer packets only exist from 1.20.5 on, and the packet ids used here are the same for
+/// every version in the range.
+pub const PROTOCOL_VERSIONS: RangeInclusive<i32> = 766..=767;
+
+/// Packets the client sends, after [`login::serverbound`](super::login::serverbound) ones
+pub mod serverbound {
+    use crate::proto::codec::packet;
+
+    packet! {
+        /// A data pack the client has, or the server offers
+        #[derive(Debug)]
+        pub struct KnownPack: Encode, Decode {
+            pub namespace: String,
+            pub id: String,
+            pub version: String,
+        }
+    }
+
+    packet! {
+        /// The packs the client has from the ones the server offered
+        #[derive(Debug)]
+        pub struct ConfigurationKnownPacks(0x07): Encode, Decode {
+            pub packs: Vec<KnownPack>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct AcknowledgeFinishConfiguration(0x03): Encode, Decode {}
+    }
+}
+
+/// Packets the proxy sends, after [`login::clientbound`](super::login::clientbound) ones
+pub mod clientbound {
+    use crate::proto::{
+        codec::{packet, Nbt, VarInt},
+        packet::RawTextComponent,
+    };
+
+    pub use super::serverbound::KnownPack;
+
+    packet! {
+        #[derive(Debug)]
+        pub struct ConfigurationDisconnect(0x02): Encode {
+            pub reason: Nbt<RawTextComponent>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct FinishConfiguration(0x03): Encode {}
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct RegistryEntry: Encode {
+            pub id: String,
+            /// Always false, as the data comes from the known pack
+            pub has_data: bool,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct RegistryData(0x07): Encode {
+            pub registry: String,
+            pub entries: Vec<RegistryEntry>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct FeatureFlags(0x0C): Encode {
+            pub feature_flags: Vec<String>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct ConfigurationKnownPacks(0x0E): Encode {
+            pub packs: Vec<KnownPack>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct PlayDisconnect(0x1D): Encode {
+            pub reason: Nbt<RawTextComponent>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct GameEvent(0x22): Encode {
+            pub event: u8,
+            pub value: f32,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct KeepAlive(0x26): Encode {
+            pub id: i64,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct DeathLocation: Encode {
+            pub dimension_name: String,
+            pub location: i64,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct LoginPlay(0x2B): Encode {
+            pub entity_id: i32,
+            pub is_hardcore: bool,
+            pub dimension_names: Vec<String>,
+            pub max_players: VarInt,
+            pub view_distance: VarInt,
+            pub simulation_distance: VarInt,
+            pub reduced_debug_info: bool,
+            pub enable_respawn_screen: bool,
+            pub do_limited_crafting: bool,
+            /// Index into the dimension type registry
+            pub dimension_type: VarInt,
+            pub dimension_name: String,
+            pub hashed_seed: i64,
+            pub game_mode: u8,
+            /// -1 for none
+            pub previous_game_mode: i8,
+            pub is_debug: bool,
+            pub is_flat: bool,
+            pub death_location: Option<DeathLocation>,
+            pub portal_cooldown: VarInt,
+            pub enforces_secure_chat: bool,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct SynchronizePlayerPosition(0x40): Encode {
+            pub x: f64,
+            pub y: f64,
+            pub z: f64,
+            pub yaw: f32,
+            pub pitch: f32,
+            /// Which of the fields are relative to the current position
+            pub flags: i8,
+            pub teleport_id: VarInt,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct SystemChatMessage(0x6C): Encode {
+            pub content: Nbt<RawTextComponent>,
+            /// Whether the message goes in the action bar instead of the chat
+            pub overlay: bool,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct Transfer(0x73): Encode {
+            pub host: String,
+            pub port: VarInt,
+        }
+    }
+}
+
+const DIMENSION: &str = "minecraft:overworld";
+
+/// Damage types the client looks up as soon as it creates the world
+const DAMAGE_TYPES: &[&str] = &[
+    "minecraft:arrow",
+    "minecraft:bad_respawn_point",
+    "minecraft:cactus",
+    "minecraft:cramming",
+    "minecraft:dragon_breath",
+    "minecraft:drown",
+    "minecraft:dry_out",
+    "minecraft:explosion",
+    "minecraft:fall",
+    "minecraft:falling_anvil",
+    "minecraft:falling_block",
+    "minecraft:falling_stalactite",
+    "minecraft:fireball",
+    "minecraft:fireworks",
+    "minecraft:fly_into_wall",
+    "minecraft:freeze",
+    "minecraft:generic",
+    "minecraft:generic_kill",
+    "minecraft:hot_floor",
+    "minecraft:in_fire",
+    "minecraft:in_wall",
+    "minecraft:indirect_magic",
+    "minecraft:lava",
+    "minecraft:lightning_bolt",
+    "minecraft:magic",
+    "minecraft:mob_attack",
+    "minecraft:mob_attack_no_aggro",
+    "minecraft:mob_projectile",
+    "minecraft:on_fire",
+    "minecraft:out_of_world",
+    "minecraft:outside_border",
+    "minecraft:player_attack",
+    "minecraft:player_explosion",
+    "minecraft:sonic_boom",
+    "minecraft:stalagmite",
+    "minecraft:starve",
+    "minecraft:sting",
+    "minecraft:sweet_berry_bush",
+    "minecraft:thorns",
+    "minecraft:thrown",
+    "minecraft:trident",
+    "minecraft:unattributed_fireball",
+    "minecraft:wither",
+    "minecraft:wither_skull",
+];
+
+/// Names of the vanilla data pack, which differ between releases sharing a protocol version
+fn core_pack_versions(protocol_version: i32) -> &'static [&'static str] {
+    match protocol_version {
+        766 => &["1.20.5", "1.20.6"],
+        _ => &["1.21", "1.21.1"],
+    }
+}
+
+/// The registry entries the client needs to join the void world
+///
+/// Entries are sent without data, so the client loads them from its own copy of the vanilla
+/// data pack. Referenced entries have to be present too, such as the biome of the wolf variant.
+pub fn registries(protocol_version: i32) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut registries = vec![
+        ("minecraft:dimension_type", &[DIMENSION][..]),
+        (
+            "minecraft:worldgen/biome",
+            &["minecraft:plains", "minecraft:taiga"],
+        ),
+        ("minecraft:damage_type", DAMAGE_TYPES),
+        ("minecraft:wolf_variant", &["minecraft:pale"]),
+    ];
+
+    // Paintings became data driven in 1.21, and the registry must not be empty
+    if protocol_version >= 767 {
+        registries.push(("minecraft:painting_variant", &["minecraft:kebab"]));
+    }
+
+    registries
+}
+
+/// Offer every release of the vanilla pack for the protocol version, the client picks its own
+pub fn known_packs(protocol_version: i32) -> clientbound::ConfigurationKnownPacks {
+    let packs = core_pack_versions(protocol_version)
+        .iter()
+        .map(|version| KnownPack {
+            namespace: "minecraft".to_string(),
+            id: "core".to_string(),
+            version: version.to_string(),
+        })
+        .collect();
+
+    clientbound::ConfigurationKnownPacks { packs }
+}
+
+/// Whether the client's known packs include the vanilla pack
+pub fn has_core_pack(known_packs: &serverbound::ConfigurationKnownPacks) -> bool {
+    known_packs
+        .packs
+        .iter()
+        .any(|pack| pack.namespace == "minecraft" && pack.id == "core")
+}
+
+pub fn registry_data(registry: &str, entries: &[&str]) -> clientbound::RegistryData {
+    clientbound::RegistryData {
+        registry: registry.to_string(),
+        entries: entries
+            .iter()
+            .map(|entry| clientbound::RegistryEntry {
+                id: entry.to_string(),
+                has_data: false,
+            })
+            .collect(),
+    }
+}
+
+pub fn feature_flags() -> clientbound::FeatureFlags {
+    clientbound::FeatureFlags {
+        feature_flags: vec!["minecraft:vanilla".to_string()],
+    }
+}
+
+pub fn login_play() -> clientbound::LoginPlay {
+    clientbound::LoginPlay {
+        entity_id: 1,
+        is_hardcore: false,
+        dimension_names: vec![DIMENSION.to_string()],
+        max_players: VarInt(1),
+        view_distance: VarInt(2),
+        simulation_distance: VarInt(2),
+        reduced_debug_info: false,
+        enable_respawn_screen: true,
+        do_limited_crafting: false,
+        dimension_type: VarInt(0),
+        dimension_name: DIMENSION.to_string(),
+        hashed_seed: 0,
+        // Spectators do not wait for the chunk they are in to load, of which there are none
+        game_mode: 3,
+        previous_game_mode: -1,
+        is_debug: false,
+        is_flat: true,
+        death_location: None,
+        portal_cooldown: VarInt(0),
+        enforces_secure_chat: false,
+    }
+}
+
+/// Start waiting for level chunks, which closes the loading screen
+pub fn game_event_start_waiting() -> clientbound::GameEvent {
+    clientbound::GameEvent {
+        event: 13,
+        value: 0.0,
+    }
+}
+
+pub fn synchronize_player_position() -> clientbound::SynchronizePlayerPosition {
+    clientbound::SynchronizePlayerPosition {
+        x: 0.0,
+        y: 100.0,
+        z: 0.0,
+        yaw: 0.0,
+        pitch: 0.0,
+        flags: 0,
+        teleport_id: VarInt(1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{has_core_pack, known_packs, serverbound};
+    use crate::proto::codec::{encode, Decode, Reader};
+
+    #[test]
+    fn known_packs_round_trip() {
+        let data = encode(&known_packs(767));
+        let mut reader = Reader::new(&data, 64);
+        let known_packs = serverbound::ConfigurationKnownPacks::decode(&mut reader).unwrap();
+        reader.finish().unwrap();
+        assert!(has_core_pack(&known_packs));
+
+        let no_packs = serverbound::ConfigurationKnownPacks::decode(&mut Reader::new(&[0], 64));
+        assert!(!has_core_pack(&no_packs.unwrap()));
+
+        assert!(
+            serverbound::ConfigurationKnownPacks::decode(&mut Reader::new(&[1, 0], 64)).is_err()
+        );
+    }
+}
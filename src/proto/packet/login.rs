@@ -0,0 +1,112 @@
+/// Packets the client sends
+pub mod serverbound {
+    use uuid::Uuid;
+
+    use crate::proto::{
+        codec::{packet, Decode, Encode, PacketId, Reader, RemainingBytes, VarInt},
+        error::ProtocolError,
+    };
+
+    /// The first packet of the login, naming the player
+    ///
+    /// Every version starts with the name, but the fields after it have changed many times. The
+    /// UUID is only read when it is all that follows, like from 1.20.2 on.
+    #[derive(Debug)]
+    pub struct LoginStart {
+        pub name: String,
+        pub uuid: Option<Uuid>,
+    }
+
+    impl PacketId for LoginStart {
+        const ID: i32 = 0x00;
+    }
+
+    impl Encode for LoginStart {
+        fn encode(&self, buf: &mut Vec<u8>) {
+            self.name.encode(buf);
+
+            if let Some(uuid) = self.uuid {
+                uuid.encode(buf);
+            }
+        }
+    }
+
+    impl Decode for LoginStart {
+        fn decode(reader: &mut Reader<'_>) -> Result<Self, ProtocolError> {
+            let name = String::decode(reader)?;
+            let uuid = match reader.remaining() {
+                16 => Some(Uuid::decode(reader)?),
+                _ => None,
+            };
+
+            // Whatever else older versions send is of no interest
+            reader.take_remaining();
+
+            Ok(LoginStart { name, uuid })
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct LoginPluginResponse(0x02): Encode, Decode {
+            pub message_id: VarInt,
+            /// Whether the client understood the request, in which case data follows
+            pub successful: bool,
+            pub data: RemainingBytes,
+        }
+    }
+
+    packet! {
+        /// Sent in answer to Login Success, after which the client is configuring
+        #[derive(Debug)]
+        pub struct LoginAcknowledged(0x03): Encode, Decode {}
+    }
+}
+
+/// Packets the server sends
+pub mod clientbound {
+    use uuid::Uuid;
+
+    use crate::proto::{
+        codec::{packet, Json, RemainingBytes, VarInt},
+        packet::RawTextComponent,
+    };
+
+    packet! {
+        #[derive(Debug)]
+        pub struct Disconnect(0x00): Encode {
+            pub reason: Json<RawTextComponent>,
+        }
+    }
+
+    packet! {
+        /// A property of the player's profile, such as their skin
+        #[derive(Debug)]
+        pub struct Property: Encode, Decode {
+            pub name: String,
+            pub value: String,
+            pub signature: Option<String>,
+        }
+    }
+
+    packet! {
+        /// Login Success as sent from 1.20.5 on, which added strict error handling
+        #[derive(Debug)]
+        pub struct LoginSuccess(0x02): Encode, Decode {
+            pub uuid: Uuid,
+            pub name: String,
+            pub properties: Vec<Property>,
+            /// Whether the client disconnects on packets it fails to decode, instead of skipping them
+            pub strict_error_handling: bool,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct LoginPluginRequest(0x04): Encode, Decode {
+            pub message_id: VarInt,
+            pub channel: String,
+            pub data: RemainingBytes,
+        }
+    }
+}
@@ -7,8 +7,18 @@ use std::{
 };
 use tracing::warn;
 
+/// Packets sent before the client has chosen a state
+pub mod handshaking;
+/// Legacy (pre-1.7) server list ping structs
+pub mod legacy;
+/// Packets for holding players in a void world
+pub mod limbo;
+/// Packets sent while logging in
+pub mod login;
 /// Response packet structs
 pub mod response;
+/// Packets sent while pinging the server
+pub mod status;
 
 #[derive(Debug)]
 pub struct Packet {
@@ -47,6 +57,26 @@ pub enum RawTextComponent {
     String(String),
 }
 
+impl RawTextComponent {
+    /// Replace the text of the component and all of its children, keeping their styles
+    pub fn map_text(&self, f: &impl Fn(&str) -> String) -> RawTextComponent {
+        match self {
+            RawTextComponent::Object(object) => RawTextComponent::Object(RawTextComponentObject {
+                text: f(&object.text),
+                extra: object
+                    .extra
+                    .as_ref()
+                    .map(|extra| extra.iter().map(|child| child.map_text(f)).collect()),
+                ..object.clone()
+            }),
+            RawTextComponent::Array(array) => {
+                RawTextComponent::Array(array.iter().map(|child| child.map_text(f)).collect())
+            }
+            RawTextComponent::String(text) => RawTextComponent::String(f(text)),
+        }
+    }
+}
+
 impl From<RawTextComponent> for RawTextComponentObject {
     fn from(value: RawTextComponent) -> Self {
         match value {
@@ -236,6 +266,55 @@ impl ElaboratedTextComponent {
 
         components
     }
+
+    /// Encode the components as text using legacy `§` formatting codes
+    ///
+    /// Hex colors have no formatting code, so they are rendered in the default color
+    pub fn encode_formatting_codes(components: &[ElaboratedTextComponent]) -> String {
+        let mut string = String::new();
+
+        for component in components {
+            let named_color = match &component.color {
+                Some(Color::Named(color)) => Some(*color),
+                Some(Color::Hex(_)) | None => None,
+            };
+
+            let formatting = [
+                (component.obfuscated, 'k'),
+                (component.bold, 'l'),
+                (component.strikethrough, 'm'),
+                (component.underlined, 'n'),
+                (component.italic, 'o'),
+            ];
+
+            // A color code resets any formatting, so every styled component starts from scratch
+            if !string.is_empty() {
+                string.push_str("§r");
+            }
+
+            if let Some(color) = named_color {
+                string.push('§');
+                string.push(color.to_code());
+            }
+
+            for (_, code) in formatting.iter().filter(|(enabled, _)| *enabled) {
+                string.push('§');
+                string.push(*code);
+            }
+
+            string.push_str(&component.text);
+        }
+
+        string
+    }
+
+    /// Concatenate the text of the components, discarding all formatting
+    pub fn plain_text(components: &[ElaboratedTextComponent]) -> String {
+        components
+            .iter()
+            .map(|component| component.text.as_str())
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -254,6 +333,7 @@ impl Color {
         }
     }
 
+    #[allow(dead_code)]
     pub fn background_color(&self) -> &str {
         match self {
             Color::Named(color_name) => color_name.background_color(),
@@ -353,6 +433,7 @@ impl ColorName {
         }
     }
 
+    #[allow(dead_code)]
     pub fn background_color(&self) -> &'static str {
         match self {
             ColorName::Black => "#000000",
@@ -375,7 +456,7 @@ impl ColorName {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum NextState {
     Ping,
     Login,
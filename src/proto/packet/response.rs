@@ -15,6 +15,31 @@ pub struct StatusResponse {
     pub favicon: Option<String>,
 }
 
+impl StatusResponse {
+    /// Replace the text shown to the client, in the description, version name and player sample
+    pub fn map_text(&self, f: &impl Fn(&str) -> String) -> StatusResponse {
+        StatusResponse {
+            version: Version {
+                name: f(&self.version.name).into(),
+                protocol: self.version.protocol,
+            },
+            players: self.players.as_ref().map(|players| Players {
+                sample: players
+                    .sample
+                    .iter()
+                    .map(|player| Player {
+                        name: f(&player.name).into(),
+                        id: player.id,
+                    })
+                    .collect(),
+                ..*players
+            }),
+            description: self.description.map_text(f),
+            favicon: self.favicon.clone(),
+        }
+    }
+}
+
 /// The version part of the JSON response to a ping
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(test, derive(schemars::JsonSchema))]
@@ -0,0 +1,39 @@
+/// Packets the client sends
+pub mod serverbound {
+    use crate::proto::codec::packet;
+
+    packet! {
+        #[derive(Debug)]
+        pub struct StatusRequest(0x00): Encode, Decode {}
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct PingRequest(0x01): Encode, Decode {
+            /// Any number, which the server echoes back
+            pub payload: i64,
+        }
+    }
+}
+
+/// Packets the server sends
+pub mod clientbound {
+    use crate::proto::{
+        codec::{packet, Json},
+        packet::response,
+    };
+
+    packet! {
+        #[derive(Debug)]
+        pub struct StatusResponse(0x00): Encode, Decode {
+            pub response: Json<response::StatusResponse>,
+        }
+    }
+
+    packet! {
+        #[derive(Debug)]
+        pub struct PongResponse(0x01): Encode, Decode {
+            pub payload: i64,
+        }
+    }
+}
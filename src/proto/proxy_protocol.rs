@@ -0,0 +1,255 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use serde::Deserialize;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing_error::{InstrumentError, InstrumentResult, TracedError};
+
+/// The signature that starts every version 2 header
+pub const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest possible version 1 header, including the trailing CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+/// A version of the HAProxy PROXY protocol
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyProtocolVersion {
+    /// The human readable header
+    V1,
+    /// The binary header
+    V2,
+}
+
+impl Display for ProxyProtocolVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyProtocolVersion::V1 => f.write_str("v1"),
+            ProxyProtocolVersion::V2 => f.write_str("v2"),
+        }
+    }
+}
+
+/// Put both addresses in the same family, since a header can only carry one
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let to_v6 = |address: SocketAddr| match address.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), address.port()),
+        IpAddr::V6(_) => address,
+    };
+
+    match (source, destination) {
+        (SocketAddr::V4(_), SocketAddr::V4(_)) | (SocketAddr::V6(_), SocketAddr::V6(_)) => {
+            (source, destination)
+        }
+        _ => (to_v6(source), to_v6(destination)),
+    }
+}
+
+/// Encode a PROXY protocol header for a proxied TCP connection
+pub fn encode_header(
+    version: ProxyProtocolVersion,
+    source: SocketAddr,
+    destination: SocketAddr,
+) -> Vec<u8> {
+    let (source, destination) = same_family(source, destination);
+
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let family = match source {
+                SocketAddr::V4(_) => "TCP4",
+                SocketAddr::V6(_) => "TCP6",
+            };
+
+            format!(
+                "PROXY {family} {} {} {} {}\r\n",
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            )
+            .into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            let (family, addresses) = match (source.ip(), destination.ip()) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    (0x11, [source.octets(), destination.octets()].concat())
+                }
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    (0x21, [source.octets(), destination.octets()].concat())
+                }
+                _ => unreachable!("addresses were put in the same family"),
+            };
+
+            let length = addresses.len() + 4;
+
+            let mut header = Vec::with_capacity(V2_SIGNATURE.len() + 4 + length);
+            header.extend_from_slice(&V2_SIGNATURE);
+            // Version 2, PROXY command
+            header.push(0x21);
+            header.push(family);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+            header.extend_from_slice(&addresses);
+            header.extend_from_slice(&source.port().to_be_bytes());
+            header.extend_from_slice(&destination.port().to_be_bytes());
+            header
+        }
+    }
+}
+
+/// Write a PROXY protocol header, which must be the first thing sent to the upstream
+#[tracing::instrument(skip(stream), err)]
+pub async fn write_header(
+    stream: &mut (dyn AsyncWrite + Unpin + Send),
+    version: ProxyProtocolVersion,
+    source: SocketAddr,
+    destination: SocketAddr,
+) -> Result<(), TracedError<io::Error>> {
+    stream
+        .write_all(&encode_header(version, source, destination))
+        .await
+        .map_err(InstrumentError::in_current_span)
+}
+
+fn invalid_header(message: &'static str) -> TracedError<io::Error> {
+    io::Error::new(io::ErrorKind::InvalidData, message).in_current_span()
+}
+
+/// Read a PROXY protocol header of either version, returning the source address it carries
+///
+/// Headers for health checks (`LOCAL` or `UNKNOWN`) and for non-TCP connections carry no
+/// usable address, and give `None`.
+#[tracing::instrument(skip_all, err)]
+pub async fn read_header(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+) -> Result<Option<SocketAddr>, TracedError<io::Error>> {
+    // Both versions are at least this long, so this never reads past the header
+    let mut header = vec![0u8; V2_SIGNATURE.len()];
+    stream.read_exact(&mut header).await.in_current_span()?;
+
+    if header == V2_SIGNATURE {
+        let version_command = stream.read_u8().await.in_current_span()?;
+        let family = stream.read_u8().await.in_current_span()?;
+        let length = stream.read_u16().await.in_current_span()?;
+
+        let mut addresses = vec![0u8; usize::from(length)];
+        stream.read_exact(&mut addresses).await.in_current_span()?;
+
+        if version_command >> 4 != 2 {
+            return Err(invalid_header("unsupported PROXY protocol version"));
+        }
+
+        // Only the PROXY command carries addresses, LOCAL is sent by the proxy itself
+        if version_command & 0x0F != 1 {
+            return Ok(None);
+        }
+
+        let source = match family {
+            0x11 if addresses.len() >= 12 => {
+                let ip: [u8; 4] = addresses[0..4].try_into().unwrap();
+                let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port)
+            }
+            0x21 if addresses.len() >= 36 => {
+                let ip: [u8; 16] = addresses[0..16].try_into().unwrap();
+                let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)
+            }
+            0x11 | 0x21 => return Err(invalid_header("PROXY protocol addresses truncated")),
+            _ => return Ok(None),
+        };
+
+        return Ok(Some(source));
+    }
+
+    if !header.starts_with(b"PROXY ") {
+        return Err(invalid_header("missing PROXY protocol header"));
+    }
+
+    while !header.ends_with(b"\r\n") {
+        if header.len() == V1_MAX_LENGTH {
+            return Err(invalid_header("PROXY protocol header too long"));
+        }
+
+        header.push(stream.read_u8().await.in_current_span()?);
+    }
+
+    let header = std::str::from_utf8(&header[..header.len() - 2])
+        .map_err(|_| invalid_header("PROXY protocol header is not ASCII"))?;
+
+    match header.split(' ').collect::<Vec<_>>().as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _destination, source_port, _destination_port] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .map_err(|_| invalid_header("invalid PROXY protocol source address"))?;
+            let port = source_port
+                .parse::<u16>()
+                .map_err(|_| invalid_header("invalid PROXY protocol source port"))?;
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid_header("malformed PROXY protocol header")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_header, read_header, ProxyProtocolVersion, V2_SIGNATURE};
+
+    #[test]
+    fn encode_headers() {
+        let source = "203.0.113.7:51234".parse().unwrap();
+
+        assert_eq!(
+            encode_header(
+                ProxyProtocolVersion::V1,
+                source,
+                "192.0.2.1:25565".parse().unwrap()
+            ),
+            b"PROXY TCP4 203.0.113.7 192.0.2.1 51234 25565\r\n"
+        );
+        assert_eq!(
+            encode_header(
+                ProxyProtocolVersion::V1,
+                source,
+                "[::1]:25565".parse().unwrap()
+            ),
+            b"PROXY TCP6 ::ffff:203.0.113.7 ::1 51234 25565\r\n"
+        );
+
+        let header = encode_header(
+            ProxyProtocolVersion::V2,
+            source,
+            "192.0.2.1:25565".parse().unwrap(),
+        );
+        assert_eq!(header[..12], V2_SIGNATURE);
+        assert_eq!(
+            header[12..],
+            [0x21, 0x11, 0, 12, 203, 0, 113, 7, 192, 0, 2, 1, 0xC8, 0x22, 0x63, 0xDD]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_headers() {
+        let source = "[2001:db8::7]:51234".parse().unwrap();
+        let destination = "[2001:db8::1]:25565".parse().unwrap();
+
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let mut header = encode_header(version, source, destination);
+            header.extend_from_slice(b"handshake");
+
+            let mut stream = header.as_slice();
+            assert_eq!(read_header(&mut stream).await.unwrap(), Some(source));
+            assert_eq!(stream, b"handshake");
+        }
+
+        let mut health_check = &b"PROXY UNKNOWN\r\n"[..];
+        assert_eq!(read_header(&mut health_check).await.unwrap(), None);
+
+        let mut handshake = &b"\x10\x00\xff\x05\x09localhost\x63\xdd\x01"[..];
+        assert!(read_header(&mut handshake).await.is_err());
+    }
+}
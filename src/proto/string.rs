@@ -1,9 +1,8 @@
-use tokio::io::{self, AsyncRead, AsyncReadExt};
-use tracing_error::TracedError;
-
 use crate::proto::var_int;
 use std::convert::TryInto;
-use std::marker::Unpin;
+
+/// Longest string the protocol allows, in UTF-16 code units
+pub const MAX_LENGTH: usize = 32767;
 
 // Generate a UTF 8 string with a var_int size prefix
 #[tracing::instrument]
@@ -15,15 +14,3 @@ pub fn write(string: &str) -> Vec<u8> {
 
     string_vec
 }
-
-// Read a UTF 8 string with a var_int size prefix
-#[tracing::instrument(skip_all)]
-pub async fn read(
-    stream: &mut (dyn AsyncRead + Unpin + Send),
-) -> Result<String, TracedError<io::Error>> {
-    let len = var_int::read(stream).await?.value;
-    let mut buf = vec![0u8; len.try_into().unwrap()];
-    stream.read_exact(&mut buf).await?;
-
-    Ok(String::from_utf8_lossy(&buf).to_string())
-}
@@ -0,0 +1,316 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use ipnet::IpNet;
+use tokio::{sync::watch::Receiver, time::sleep};
+use tracing::{info, warn};
+
+use crate::config::schema::{Config, RateLimitConfig, TokenBucketConfig};
+
+/// How often buckets that have refilled, and bans that have run out, are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Why a connection was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The address is serving a ban
+    Banned,
+    /// The address went over its own rate limit, and was banned for it if `banned`
+    IpRateLimited { banned: bool },
+    /// The address's network went over its rate limit, and the address was banned for it if
+    /// `banned`
+    NetworkRateLimited { banned: bool },
+    /// Too many connections are waiting on their handshake already
+    TooManyHandshakes,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Banned => f.write_str("banned"),
+            Rejection::IpRateLimited { .. } => f.write_str("address rate limited"),
+            Rejection::NetworkRateLimited { .. } => f.write_str("network rate limited"),
+            Rejection::TooManyHandshakes => f.write_str("too many handshakes"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(config: &TokenBucketConfig, now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::from(config.burst),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, config: &TokenBucketConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * config.per_second).min(f64::from(config.burst));
+        self.updated = now;
+    }
+
+    /// Take a token for a connection, if there is one left
+    fn take(&mut self, config: &TokenBucketConfig, now: Instant) -> bool {
+        self.refill(config, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Strikes {
+    count: u32,
+    since: Instant,
+}
+
+/// Keeps track of how often every address connects
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    ips: DashMap<IpAddr, TokenBucket>,
+    networks: DashMap<IpNet, TokenBucket>,
+    strikes: DashMap<IpAddr, Strikes>,
+    bans: DashMap<IpAddr, Instant>,
+    handshaking: Arc<AtomicUsize>,
+}
+
+/// Counts a connection as handshaking until it is dropped
+#[derive(Debug)]
+pub struct HandshakeGuard {
+    handshaking: Arc<AtomicUsize>,
+}
+
+impl Drop for HandshakeGuard {
+    fn drop(&mut self) {
+        self.handshaking.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The network a rate limited address is grouped in
+fn network(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> IpNet {
+    let prefix = match ip {
+        IpAddr::V4(_) => ipv4_prefix.min(32),
+        IpAddr::V6(_) => ipv6_prefix.min(128),
+    };
+
+    IpNet::new(ip, prefix)
+        .expect("prefix should be clamped to the address length")
+        .trunc()
+}
+
+impl RateLimiter {
+    /// Let a connection from `ip` through, unless it breaks one of the limits
+    ///
+    /// The connection counts as handshaking until the returned guard is dropped.
+    pub fn admit(&self, ip: IpAddr, config: &RateLimitConfig) -> Result<HandshakeGuard, Rejection> {
+        let ip = ip.to_canonical();
+        let now = Instant::now();
+
+        if let Some(until) = self.bans.get(&ip).map(|until| *until) {
+            if until > now {
+                return Err(Rejection::Banned);
+            }
+
+            self.bans.remove(&ip);
+        }
+
+        // Checked before any tokens are taken, which clients are not to blame for running out of
+        let handshaking = self.handshaking.fetch_add(1, Ordering::Relaxed);
+        let guard = HandshakeGuard {
+            handshaking: self.handshaking.clone(),
+        };
+        if config
+            .max_handshaking
+            .is_some_and(|max_handshaking| handshaking >= max_handshaking)
+        {
+            return Err(Rejection::TooManyHandshakes);
+        }
+
+        if let Some(limit) = &config.per_ip {
+            let allowed = self
+                .ips
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::full(limit, now))
+                .take(limit, now);
+
+            if !allowed {
+                let banned = self.strike(ip, config, now);
+                return Err(Rejection::IpRateLimited { banned });
+            }
+        }
+
+        if let Some(limit) = &config.per_network {
+            let allowed = self
+                .networks
+                .entry(network(ip, limit.ipv4_prefix, limit.ipv6_prefix))
+                .or_insert_with(|| TokenBucket::full(&limit.bucket, now))
+                .take(&limit.bucket, now);
+
+            if !allowed {
+                let banned = self.strike(ip, config, now);
+                return Err(Rejection::NetworkRateLimited { banned });
+            }
+        }
+
+        Ok(guard)
+    }
+
+    /// Count a strike against the address, returning whether it got the address banned
+    fn strike(&self, ip: IpAddr, config: &RateLimitConfig, now: Instant) -> bool {
+        let Some(ban) = &config.ban else {
+            return false;
+        };
+
+        let mut strikes = self.strikes.entry(ip).or_insert(Strikes {
+            count: 0,
+            since: now,
+        });
+
+        if now.saturating_duration_since(strikes.since) > ban.window() {
+            *strikes = Strikes {
+                count: 0,
+                since: now,
+            };
+        }
+        strikes.count += 1;
+
+        if strikes.count < ban.strikes {
+            return false;
+        }
+
+        drop(strikes);
+        self.strikes.remove(&ip);
+        self.bans.insert(ip, now + ban.duration());
+        warn!(%ip, duration = ?ban.duration(), "banned address for going over its rate limits");
+
+        true
+    }
+
+    /// Forget everything that no longer has any effect
+    fn prune(&self, config: &RateLimitConfig) {
+        let now = Instant::now();
+
+        match &config.per_ip {
+            Some(limit) => self.ips.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < f64::from(limit.burst)
+            }),
+            None => self.ips.clear(),
+        }
+
+        match &config.per_network {
+            Some(limit) => self.networks.retain(|_, bucket| {
+                bucket.refill(&limit.bucket, now);
+                bucket.tokens < f64::from(limit.bucket.burst)
+            }),
+            None => self.networks.clear(),
+        }
+
+        match &config.ban {
+            Some(ban) => self
+                .strikes
+                .retain(|_, strikes| now.saturating_duration_since(strikes.since) <= ban.window()),
+            None => self.strikes.clear(),
+        }
+
+        self.bans.retain(|_, until| *until > now);
+    }
+}
+
+/// Periodically forget the buckets, strikes and bans that no longer matter
+pub async fn watch(config: Receiver<Arc<Config>>, rate_limiter: Arc<RateLimiter>) {
+    info!("pruning rate limits");
+
+    loop {
+        sleep(PRUNE_INTERVAL).await;
+
+        let config = config.borrow().clone();
+        rate_limiter.prune(&config.proxy.rate_limit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use super::{RateLimiter, Rejection};
+    use crate::config::schema::{
+        BanConfig, NetworkRateLimitConfig, RateLimitConfig, TokenBucketConfig,
+    };
+
+    #[test]
+    fn rate_limits() {
+        let rate_limiter = RateLimiter::default();
+        let config = RateLimitConfig {
+            per_ip: Some(TokenBucketConfig {
+                burst: 2,
+                per_second: 0.0,
+            }),
+            per_network: Some(NetworkRateLimitConfig {
+                bucket: TokenBucketConfig {
+                    burst: 3,
+                    per_second: 0.0,
+                },
+                ipv4_prefix: 24,
+                ipv6_prefix: 48,
+            }),
+            max_handshaking: Some(2),
+            ban: Some(BanConfig {
+                strikes: 2,
+                window: 60,
+                duration: 600,
+            }),
+        };
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        let first = rate_limiter.admit(ip("192.0.2.1"), &config).unwrap();
+        let second = rate_limiter.admit(ip("192.0.2.1"), &config).unwrap();
+        assert_eq!(
+            rate_limiter.admit(ip("192.0.2.2"), &config).unwrap_err(),
+            Rejection::TooManyHandshakes
+        );
+        drop((first, second));
+
+        assert_eq!(
+            rate_limiter.admit(ip("192.0.2.1"), &config).unwrap_err(),
+            Rejection::IpRateLimited { banned: false }
+        );
+        assert!(rate_limiter.admit(ip("192.0.2.2"), &config).is_ok());
+        assert_eq!(
+            rate_limiter.admit(ip("192.0.2.3"), &config).unwrap_err(),
+            Rejection::NetworkRateLimited { banned: false }
+        );
+        assert!(rate_limiter.admit(ip("198.51.100.1"), &config).is_ok());
+
+        // IPv4-mapped addresses are the same address
+        assert_eq!(
+            rate_limiter
+                .admit(ip("::ffff:192.0.2.1"), &config)
+                .unwrap_err(),
+            Rejection::IpRateLimited { banned: true }
+        );
+        assert_eq!(
+            rate_limiter.admit(ip("192.0.2.1"), &config).unwrap_err(),
+            Rejection::Banned
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+use mcproxy_model::{Hostname, Upstream};
+use serde::Deserialize;
+
+use super::Route;
+use crate::health::UpstreamHealth;
+
+/// How connections are spread across the upstreams of a pool
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    /// Take turns between the upstreams
+    #[default]
+    RoundRobin,
+    /// Prefer the upstream with the fewest connections proxied to it
+    LeastConnections,
+    /// Prefer the upstream with the fewest players online, as of its last ping
+    LeastPlayers,
+}
+
+impl Display for BalanceStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BalanceStrategy::RoundRobin => f.write_str("round robin"),
+            BalanceStrategy::LeastConnections => f.write_str("least connections"),
+            BalanceStrategy::LeastPlayers => f.write_str("least players"),
+        }
+    }
+}
+
+/// Picks which upstream of a pool a connection goes to
+#[derive(Debug)]
+pub struct LoadBalancer {
+    /// Position of the round robin through the pool of each hostname
+    cursors: DashMap<Hostname, usize>,
+
+    /// Connections currently proxied to each upstream, like `active_server_connections`
+    connections: DashMap<Upstream, usize>,
+
+    upstream_health: Arc<UpstreamHealth>,
+}
+
+impl LoadBalancer {
+    pub fn new(upstream_health: Arc<UpstreamHealth>) -> Self {
+        LoadBalancer {
+            cursors: DashMap::new(),
+            connections: DashMap::new(),
+            upstream_health,
+        }
+    }
+
+    /// The order in which to try connecting to the upstreams of a route
+    ///
+    /// Healthy members are ordered by the route's strategy, and members known to be unhealthy
+    /// are only tried once none of those are left.
+    pub fn order(&self, hostname: &Hostname, route: &Route) -> Vec<Upstream> {
+        if route.upstreams.len() == 1 {
+            return route.upstreams.clone();
+        }
+
+        let (mut healthy, unhealthy): (Vec<_>, Vec<_>) = route
+            .upstreams
+            .iter()
+            .cloned()
+            .partition(|upstream| self.upstream_health.is_healthy(upstream));
+
+        if !healthy.is_empty() {
+            // Rotating first also spreads connections between members the strategy ties
+            let cursor = {
+                let mut cursor = self.cursors.entry(hostname.clone()).or_default();
+                *cursor = cursor.wrapping_add(1);
+                *cursor
+            };
+
+            let len = healthy.len();
+            healthy.rotate_left(cursor % len);
+        }
+
+        match route.options.balance {
+            BalanceStrategy::RoundRobin => {}
+            BalanceStrategy::LeastConnections => {
+                healthy.sort_by_key(|upstream| self.connections(upstream))
+            }
+            BalanceStrategy::LeastPlayers => healthy.sort_by_key(|upstream| {
+                let players_online = self
+                    .upstream_health
+                    .get(upstream)
+                    .and_then(|check| check.players_online());
+
+                // Members that were never pinged go last
+                (
+                    players_online.unwrap_or(u32::MAX),
+                    self.connections(upstream),
+                )
+            }),
+        }
+
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    pub fn connections(&self, upstream: &Upstream) -> usize {
+        self.connections
+            .get(upstream)
+            .map_or(0, |connections| *connections)
+    }
+
+    /// Count a connection proxied to an upstream, until the returned guard is dropped
+    pub fn connect(self: &Arc<Self>, upstream: Upstream) -> ConnectionGuard {
+        *self.connections.entry(upstream.clone()).or_default() += 1;
+
+        ConnectionGuard {
+            load_balancer: self.clone(),
+            upstream,
+        }
+    }
+}
+
+/// A connection counted by [`LoadBalancer::connect`]
+pub struct ConnectionGuard {
+    load_balancer: Arc<LoadBalancer>,
+    upstream: Upstream,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(mut connections) = self.load_balancer.connections.get_mut(&self.upstream) {
+            *connections = connections.saturating_sub(1);
+        }
+
+        self.load_balancer
+            .connections
+            .remove_if(&self.upstream, |_, connections| *connections == 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use mcproxy_model::{Hostname, Upstream};
+
+    use super::{BalanceStrategy, LoadBalancer};
+    use crate::{config::schema::ServerOptions, health::UpstreamHealth, routing::Route};
+
+    #[test]
+    fn pool_order() {
+        let upstreams = ["10.0.0.1:25565", "10.0.0.2:25565", "10.0.0.3:25565"]
+            .map(|upstream| Upstream::from(upstream.parse::<std::net::SocketAddr>().unwrap()));
+        let hostname = Hostname::from("lobby.example.com");
+        let route = |balance| Route {
+            upstreams: upstreams.to_vec(),
+            options: Arc::new(ServerOptions {
+                balance,
+                ..Default::default()
+            }),
+        };
+
+        let upstream_health = Arc::new(UpstreamHealth::default());
+        let load_balancer = Arc::new(LoadBalancer::new(upstream_health.clone()));
+
+        // Round robin takes turns
+        let round_robin = route(BalanceStrategy::RoundRobin);
+        let first = load_balancer.order(&hostname, &round_robin)[0].clone();
+        let second = load_balancer.order(&hostname, &round_robin)[0].clone();
+        assert_ne!(first, second);
+
+        // Busy upstreams are avoided
+        let _guards = [
+            load_balancer.connect(upstreams[0].clone()),
+            load_balancer.connect(upstreams[1].clone()),
+        ];
+        let least_connections = route(BalanceStrategy::LeastConnections);
+        assert_eq!(
+            load_balancer.order(&hostname, &least_connections)[0],
+            upstreams[2]
+        );
+
+        // Unhealthy upstreams go last, even when they are the least busy
+        upstream_health.record_ping(upstreams[2].clone(), None);
+        assert_eq!(
+            load_balancer.order(&hostname, &least_connections)[2],
+            upstreams[2]
+        );
+
+        drop(_guards);
+        assert_eq!(load_balancer.connections(&upstreams[0]), 0);
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use mcproxy_model::{Hostname, Upstream};
+use tracing::trace;
+
+use crate::config::schema::{Config, ServerOptions};
+
+#[cfg(feature = "discovery")]
+use crate::config::schema::RoutePrecedence;
+#[cfg(feature = "discovery")]
+use mcproxy_discovery::DiscoveredServers;
+
+pub mod balance;
+pub mod pattern;
+
+/// The upstreams a connection may be proxied to, along with how to handle it
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Never empty, and holds more than one upstream when connections are balanced over a pool
+    pub upstreams: Vec<Upstream>,
+    pub options: Arc<ServerOptions>,
+}
+
+impl Route {
+    /// The first upstream, which is the only one unless the route is a pool
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstreams[0]
+    }
+}
+
+impl From<Upstream> for Route {
+    fn from(upstream: Upstream) -> Self {
+        Route {
+            upstreams: vec![upstream],
+            options: Arc::default(),
+        }
+    }
+}
+
+/// Find the route that a handshake address should be proxied to
+///
+/// Exact mappings are tried first, ordering static and discovered servers by the configured
+/// precedence. Only when no exact mapping exists are wildcard entries in `static_servers`
+/// and then `pattern_servers` consulted.
+#[tracing::instrument(name = "routing::resolve", skip_all, fields(%hostname))]
+pub fn resolve_upstream(
+    config: &Config,
+    #[cfg(feature = "discovery")] discovered_servers: &DiscoveredServers,
+    hostname: &Hostname,
+) -> Option<Route> {
+    let static_upstream = || {
+        let upstream = config.static_servers.get_exact(hostname);
+
+        if upstream.is_some() {
+            trace!("found static mapping");
+        }
+
+        upstream
+    };
+
+    #[cfg(feature = "discovery")]
+    let exact_upstream = {
+        let discovered_upstream = || {
+            let upstreams = discovered_servers.get_by_hostname(hostname);
+            let upstream = (!upstreams.is_empty()).then(|| Route {
+                upstreams,
+                options: Arc::new(ServerOptions {
+                    balance: config
+                        .discovery
+                        .as_ref()
+                        .map(|discovery| discovery.balance)
+                        .unwrap_or_default(),
+                    ..Default::default()
+                }),
+            });
+
+            if upstream.is_some() {
+                trace!("found discovered mapping");
+            }
+
+            upstream
+        };
+
+        match config
+            .discovery
+            .as_ref()
+            .map(|discovery| discovery.precedence)
+        {
+            Some(RoutePrecedence::Static) => static_upstream().or_else(discovered_upstream),
+            Some(RoutePrecedence::Discovered) => discovered_upstream().or_else(static_upstream),
+            None => static_upstream(),
+        }
+    };
+
+    #[cfg(not(feature = "discovery"))]
+    let exact_upstream = static_upstream();
+
+    exact_upstream
+        .or_else(|| {
+            let upstream = config.static_servers.get_wildcard(hostname);
+
+            if upstream.is_some() {
+                trace!("found wildcard mapping");
+            }
+
+            upstream
+        })
+        .or_else(|| {
+            let upstream = config
+                .pattern_servers
+                .iter()
+                .find_map(|server| server.route(hostname));
+
+            if upstream.is_some() {
+                trace!("found pattern mapping");
+            }
+
+            upstream
+        })
+}
+
+#[cfg(all(test, feature = "discovery"))]
+mod test {
+    use mcproxy_discovery::{DiscoveredServers, ServerId};
+    use mcproxy_model::{Hostname, Upstream};
+
+    use super::resolve_upstream;
+    use crate::config::schema::Config;
+
+    #[test]
+    fn route_precedence() {
+        let config = |precedence: &str| {
+            toml::from_str::<Config>(&format!(
+                r#"
+                    [placeholder_server.responses]
+                    [proxy]
+                    listen_address = "127.0.0.1:25565"
+                    [static_servers]
+                    "both.example.com" = "127.0.0.1:25570"
+                    "static.example.com" = "127.0.0.1:25571"
+                    [discovery]
+                    precedence = "{precedence}"
+                "#
+            ))
+            .unwrap()
+        };
+
+        let discovered_servers = DiscoveredServers::default();
+        discovered_servers.insert_active(
+            ServerId::Docker("ab".repeat(32).parse().unwrap()),
+            vec![
+                Hostname::from("both.example.com"),
+                Hostname::from("discovered.example.com"),
+            ],
+            Upstream::from("10.0.0.2:25565".parse::<std::net::SocketAddr>().unwrap()),
+        );
+
+        let route = |config: &Config, hostname: &str| {
+            resolve_upstream(config, &discovered_servers, &Hostname::from(hostname))
+                .map(|route| route.upstream().to_string())
+        };
+
+        let static_first = config("static");
+        assert_eq!(
+            route(&static_first, "both.example.com").as_deref(),
+            Some("127.0.0.1:25570")
+        );
+        assert_eq!(
+            route(&static_first, "discovered.example.com").as_deref(),
+            Some("10.0.0.2:25565")
+        );
+
+        let discovered_first = config("discovered");
+        assert_eq!(
+            route(&discovered_first, "both.example.com").as_deref(),
+            Some("10.0.0.2:25565")
+        );
+        assert_eq!(
+            route(&discovered_first, "static.example.com").as_deref(),
+            Some("127.0.0.1:25571")
+        );
+    }
+}
@@ -0,0 +1,523 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::{self, Display, Formatter, Write},
+    sync::Arc,
+};
+
+use mcproxy_model::{Hostname, Upstream};
+use regex::Regex;
+use serde::Deserialize;
+
+use super::Route;
+use crate::config::schema::{ServerConfig, ServerOptions};
+
+/// A key in `static_servers`, matched either exactly or with `*` wildcards
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub enum HostnamePattern {
+    Exact(Hostname),
+    Wildcard(WildcardPattern),
+}
+
+impl TryFrom<String> for HostnamePattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        // Normalize the literal labels in the same way as the hostnames they are matched against
+        let pattern = Hostname::from(pattern);
+
+        if !pattern.as_ref().contains('*') {
+            return Ok(HostnamePattern::Exact(pattern));
+        }
+
+        let labels = pattern
+            .as_ref()
+            .split('.')
+            .map(|label| match label {
+                "*" => Ok(None),
+                label if label.contains('*') => Err(format!(
+                    "wildcard in {pattern:?} must replace a whole label, found {label:?}"
+                )),
+                label => Ok(Some(Arc::from(label))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(HostnamePattern::Wildcard(WildcardPattern { labels }))
+    }
+}
+
+impl Display for HostnamePattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HostnamePattern::Exact(hostname) => Display::fmt(hostname, f),
+            HostnamePattern::Wildcard(pattern) => Display::fmt(pattern, f),
+        }
+    }
+}
+
+/// A hostname where whole labels are replaced by `*`, each matching exactly one label
+///
+/// The labels matched by wildcards are captured from left to right as `{1}`, `{2}`, ...
+/// and the whole hostname as `{0}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WildcardPattern {
+    /// The labels of the pattern, where `None` is a wildcard
+    labels: Vec<Option<Arc<str>>>,
+}
+
+impl WildcardPattern {
+    fn wildcards(&self) -> usize {
+        self.labels.iter().filter(|label| label.is_none()).count()
+    }
+
+    /// Match the hostname against the pattern, returning the captured labels
+    pub fn captures<'h>(&self, hostname: &'h str) -> Option<Vec<&'h str>> {
+        if hostname.split('.').count() != self.labels.len() {
+            return None;
+        }
+
+        let mut captures = vec![hostname];
+
+        for (pattern, label) in self.labels.iter().zip(hostname.split('.')) {
+            match pattern {
+                None if !label.is_empty() => captures.push(label),
+                Some(pattern) if pattern.as_ref() == label => {}
+                _ => return None,
+            }
+        }
+
+        Some(captures)
+    }
+
+    /// Order patterns from most to least specific
+    ///
+    /// Labels are compared from the right, as in the DNS hierarchy, and a literal label is
+    /// more specific than a wildcard. Patterns of the same specificity are ordered
+    /// alphabetically so that the order never depends on the config file.
+    fn cmp_specificity(&self, other: &Self) -> Ordering {
+        let wildcards = |pattern: &Self| {
+            pattern
+                .labels
+                .iter()
+                .rev()
+                .map(Option::is_none)
+                .collect::<Vec<_>>()
+        };
+
+        wildcards(self)
+            .cmp(&wildcards(other))
+            .then_with(|| self.labels.cmp(&other.labels))
+    }
+}
+
+impl Display for WildcardPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, label) in self.labels.iter().enumerate() {
+            if index != 0 {
+                f.write_char('.')?;
+            }
+
+            f.write_str(label.as_deref().unwrap_or("*"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An upstream whose host may reference captures from the matched hostname as `{n}` or `{name}`
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct UpstreamTemplate(Upstream);
+
+impl UpstreamTemplate {
+    /// The names of all captures referenced by the template
+    fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.0.host.split('{').skip(1).filter_map(|rest| {
+            rest.split_once('}')
+                .map(|(placeholder, _)| placeholder.trim())
+        })
+    }
+
+    /// The upstream itself, if it does not reference any captures
+    pub fn as_upstream(&self) -> Option<&Upstream> {
+        self.placeholders().next().is_none().then_some(&self.0)
+    }
+
+    /// Substitute every placeholder with its capture, or nothing if the capture is missing
+    pub fn render<'c>(&self, capture: impl Fn(&str) -> Option<&'c str>) -> Upstream {
+        let mut host = String::with_capacity(self.0.host.len());
+        let mut rest = self.0.host.as_ref();
+
+        while let Some((before, after)) = rest.split_once('{') {
+            host.push_str(before);
+
+            match after.split_once('}') {
+                Some((placeholder, after)) => {
+                    host.push_str(capture(placeholder.trim()).unwrap_or_default());
+                    rest = after;
+                }
+                None => {
+                    host.push('{');
+                    rest = after;
+                }
+            }
+        }
+        host.push_str(rest);
+
+        Upstream {
+            host: Arc::from(host),
+            port: self.0.port,
+        }
+    }
+}
+
+impl Display for UpstreamTemplate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// The `static_servers` mapping, split into exact and wildcard entries
+#[derive(Deserialize, Debug, Default)]
+#[serde(try_from = "HashMap<HostnamePattern, ServerConfig>")]
+pub struct StaticServers {
+    /// Never references any captures
+    exact: HashMap<Hostname, ServerConfig>,
+    /// Sorted from most to least specific
+    wildcards: Vec<(WildcardPattern, ServerConfig)>,
+}
+
+impl TryFrom<HashMap<HostnamePattern, ServerConfig>> for StaticServers {
+    type Error = String;
+
+    fn try_from(map: HashMap<HostnamePattern, ServerConfig>) -> Result<Self, Self::Error> {
+        let mut servers = StaticServers::default();
+
+        for (pattern, server) in map {
+            if server.upstreams.is_empty() {
+                return Err(format!("{pattern} has an empty pool of upstreams"));
+            }
+
+            match pattern {
+                HostnamePattern::Exact(hostname) => {
+                    for upstream in &server.upstreams {
+                        if upstream.as_upstream().is_none() {
+                            return Err(format!(
+                                "{upstream} references a capture, but {hostname} has no wildcards"
+                            ));
+                        }
+                    }
+
+                    servers.exact.insert(hostname, server);
+                }
+                HostnamePattern::Wildcard(pattern) => {
+                    let wildcards = pattern.wildcards();
+
+                    for upstream in &server.upstreams {
+                        for placeholder in upstream.placeholders() {
+                            if !placeholder
+                                .parse::<usize>()
+                                .is_ok_and(|index| index <= wildcards)
+                            {
+                                return Err(format!(
+                                    "{upstream} references {{{placeholder}}}, but {pattern} only captures {{0}} to {{{wildcards}}}"
+                                ));
+                            }
+                        }
+                    }
+
+                    servers.wildcards.push((pattern, server));
+                }
+            }
+        }
+
+        servers
+            .wildcards
+            .sort_by(|(a, _), (b, _)| a.cmp_specificity(b));
+
+        Ok(servers)
+    }
+}
+
+impl StaticServers {
+    pub fn get_exact(&self, hostname: &Hostname) -> Option<Route> {
+        self.exact.get(hostname).map(|server| Route {
+            upstreams: server
+                .upstreams
+                .iter()
+                .map(|upstream| upstream.0.clone())
+                .collect(),
+            options: server.options.clone(),
+        })
+    }
+
+    /// Find the most specific wildcard entry matching the hostname
+    pub fn get_wildcard(&self, hostname: &Hostname) -> Option<Route> {
+        self.wildcards.iter().find_map(|(pattern, server)| {
+            let captures = pattern.captures(hostname.as_ref())?;
+
+            Some(Route {
+                upstreams: server
+                    .upstreams
+                    .iter()
+                    .map(|upstream| {
+                        upstream.render(|placeholder| {
+                            placeholder
+                                .parse::<usize>()
+                                .ok()
+                                .and_then(|index| captures.get(index).copied())
+                        })
+                    })
+                    .collect(),
+                options: server.options.clone(),
+            })
+        })
+    }
+
+    /// Every entry, with wildcard entries in order of specificity
+    pub fn entries(&self) -> impl Iterator<Item = (HostnamePattern, &ServerConfig)> + '_ {
+        let exact = self
+            .exact
+            .iter()
+            .map(|(hostname, server)| (HostnamePattern::Exact(hostname.clone()), server));
+        let wildcards = self
+            .wildcards
+            .iter()
+            .map(|(pattern, server)| (HostnamePattern::Wildcard(pattern.clone()), server));
+
+        exact.chain(wildcards)
+    }
+
+    /// The options of every entry, to be filled in once the config is loaded
+    pub fn options_mut(&mut self) -> impl Iterator<Item = &mut Arc<ServerOptions>> {
+        self.exact
+            .values_mut()
+            .chain(self.wildcards.iter_mut().map(|(_, server)| server))
+            .map(|server| &mut server.options)
+    }
+
+    /// Every upstream that does not depend on the connecting hostname, with its options
+    pub fn upstreams(&self) -> impl Iterator<Item = (&Upstream, &ServerOptions)> {
+        self.exact
+            .values()
+            .chain(self.wildcards.iter().map(|(_, server)| server))
+            .flat_map(|server| {
+                server
+                    .upstreams
+                    .iter()
+                    .filter_map(|upstream| Some((upstream.as_upstream()?, &*server.options)))
+            })
+    }
+}
+
+/// A regular expression, matched against the whole normalized hostname
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct HostnameRegex(Regex);
+
+impl TryFrom<String> for HostnameRegex {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&format!("^(?:{pattern})$")).map(HostnameRegex)
+    }
+}
+
+impl Display for HostnameRegex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let pattern = self.0.as_str();
+
+        f.write_str(&pattern["^(?:".len()..pattern.len() - ")$".len()])
+    }
+}
+
+/// A routing rule matching hostnames by regular expression
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "RawPatternServer")]
+pub struct PatternServer {
+    pub pattern: HostnameRegex,
+    pub upstream: UpstreamTemplate,
+    pub options: Arc<ServerOptions>,
+}
+
+/// A routing rule matching hostnames by regular expression
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct RawPatternServer {
+    /// Regular expression that must match the whole hostname
+    #[cfg_attr(test, schemars(with = "String"))]
+    pattern: HostnameRegex,
+    /// The upstream to route to
+    ///
+    /// The host may reference capture groups of the pattern by index (`{1}`) or by name (`{name}`)
+    upstream: UpstreamTemplate,
+    #[serde(flatten)]
+    options: ServerOptions,
+}
+
+impl TryFrom<RawPatternServer> for PatternServer {
+    type Error = String;
+
+    fn try_from(
+        RawPatternServer {
+            pattern,
+            upstream,
+            options,
+        }: RawPatternServer,
+    ) -> Result<Self, Self::Error> {
+        for placeholder in upstream.placeholders() {
+            let exists = match placeholder.parse::<usize>() {
+                Ok(index) => index < pattern.0.captures_len(),
+                Err(_) => pattern
+                    .0
+                    .capture_names()
+                    .flatten()
+                    .any(|name| name == placeholder),
+            };
+
+            if !exists {
+                return Err(format!(
+                    "{upstream} references {{{placeholder}}}, which is not a capture group of {pattern}"
+                ));
+            }
+        }
+
+        Ok(PatternServer {
+            pattern,
+            upstream,
+            options: Arc::new(options),
+        })
+    }
+}
+
+impl PatternServer {
+    pub fn route(&self, hostname: &Hostname) -> Option<Route> {
+        let captures = self.pattern.0.captures(hostname.as_ref())?;
+
+        Some(Route {
+            upstreams: vec![self.upstream.render(|placeholder| {
+                match placeholder.parse::<usize>() {
+                    Ok(index) => captures.get(index),
+                    Err(_) => captures.name(placeholder),
+                }
+                .map(|capture| capture.as_str())
+            })],
+            options: self.options.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use mcproxy_model::{Hostname, Upstream};
+
+    use super::{HostnamePattern, PatternServer, StaticServers, UpstreamTemplate};
+    use crate::config::schema::ServerConfig;
+
+    fn upstream(upstream: &str) -> UpstreamTemplate {
+        let (host, port) = upstream.split_once(':').unwrap();
+
+        UpstreamTemplate(Upstream {
+            host: host.into(),
+            port: port.parse().unwrap(),
+        })
+    }
+
+    fn static_servers(entries: &[(&str, &str)]) -> Result<StaticServers, String> {
+        StaticServers::try_from(
+            entries
+                .iter()
+                .map(|(pattern, target)| {
+                    (
+                        HostnamePattern::try_from(pattern.to_string()).unwrap(),
+                        ServerConfig {
+                            upstreams: vec![upstream(target)],
+                            options: Default::default(),
+                        },
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    fn route(servers: &StaticServers, hostname: &str) -> Option<String> {
+        let hostname = Hostname::from(hostname);
+
+        servers
+            .get_exact(&hostname)
+            .or_else(|| servers.get_wildcard(&hostname))
+            .map(|route| route.upstream().to_string())
+    }
+
+    #[test]
+    fn wildcard_specificity() {
+        let servers = static_servers(&[
+            ("lobby.survival.example.com", "lobby:25565"),
+            ("*.survival.example.com", "{1}.internal:25565"),
+            ("*.*.example.com", "{2}-{1}.internal:25565"),
+            ("a.*.example.com", "a.internal:25565"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            route(&servers, "lobby.survival.example.com").as_deref(),
+            Some("lobby:25565")
+        );
+        assert_eq!(
+            route(&servers, "a.survival.example.com").as_deref(),
+            Some("a.internal:25565")
+        );
+        assert_eq!(
+            route(&servers, "b.survival.example.com").as_deref(),
+            Some("b.internal:25565")
+        );
+        assert_eq!(
+            route(&servers, "b.creative.example.com").as_deref(),
+            Some("creative-b.internal:25565")
+        );
+        assert_eq!(route(&servers, "survival.example.com"), None);
+        assert_eq!(route(&servers, "c.b.survival.example.com"), None);
+    }
+
+    #[test]
+    fn invalid_static_servers() {
+        assert!(HostnamePattern::try_from("a*.example.com".to_string()).is_err());
+        assert!(static_servers(&[("example.com", "{1}.internal:25565")]).is_err());
+        assert!(static_servers(&[("*.example.com", "{2}.internal:25565")]).is_err());
+    }
+
+    #[test]
+    fn regex_captures() {
+        let server = PatternServer::try_from(super::RawPatternServer {
+            pattern: r"(?<world>\w+)-(\d+)\.example\.com"
+                .to_string()
+                .try_into()
+                .unwrap(),
+            upstream: upstream("{world}-{2}.internal:25565"),
+            options: Default::default(),
+        })
+        .unwrap();
+
+        assert_eq!(
+            server
+                .route(&Hostname::from("skyblock-3.example.com"))
+                .map(|route| route.upstream().to_string())
+                .as_deref(),
+            Some("skyblock-3.internal:25565")
+        );
+        assert!(server
+            .route(&Hostname::from("skyblock-3.example.com.evil"))
+            .is_none());
+
+        assert!(PatternServer::try_from(super::RawPatternServer {
+            pattern: r"(\w+)\.example\.com".to_string().try_into().unwrap(),
+            upstream: upstream("{name}.internal:25565"),
+            options: Default::default(),
+        })
+        .is_err());
+    }
+}
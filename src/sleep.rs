@@ -0,0 +1,356 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use mcproxy_model::Upstream;
+use tokio::{io, process::Command, sync::watch::Receiver, task, time::sleep};
+use tracing::{error, info};
+use tracing_error::{InstrumentError, TracedError};
+
+use crate::{config::schema::Config, health::UpstreamHealth};
+
+/// How long to wait before looking at the config again while sleep is not configured
+const UNCONFIGURED_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where an upstream put to sleep by its stop command is at
+#[derive(Debug, Clone, Copy)]
+pub enum SleepState {
+    /// The stop command was run, at the given time
+    #[cfg_attr(not(feature = "ui"), allow(dead_code))]
+    Asleep(Instant),
+    /// The start command was run, at the given time, but the upstream has not accepted a
+    /// connection since
+    Waking(Instant),
+}
+
+impl SleepState {
+    #[cfg(feature = "ui")]
+    pub fn since(&self) -> Instant {
+        match *self {
+            SleepState::Asleep(since) | SleepState::Waking(since) => since,
+        }
+    }
+}
+
+impl Display for SleepState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SleepState::Asleep(_) => f.write_str("asleep"),
+            SleepState::Waking(_) => f.write_str("waking"),
+        }
+    }
+}
+
+/// The upstreams with a stop command, and whether they are sleeping
+#[derive(Debug, Default)]
+pub struct SleepingServers {
+    /// When each watched upstream last had players online, or was first seen without any
+    last_active: DashMap<Upstream, Instant>,
+
+    states: DashMap<Upstream, SleepState>,
+
+    /// How long each upstream took to accept a connection the last time it was woken
+    wake_durations: DashMap<Upstream, Duration>,
+}
+
+impl SleepingServers {
+    pub fn state(&self, upstream: &Upstream) -> Option<SleepState> {
+        self.states.get(upstream).map(|state| *state)
+    }
+
+    /// Snapshot of every upstream that is asleep or waking
+    pub fn states(&self) -> Vec<(Upstream, SleepState)> {
+        self.states
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Amount of upstreams that are asleep or waking
+    #[cfg(feature = "metrics")]
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Run the start command of an upstream that is asleep
+    ///
+    /// Returns `false` if the upstream is not asleep, including when it is already waking.
+    pub fn wake(self: &Arc<Self>, upstream: &Upstream, start_command: &[String]) -> bool {
+        match self.states.get_mut(upstream) {
+            Some(mut state) if matches!(*state, SleepState::Asleep(_)) => {
+                *state = SleepState::Waking(Instant::now());
+            }
+            _ => return false,
+        }
+
+        task::spawn({
+            let (sleeping_servers, upstream, start_command) =
+                (self.clone(), upstream.clone(), start_command.to_vec());
+
+            async move {
+                if let Err(error) = run_command(&start_command).await {
+                    error!(%upstream, %error, "failed to wake upstream");
+
+                    sleeping_servers
+                        .states
+                        .insert(upstream, SleepState::Asleep(Instant::now()));
+                }
+            }
+        });
+
+        true
+    }
+
+    /// Record that an upstream accepted a connection, and so is awake
+    pub fn awake(&self, upstream: &Upstream) {
+        self.awake_at(upstream, Instant::now());
+    }
+
+    /// Record that an upstream was seen up at the given time, and so is awake
+    fn awake_at(&self, upstream: &Upstream, at: Instant) {
+        let Some((_, state)) = self.states.remove(upstream) else {
+            return;
+        };
+
+        if let SleepState::Waking(since) = state {
+            self.wake_durations
+                .insert(upstream.clone(), at.saturating_duration_since(since));
+        }
+        self.last_active.insert(upstream.clone(), at);
+    }
+
+    /// Mark waking upstreams awake once a health check made since they were woken sees them up,
+    /// even if no player connects to them
+    fn awake_healthy(&self, upstream_health: &UpstreamHealth) {
+        for (upstream, state) in self.states() {
+            let SleepState::Waking(since) = state else {
+                continue;
+            };
+
+            if let Some(check) = upstream_health
+                .get(&upstream)
+                .filter(|check| check.healthy && check.checked > since)
+            {
+                self.awake_at(&upstream, check.checked);
+            }
+        }
+    }
+
+    /// How much longer a waking upstream should take to accept a connection, going by how long
+    /// it took the last time
+    pub fn wake_eta(&self, upstream: &Upstream) -> Option<Duration> {
+        let Some(SleepState::Waking(since)) = self.state(upstream) else {
+            return None;
+        };
+        let took = *self.wake_durations.get(upstream)?;
+
+        Some(took.saturating_sub(since.elapsed()))
+    }
+
+    /// Run the stop command of an upstream, marking it asleep
+    fn put_to_sleep(self: &Arc<Self>, upstream: Upstream, stop_command: Vec<String>) {
+        self.states
+            .insert(upstream.clone(), SleepState::Asleep(Instant::now()));
+        self.last_active.remove(&upstream);
+
+        task::spawn({
+            let sleeping_servers = self.clone();
+
+            async move {
+                if let Err(error) = run_command(&stop_command).await {
+                    error!(%upstream, %error, "failed to put upstream to sleep");
+
+                    sleeping_servers.states.remove(&upstream);
+                }
+            }
+        });
+    }
+
+    /// Record how many players an upstream has, returning whether it has been idle for too long
+    fn record_players(&self, upstream: &Upstream, online: u32, idle_timeout: Duration) -> bool {
+        if online > 0 {
+            self.last_active.insert(upstream.clone(), Instant::now());
+
+            return false;
+        }
+
+        let idle_since = *self
+            .last_active
+            .entry(upstream.clone())
+            .or_insert_with(Instant::now);
+
+        idle_since.elapsed() >= idle_timeout
+    }
+}
+
+/// Run a stop or start command, failing if it does not exit successfully
+#[tracing::instrument(err)]
+async fn run_command(command: &[String]) -> Result<(), TracedError<io::Error>> {
+    let Some((program, arguments)) = command.split_first() else {
+        return Err(io::Error::other("command is empty").in_current_span());
+    };
+
+    let status = Command::new(program)
+        .args(arguments)
+        .status()
+        .await
+        .map_err(InstrumentError::in_current_span)?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("command exited with {status}")).in_current_span()),
+    }
+}
+
+/// What puts an upstream to sleep
+enum Sleeper {
+    StopCommand(Vec<String>),
+    #[cfg(feature = "discovery")]
+    Discovery(mcproxy_discovery::ServerId),
+}
+
+/// Periodically look at how many players every upstream that can be put to sleep has, putting
+/// idle ones to sleep
+pub async fn watch(
+    config: Receiver<Arc<Config>>,
+    sleeping_servers: Arc<SleepingServers>,
+    upstream_health: Arc<UpstreamHealth>,
+    #[cfg(feature = "discovery")] discovered_servers: Arc<mcproxy_discovery::DiscoveredServers>,
+    #[cfg(feature = "metrics")] sleep_metrics: crate::metrics::SleepMetrics,
+) {
+    info!("watching for idle servers");
+
+    loop {
+        sleeping_servers.awake_healthy(&upstream_health);
+
+        let config = config.borrow().clone();
+        let Some(sleep_config) = config.sleep else {
+            sleep(UNCONFIGURED_INTERVAL).await;
+            continue;
+        };
+
+        let mut sleepers = Vec::new();
+
+        for (upstream, options) in config.static_servers.upstreams() {
+            let Some(stop_command) = &options.stop_command else {
+                continue;
+            };
+
+            // Sleeping upstreams are woken by players, not by the watcher
+            if sleeping_servers.state(upstream).is_some() {
+                continue;
+            }
+
+            sleepers.push((upstream.clone(), Sleeper::StopCommand(stop_command.clone())));
+        }
+
+        #[cfg(feature = "discovery")]
+        if config
+            .discovery
+            .as_ref()
+            .is_some_and(|discovery| discovery.sleep_when_idle)
+        {
+            for (server_id, upstream) in discovered_servers.active_servers() {
+                sleepers.push((upstream, Sleeper::Discovery(server_id)));
+            }
+        }
+
+        for (upstream, sleeper) in sleepers {
+            let online = upstream_health
+                .get(&upstream)
+                .and_then(|check| check.players_online());
+
+            let Some(online) = online else {
+                // An upstream that can not be reached can not be put to sleep either
+                sleeping_servers.last_active.remove(&upstream);
+                continue;
+            };
+
+            if !sleeping_servers.record_players(&upstream, online, sleep_config.idle_timeout()) {
+                continue;
+            }
+
+            let put_to_sleep = match sleeper {
+                Sleeper::StopCommand(stop_command) => {
+                    sleeping_servers.put_to_sleep(upstream.clone(), stop_command);
+                    true
+                }
+                #[cfg(feature = "discovery")]
+                Sleeper::Discovery(server_id) => {
+                    sleeping_servers.last_active.remove(&upstream);
+                    discovered_servers.put_to_sleep(server_id)
+                }
+            };
+
+            if put_to_sleep {
+                info!(%upstream, "put idle upstream to sleep");
+
+                #[cfg(feature = "metrics")]
+                sleep_metrics.servers_put_to_sleep.inc();
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        {
+            #[cfg(feature = "discovery")]
+            let sleeping = sleeping_servers.len() + discovered_servers.sleeping_len();
+            #[cfg(not(feature = "discovery"))]
+            let sleeping = sleeping_servers.len();
+
+            sleep_metrics.servers_sleeping.set(sleeping as i64);
+        }
+
+        sleep(sleep_config.check_interval()).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use mcproxy_model::Upstream;
+
+    use super::{SleepState, SleepingServers};
+    use crate::health::UpstreamHealth;
+
+    #[test]
+    fn idle_timeout() {
+        let sleeping_servers = SleepingServers::default();
+        let upstream = Upstream::from("127.0.0.1:25565".parse::<std::net::SocketAddr>().unwrap());
+
+        assert!(!sleeping_servers.record_players(&upstream, 0, Duration::from_secs(60)));
+        assert!(sleeping_servers.record_players(&upstream, 0, Duration::ZERO));
+
+        // Players joining reset the timer
+        assert!(!sleeping_servers.record_players(&upstream, 2, Duration::ZERO));
+        assert!(!sleeping_servers.record_players(&upstream, 0, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn awake_once_healthy() {
+        let sleeping_servers = SleepingServers::default();
+        let upstream_health = UpstreamHealth::default();
+        let upstream = Upstream::from("127.0.0.1:25565".parse::<std::net::SocketAddr>().unwrap());
+
+        sleeping_servers
+            .states
+            .insert(upstream.clone(), SleepState::Waking(Instant::now()));
+
+        // Not checked at all yet, and then found down
+        sleeping_servers.awake_healthy(&upstream_health);
+        upstream_health.record_ping(upstream.clone(), None);
+        sleeping_servers.awake_healthy(&upstream_health);
+        assert!(matches!(
+            sleeping_servers.state(&upstream),
+            Some(SleepState::Waking(_))
+        ));
+
+        upstream_health.record_connection(&upstream, true);
+        sleeping_servers.awake_healthy(&upstream_health);
+        assert!(sleeping_servers.state(&upstream).is_none());
+        assert!(sleeping_servers.wake_durations.contains_key(&upstream));
+    }
+}
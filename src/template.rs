@@ -0,0 +1,203 @@
+use std::time::{Duration, SystemTime};
+
+use mcproxy_model::{Hostname, Upstream};
+
+use crate::proto::packet::response::StatusResponse;
+
+/// Release names of the protocol versions clients announce in their handshake
+const VERSION_NAMES: &[(i32, &str)] = &[
+    (4, "1.7.2"),
+    (5, "1.7.10"),
+    (47, "1.8"),
+    (73, "1.6.1"),
+    (74, "1.6.2"),
+    (78, "1.6.4"),
+    (107, "1.9"),
+    (108, "1.9.1"),
+    (109, "1.9.2"),
+    (110, "1.9.4"),
+    (210, "1.10"),
+    (315, "1.11"),
+    (316, "1.11.2"),
+    (335, "1.12"),
+    (338, "1.12.1"),
+    (340, "1.12.2"),
+    (393, "1.13"),
+    (401, "1.13.1"),
+    (404, "1.13.2"),
+    (477, "1.14"),
+    (480, "1.14.1"),
+    (485, "1.14.2"),
+    (490, "1.14.3"),
+    (498, "1.14.4"),
+    (573, "1.15"),
+    (575, "1.15.1"),
+    (578, "1.15.2"),
+    (735, "1.16"),
+    (736, "1.16.1"),
+    (751, "1.16.2"),
+    (753, "1.16.3"),
+    (754, "1.16.5"),
+    (755, "1.17"),
+    (756, "1.17.1"),
+    (757, "1.18.1"),
+    (758, "1.18.2"),
+    (759, "1.19"),
+    (760, "1.19.2"),
+    (761, "1.19.3"),
+    (762, "1.19.4"),
+    (763, "1.20.1"),
+    (764, "1.20.2"),
+    (765, "1.20.4"),
+    (766, "1.20.6"),
+    (767, "1.21.1"),
+    (768, "1.21.3"),
+    (769, "1.21.4"),
+    (770, "1.21.5"),
+    (771, "1.21.6"),
+    (772, "1.21.8"),
+];
+
+/// What is known about a connection and the proxy, substituted into placeholder responses
+///
+/// Text may reference `{hostname}`, `{upstream}`, `{client_version}`, `{last_seen_online}`,
+/// `{players_online_network}` and `{wake_eta}`. Anything else in braces is left as is.
+#[derive(Debug)]
+pub struct TemplateContext<'c> {
+    /// The address the client connected to
+    pub hostname: &'c Hostname,
+    /// The upstream the hostname routes to
+    pub upstream: Option<&'c Upstream>,
+    /// The protocol version from the client's handshake
+    pub protocol_version: i32,
+    /// When the upstream last answered a ping
+    pub last_seen_online: Option<SystemTime>,
+    /// Players online across every healthy upstream
+    pub players_online_network: u32,
+    /// How much longer the upstream should take to start
+    pub wake_eta: Option<Duration>,
+}
+
+impl TemplateContext<'_> {
+    fn value(&self, variable: &str) -> Option<String> {
+        Some(match variable {
+            "hostname" => self.hostname.to_string(),
+            "upstream" => self
+                .upstream
+                .map_or_else(|| "unknown".to_string(), ToString::to_string),
+            "client_version" => VERSION_NAMES
+                .iter()
+                .find(|(protocol_version, _)| *protocol_version == self.protocol_version)
+                .map_or_else(
+                    || format!("protocol {}", self.protocol_version),
+                    |(_, name)| name.to_string(),
+                ),
+            "last_seen_online" => self
+                .last_seen_online
+                .and_then(|time| time.elapsed().ok())
+                .map_or_else(
+                    || "never".to_string(),
+                    |elapsed| format!("{} ago", format_duration(elapsed)),
+                ),
+            "players_online_network" => self.players_online_network.to_string(),
+            "wake_eta" => self
+                .wake_eta
+                .map_or_else(|| "unknown".to_string(), format_duration),
+            _ => return None,
+        })
+    }
+
+    /// Substitute every known variable in the text
+    pub fn render(&self, text: &str) -> String {
+        let mut rendered = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let value = rest
+                .find('}')
+                .and_then(|end| Some((end, self.value(&rest[1..end])?)));
+            match value {
+                Some((end, value)) => {
+                    rendered.push_str(&value);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        rendered.push_str(rest);
+
+        rendered
+    }
+
+    /// The response with every variable in its text substituted
+    pub fn render_response(&self, response: &StatusResponse) -> StatusResponse {
+        response.map_text(&|text| self.render(text))
+    }
+}
+
+/// Round the duration to its largest whole unit
+fn format_duration(duration: Duration) -> String {
+    match duration.as_secs() {
+        seconds @ ..60 => format!("{seconds}s"),
+        seconds @ ..3600 => format!("{}m", seconds / 60),
+        seconds @ ..86400 => format!("{}h", seconds / 3600),
+        seconds => format!("{}d", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::SocketAddr,
+        time::{Duration, SystemTime},
+    };
+
+    use mcproxy_model::{Hostname, Upstream};
+
+    use super::TemplateContext;
+
+    #[test]
+    fn render() {
+        let hostname = Hostname::from("survival.example.com");
+        let upstream = Upstream::from("10.0.0.5:25565".parse::<SocketAddr>().unwrap());
+        let context = TemplateContext {
+            hostname: &hostname,
+            upstream: Some(&upstream),
+            protocol_version: 767,
+            last_seen_online: Some(SystemTime::now() - Duration::from_secs(150)),
+            players_online_network: 42,
+            wake_eta: None,
+        };
+
+        assert_eq!(
+            context.render("{hostname} ({upstream}) on {client_version}"),
+            "survival.example.com (10.0.0.5:25565) on 1.21.1"
+        );
+        assert_eq!(
+            context.render(
+                "seen {last_seen_online}, back in {wake_eta}, {players_online_network} online"
+            ),
+            "seen 2m ago, back in unknown, 42 online"
+        );
+        // Unknown variables and stray braces are kept
+        assert_eq!(context.render("{ {name}} {hostname"), "{ {name}} {hostname");
+
+        let context = TemplateContext {
+            upstream: None,
+            protocol_version: 1,
+            last_seen_online: None,
+            wake_eta: Some(Duration::from_secs(30)),
+            ..context
+        };
+        assert_eq!(
+            context.render("{upstream} {client_version} {last_seen_online} {wake_eta}"),
+            "unknown protocol 1 never 30s"
+        );
+    }
+}
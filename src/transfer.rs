@@ -0,0 +1,82 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use mcproxy_model::Hostname;
+
+/// How long a player transferred out of limbo has to come back
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Transfers issued by limbo, which the player comes back with as if it were logging in
+///
+/// Players are told by limbo to connect to the address they first connected to, so they are
+/// recognized by their IP and that address. Upstreams only ever see them logging in, whether
+/// they accept transfers or not.
+#[derive(Debug, Default)]
+pub struct LimboTransfers {
+    /// When each pending transfer was issued, several players can share an IP
+    issued: DashMap<(IpAddr, Hostname), Vec<Instant>>,
+}
+
+impl LimboTransfers {
+    /// Record that a player was transferred back to a hostname
+    pub fn issue(&self, ip: IpAddr, hostname: Hostname) {
+        let now = Instant::now();
+
+        // Players that never came back are forgotten whenever another one is transferred
+        self.issued.retain(|_, issued| {
+            issued.retain(|issued| now.duration_since(*issued) < TRANSFER_TIMEOUT);
+            !issued.is_empty()
+        });
+
+        self.issued.entry((ip, hostname)).or_default().push(now);
+    }
+
+    /// Whether a transfer to a hostname was issued to the IP recently, forgetting it if so
+    pub fn take(&self, ip: IpAddr, hostname: &Hostname) -> bool {
+        let key = (ip, hostname.clone());
+
+        let Some(mut issued) = self.issued.get_mut(&key) else {
+            return false;
+        };
+        issued.retain(|issued| issued.elapsed() < TRANSFER_TIMEOUT);
+        let taken = issued.pop().is_some();
+        drop(issued);
+
+        self.issued.remove_if(&key, |_, issued| issued.is_empty());
+
+        taken
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use mcproxy_model::Hostname;
+
+    use super::LimboTransfers;
+
+    #[test]
+    fn take_issued() {
+        let transfers = LimboTransfers::default();
+        let (ip, other_ip) = (
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+        );
+        let hostname = Hostname::from("play.example.com");
+
+        transfers.issue(ip, hostname.clone());
+        transfers.issue(ip, hostname.clone());
+
+        assert!(!transfers.take(other_ip, &hostname));
+        assert!(!transfers.take(ip, &Hostname::from("other.example.com")));
+
+        // Each transfer is only taken once
+        assert!(transfers.take(ip, &hostname));
+        assert!(transfers.take(ip, &hostname));
+        assert!(!transfers.take(ip, &hostname));
+    }
+}
@@ -1,17 +1,26 @@
 use std::{
-    collections::HashMap,
     fmt::{Display, Write},
     sync::Arc,
+    time::SystemTime,
 };
 
 use crate::{
     config::schema::{
-        Config, PlaceholderServerConfig, PlaceholderServerResponses, ProxyConfig, UiServerConfig,
+        AccessConfig, Config, DiscoveryConfig, HealthCheckConfig, LimboConfig, MaintenanceConfig,
+        MaintenanceWindow, PlaceholderServerConfig, PlaceholderServerResponses, ProxyConfig,
+        RateLimitConfig, SleepConfig, UiServerConfig,
     },
-    proto::packet::{
-        response::{Player, Players, StatusResponse, Version},
-        ElaboratedTextComponent,
+    health::UpstreamHealth,
+    maintenance::Maintenance,
+    proto::{
+        limits::ProtocolLimits,
+        packet::{
+            response::{Player, Players, StatusResponse, Version},
+            ElaboratedTextComponent,
+        },
     },
+    routing::{balance::LoadBalancer, pattern::PatternServer},
+    sleep::SleepingServers,
 };
 
 struct Unindenter<W>(W);
@@ -56,7 +65,42 @@ impl<W: Write> Write for Unindenter<W> {
     }
 }
 
-pub fn config_table(config: Arc<Config>) -> String {
+/// Displays a value with the characters HTML gives a meaning escaped, so it is only ever text
+struct Escaped<T>(T);
+
+impl<T: Display> Display for Escaped<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Escaper<'f, 'a>(&'f mut std::fmt::Formatter<'a>);
+
+        impl Write for Escaper<'_, '_> {
+            fn write_str(&mut self, s: &str) -> std::fmt::Result {
+                for char in s.chars() {
+                    match char {
+                        '&' => self.0.write_str("&amp;")?,
+                        '<' => self.0.write_str("&lt;")?,
+                        '>' => self.0.write_str("&gt;")?,
+                        '"' => self.0.write_str("&quot;")?,
+                        '\'' => self.0.write_str("&#39;")?,
+                        char => self.0.write_char(char)?,
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        write!(Escaper(f), "{}", self.0)
+    }
+}
+
+pub fn config_table(
+    config: Arc<Config>,
+    #[cfg(feature = "discovery")] discovered_servers: &mcproxy_discovery::DiscoveredServers,
+    sleeping_servers: &SleepingServers,
+    upstream_health: &UpstreamHealth,
+    load_balancer: &LoadBalancer,
+    maintenance_overrides: &Maintenance,
+) -> String {
     let mut html = Unindenter(String::new());
 
     write!(
@@ -86,41 +130,522 @@ pub fn config_table(config: Arc<Config>) -> String {
         let Config {
             placeholder_server,
             static_servers,
+            pattern_servers,
+            hostname_suffix_separators,
+            default_upstream,
             ui,
             proxy,
+            discovery,
+            sleep,
+            health_check,
+            maintenance,
+            access,
         } = config.as_ref();
 
         if let Some(UiServerConfig { listen_address }) = ui {
             config_value(&mut html, &"ui.listen_address", &|w| {
-                write!(w, "{listen_address}").unwrap()
+                write!(w, "{}", Escaped(listen_address)).unwrap()
             });
         }
 
         {
-            let ProxyConfig { listen_address } = proxy;
+            let ProxyConfig {
+                listen_address,
+                trusted_proxies,
+                limits,
+                rate_limit,
+            } = proxy;
             config_value(&mut html, &"proxy.listen_address", &|w| {
-                write!(w, "{listen_address}").unwrap()
+                write!(w, "{}", Escaped(listen_address)).unwrap()
+            });
+
+            if !trusted_proxies.is_empty() {
+                config_value(&mut html, &"proxy.trusted_proxies", &|w| {
+                    table(w, None, &|w| {
+                        for network in trusted_proxies {
+                            tr_td(w, &|w| write!(w, "{}", Escaped(network)).unwrap());
+                        }
+                    });
+                });
+            }
+
+            let ProtocolLimits {
+                handshake_packet_length,
+                status_packet_length,
+                login_packet_length,
+                upstream_packet_length,
+                string_length,
+            } = limits;
+            config_value(&mut html, &"proxy.limits", &|w| {
+                table(w, None, &|w| {
+                    config_value(w, &"handshake_packet_length", &|w| {
+                        write!(w, "{handshake_packet_length} bytes").unwrap()
+                    });
+                    config_value(w, &"status_packet_length", &|w| {
+                        write!(w, "{status_packet_length} bytes").unwrap()
+                    });
+                    config_value(w, &"login_packet_length", &|w| {
+                        write!(w, "{login_packet_length} bytes").unwrap()
+                    });
+                    config_value(w, &"upstream_packet_length", &|w| {
+                        write!(w, "{upstream_packet_length} bytes").unwrap()
+                    });
+                    config_value(w, &"string_length", &|w| {
+                        write!(w, "{string_length} characters").unwrap()
+                    });
+                });
             });
+
+            let RateLimitConfig {
+                per_ip,
+                per_network,
+                max_handshaking,
+                ban,
+            } = rate_limit;
+            if per_ip.is_some() || per_network.is_some() || max_handshaking.is_some() {
+                config_value(&mut html, &"proxy.rate_limit", &|w| {
+                    table(w, None, &|w| {
+                        if let Some(per_ip) = per_ip {
+                            config_value(w, &"per_ip", &|w| {
+                                write!(
+                                    w,
+                                    "{} connections, then {}/s",
+                                    per_ip.burst, per_ip.per_second
+                                )
+                                .unwrap()
+                            });
+                        }
+                        if let Some(per_network) = per_network {
+                            config_value(w, &"per_network", &|w| {
+                                write!(
+                                    w,
+                                    "{} connections, then {}/s (per /{} or /{})",
+                                    per_network.bucket.burst,
+                                    per_network.bucket.per_second,
+                                    per_network.ipv4_prefix,
+                                    per_network.ipv6_prefix
+                                )
+                                .unwrap()
+                            });
+                        }
+                        if let Some(max_handshaking) = max_handshaking {
+                            config_value(w, &"max_handshaking", &|w| {
+                                write!(w, "{max_handshaking} connections").unwrap()
+                            });
+                        }
+                        if let Some(ban) = ban {
+                            config_value(w, &"ban", &|w| {
+                                write!(
+                                    w,
+                                    "{}s after {} strikes within {}s",
+                                    ban.duration, ban.strikes, ban.window
+                                )
+                                .unwrap()
+                            });
+                        }
+                    });
+                });
+            }
         }
 
         config_value(&mut html, &"static_servers", &|w| {
-            kv_mapping(w, static_servers);
+            kv_mapping(w, static_servers.entries());
         });
 
+        if !pattern_servers.is_empty() {
+            config_value(&mut html, &"pattern_servers", &|w| {
+                table(w, None, &|w| {
+                    for PatternServer {
+                        pattern,
+                        upstream,
+                        options,
+                    } in pattern_servers
+                    {
+                        write!(
+                            w,
+                            r#"<tr><th scope="row"><code>{}</code></th><td>{}"#,
+                            Escaped(pattern),
+                            Escaped(upstream)
+                        )
+                        .unwrap();
+                        let options = options.to_string();
+                        if !options.is_empty() {
+                            write!(w, " ({})", Escaped(options)).unwrap();
+                        }
+                        write!(w, "</td></tr>").unwrap();
+                    }
+                });
+            });
+        }
+
+        if !hostname_suffix_separators.is_empty() {
+            config_value(&mut html, &"hostname_suffix_separators", &|w| {
+                table(w, None, &|w| {
+                    for separator in hostname_suffix_separators {
+                        tr_td(w, &|w| {
+                            write!(w, "<code>{}</code>", Escaped(format!("{separator:?}"))).unwrap()
+                        });
+                    }
+                });
+            });
+        }
+
+        if !default_upstream.is_empty() {
+            config_value(&mut html, &"default_upstream", &|w| {
+                table(w, None, &|w| {
+                    for upstream in default_upstream {
+                        tr_td(w, &|w| write!(w, "{}", Escaped(upstream)).unwrap());
+                    }
+                });
+            });
+        }
+
+        if let Some(DiscoveryConfig {
+            precedence,
+            wake_on_connect,
+            sleep_when_idle,
+            balance,
+        }) = discovery
         {
-            let PlaceholderServerConfig { responses } = placeholder_server;
+            config_value(&mut html, &"discovery.precedence", &|w| {
+                write!(w, "{precedence}").unwrap()
+            });
+            config_value(&mut html, &"discovery.wake_on_connect", &|w| {
+                write!(w, "{wake_on_connect}").unwrap()
+            });
+            config_value(&mut html, &"discovery.sleep_when_idle", &|w| {
+                write!(w, "{sleep_when_idle}").unwrap()
+            });
+            config_value(&mut html, &"discovery.balance", &|w| {
+                write!(w, "{balance}").unwrap()
+            });
+
+            #[cfg(feature = "discovery")]
+            config_value(&mut html, &"discovered_servers", &|w| {
+                let mut mappings = discovered_servers.mappings();
+                mappings.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+                table(w, None, &|w| {
+                    for (hostname, server_id, upstream) in &mappings {
+                        write!(
+                            w,
+                            r#"<tr><th scope="row">{}</th><td>{}</td><td>{}</td></tr>"#,
+                            Escaped(hostname),
+                            Escaped(upstream),
+                            Escaped(server_id)
+                        )
+                        .unwrap();
+                    }
+                });
+            });
+
+            #[cfg(feature = "discovery")]
+            config_value(&mut html, &"stopped_servers", &|w| {
+                let mut mappings = discovered_servers.stopped_mappings();
+                mappings.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+                table(w, None, &|w| {
+                    for (hostname, server_id) in &mappings {
+                        let state = match (
+                            discovered_servers.starting_since(hostname),
+                            discovered_servers.sleeping_since(hostname),
+                        ) {
+                            (Some(_), _) => "starting",
+                            (None, Some(_)) => "asleep",
+                            (None, None) => "stopped",
+                        };
+
+                        write!(
+                            w,
+                            r#"<tr><th scope="row">{}</th><td>{state}</td><td>{}</td></tr>"#,
+                            Escaped(hostname),
+                            Escaped(server_id)
+                        )
+                        .unwrap();
+                    }
+                });
+            });
+        }
+
+        {
+            let HealthCheckConfig { interval, timeout } = health_check;
+            config_value(&mut html, &"health_check.interval", &|w| {
+                write!(w, "{interval}s").unwrap()
+            });
+            config_value(&mut html, &"health_check.timeout", &|w| {
+                write!(w, "{timeout}ms").unwrap()
+            });
+
+            config_value(&mut html, &"upstream_health", &|w| {
+                let mut checks = upstream_health.checks();
+                checks.sort_by_key(|(upstream, _)| upstream.to_string());
+
+                table(w, None, &|w| {
+                    for (upstream, check) in &checks {
+                        let state = match check.healthy {
+                            true => "healthy",
+                            false => "unhealthy",
+                        };
+                        let latency = check
+                            .response
+                            .as_ref()
+                            .filter(|_| check.healthy)
+                            .map_or_else(
+                                || "?".to_string(),
+                                |(latency, _)| latency.as_millis().to_string(),
+                            );
+                        let players = check
+                            .players_online()
+                            .map_or_else(|| "?".to_string(), |players| players.to_string());
+                        let connections = load_balancer.connections(upstream);
+                        let seconds = check.checked.elapsed().as_secs();
+
+                        write!(
+                            w,
+                            r#"<tr><th scope="row">{}</th><td>{state}</td><td>{latency}ms</td><td>{players} players</td><td>{connections} connections</td><td>{seconds}s ago</td></tr>"#,
+                            Escaped(upstream)
+                        )
+                        .unwrap();
+                    }
+                });
+            });
+        }
+
+        if let Some(SleepConfig {
+            idle_timeout,
+            check_interval,
+        }) = sleep
+        {
+            config_value(&mut html, &"sleep.idle_timeout", &|w| {
+                write!(w, "{idle_timeout}s").unwrap()
+            });
+            config_value(&mut html, &"sleep.check_interval", &|w| {
+                write!(w, "{check_interval}s").unwrap()
+            });
+
+            config_value(&mut html, &"sleeping_servers", &|w| {
+                let mut states = sleeping_servers.states();
+                states.sort_by_key(|(upstream, _)| upstream.to_string());
+
+                table(w, None, &|w| {
+                    for (upstream, state) in &states {
+                        let seconds = state.since().elapsed().as_secs();
+
+                        write!(
+                            w,
+                            r#"<tr><th scope="row">{}</th><td>{state}</td><td>{seconds}s</td></tr>"#,
+                            Escaped(upstream)
+                        )
+                        .unwrap();
+                    }
+                });
+            });
+        }
+
+        {
+            let MaintenanceConfig {
+                enabled,
+                hostnames,
+                bypass,
+                reason,
+                windows,
+            } = maintenance;
+
+            if *enabled {
+                config_value(&mut html, &"maintenance.enabled", &|w| {
+                    write!(w, "{enabled}").unwrap()
+                });
+            }
+            if !hostnames.is_empty() {
+                config_value(&mut html, &"maintenance.hostnames", &|w| {
+                    table(w, None, &|w| {
+                        for hostname in hostnames {
+                            tr_td(w, &|w| write!(w, "{}", Escaped(hostname)).unwrap());
+                        }
+                    });
+                });
+            }
+            if !bypass.is_empty() {
+                config_value(&mut html, &"maintenance.bypass", &|w| {
+                    table(w, None, &|w| {
+                        for username in bypass {
+                            tr_td(w, &|w| write!(w, "{}", Escaped(username)).unwrap());
+                        }
+                    });
+                });
+            }
+            if let Some(reason) = reason {
+                config_value(&mut html, &"maintenance.reason", &|w| {
+                    write!(w, "<pre><code class=\"mc-font\">").unwrap();
+                    for component in ElaboratedTextComponent::from_text_component(reason.clone()) {
+                        text_component_html(w, component);
+                    }
+                    write!(w, "</code></pre>").unwrap();
+                });
+            }
+            if !windows.is_empty() {
+                config_value(&mut html, &"maintenance.windows", &|w| {
+                    let now = SystemTime::now();
+
+                    table(w, None, &|w| {
+                        for MaintenanceWindow {
+                            start,
+                            end,
+                            hostnames,
+                        } in windows
+                        {
+                            let hostnames = match hostnames.is_empty() {
+                                true => "every hostname".to_string(),
+                                false => hostnames
+                                    .iter()
+                                    .map(ToString::to_string)
+                                    .collect::<Vec<_>>()
+                                    .join(", "),
+                            };
+                            let state = if let Ok(until) = start.duration_since(now) {
+                                format!("starts in {}s", until.as_secs())
+                            } else if let Ok(until) = end.duration_since(now) {
+                                format!("active, ends in {}s", until.as_secs())
+                            } else {
+                                "over".to_string()
+                            };
+
+                            write!(
+                                w,
+                                r#"<tr><th scope="row">{}</th><td>{state}</td></tr>"#,
+                                Escaped(hostnames)
+                            )
+                            .unwrap();
+                        }
+                    });
+                });
+            }
+
+            let (global, hostnames) = maintenance_overrides.overrides();
+            if global.is_some() || !hostnames.is_empty() {
+                config_value(&mut html, &"maintenance_overrides", &|w| {
+                    let state = |enabled: bool| match enabled {
+                        true => "under maintenance",
+                        false => "not under maintenance",
+                    };
+
+                    table(w, None, &|w| {
+                        if let Some(enabled) = global {
+                            write!(
+                                w,
+                                r#"<tr><th scope="row">every hostname</th><td>{}</td></tr>"#,
+                                state(enabled)
+                            )
+                            .unwrap();
+                        }
+                        for (hostname, enabled) in &hostnames {
+                            write!(
+                                w,
+                                r#"<tr><th scope="row">{}</th><td>{}</td></tr>"#,
+                                Escaped(hostname),
+                                state(*enabled)
+                            )
+                            .unwrap();
+                        }
+                    });
+                });
+            }
+        }
+
+        {
+            let AccessConfig {
+                allow,
+                allow_file,
+                deny,
+                deny_file,
+                not_allowed_reason,
+                denied_reason,
+            } = access;
+
+            for (name, entries) in [("access.allow", allow), ("access.deny", deny)] {
+                if !entries.is_empty() {
+                    config_value(&mut html, &name, &|w| {
+                        table(w, None, &|w| {
+                            for entry in entries {
+                                tr_td(w, &|w| write!(w, "{}", Escaped(entry)).unwrap());
+                            }
+                        });
+                    });
+                }
+            }
+            for (name, file) in [
+                ("access.allow_file", allow_file),
+                ("access.deny_file", deny_file),
+            ] {
+                if let Some(file) = file {
+                    config_value(&mut html, &name, &|w| {
+                        write!(
+                            w,
+                            "{} ({} players)",
+                            Escaped(file.path.display()),
+                            file.entries.len()
+                        )
+                        .unwrap()
+                    });
+                }
+            }
+            for (name, reason) in [
+                ("access.not_allowed_reason", not_allowed_reason),
+                ("access.denied_reason", denied_reason),
+            ] {
+                if let Some(reason) = reason {
+                    config_value(&mut html, &name, &|w| {
+                        write!(w, "<pre><code class=\"mc-font\">").unwrap();
+                        for component in
+                            ElaboratedTextComponent::from_text_component(reason.clone())
+                        {
+                            text_component_html(w, component);
+                        }
+                        write!(w, "</code></pre>").unwrap();
+                    });
+                }
+            }
+        }
+
+        {
+            let PlaceholderServerConfig { responses, limbo } = placeholder_server;
+
+            if let Some(LimboConfig { message, timeout }) = limbo {
+                config_value(&mut html, &"placeholder_server.limbo.timeout", &|w| {
+                    write!(w, "{timeout}s").unwrap()
+                });
+
+                if let Some(message) = message {
+                    config_value(&mut html, &"placeholder_server.limbo.message", &|w| {
+                        write!(w, "<pre><code class=\"mc-font\">").unwrap();
+                        for component in
+                            ElaboratedTextComponent::from_text_component(message.clone())
+                        {
+                            text_component_html(w, component);
+                        }
+                        write!(w, "</code></pre>").unwrap();
+                    });
+                }
+            }
 
             {
                 let PlaceholderServerResponses {
                     offline,
                     no_mapping,
+                    starting,
+                    sleeping,
+                    maintenance,
                 } = responses;
 
                 config_value(&mut html, &"placeholder_server.responses", &|w| {
                     table(w, None, &|w| {
-                        for (response_name, response) in
-                            [("offline", offline), ("no_mapping", no_mapping)]
-                        {
+                        for (response_name, response) in [
+                            ("offline", offline),
+                            ("no_mapping", no_mapping),
+                            ("starting", starting),
+                            ("sleeping", sleeping),
+                            ("maintenance", maintenance),
+                        ] {
                             config_value(w, &response_name, &|w| {
                                 if let Some(StatusResponse {
                                     version,
@@ -132,7 +657,8 @@ pub fn config_table(config: Arc<Config>) -> String {
                                     table(w, None, &|w| {
                                         if let Some(favicon) = favicon {
                                             config_value(w, &"favicon", &|w| {
-                                                write!(w, r#"<img src="{favicon}"/>"#).unwrap();
+                                                write!(w, r#"<img src="{}"/>"#, Escaped(favicon))
+                                                    .unwrap();
                                             });
                                         }
 
@@ -141,7 +667,7 @@ pub fn config_table(config: Arc<Config>) -> String {
                                                 let Version { name, protocol } = version;
 
                                                 config_value(w, &"name", &|w| {
-                                                    write!(w, "{name}").unwrap()
+                                                    write!(w, "{}", Escaped(name)).unwrap()
                                                 });
                                                 config_value(w, &"protocol", &|w| {
                                                     write!(w, "{protocol}").unwrap()
@@ -182,7 +708,8 @@ pub fn config_table(config: Arc<Config>) -> String {
                                                                         },
                                                                     );
                                                                     config_value(w, &"id", &|w| {
-                                                                        write!(w, "{id}").unwrap()
+                                                                        write!(w, "{}", Escaped(id))
+                                                                            .unwrap()
                                                                     });
                                                                 });
                                                             });
@@ -252,7 +779,7 @@ fn text_component_html(w: &mut dyn Write, component: ElaboratedTextComponent) {
             write!(w, ";").unwrap();
         }
         if let Some(color) = color.as_ref().map(|c| c.foreground_color()) {
-            write!(w, "color:{color};").unwrap();
+            write!(w, "color:{};", Escaped(color)).unwrap();
         }
         write!(w, "\"").unwrap();
     }
@@ -261,7 +788,7 @@ fn text_component_html(w: &mut dyn Write, component: ElaboratedTextComponent) {
         write!(w, " class=\"obfuscated\"").unwrap();
     }
 
-    write!(w, ">{text}</span>").unwrap();
+    write!(w, ">{}</span>", Escaped(text)).unwrap();
 }
 
 fn tr_td(w: &mut dyn Write, inner: &dyn Fn(&mut dyn Write)) {
@@ -270,11 +797,9 @@ fn tr_td(w: &mut dyn Write, inner: &dyn Fn(&mut dyn Write)) {
     write!(w, "</td></tr>").unwrap();
 }
 
-fn table(
-    w: &mut dyn Write,
-    inner_head: Option<&dyn Fn(&mut dyn Write)>,
-    inner_body: &dyn Fn(&mut dyn Write),
-) {
+type WriteFn<'a> = &'a dyn Fn(&mut dyn Write);
+
+fn table(w: &mut dyn Write, inner_head: Option<WriteFn>, inner_body: WriteFn) {
     write!(w, "<table>").unwrap();
     if let Some(inner_head) = inner_head {
         write!(w, "<thead>").unwrap();
@@ -290,7 +815,8 @@ fn table(
 fn config_value(w: &mut dyn Write, name: &dyn Display, inner: &dyn Fn(&mut dyn Write)) {
     write!(
         w,
-        r#"<tr><th scope="row"><pre><code>{name}</code></pre></th><td>"#
+        r#"<tr><th scope="row"><pre><code>{}</code></pre></th><td>"#,
+        Escaped(name)
     )
     .unwrap();
 
@@ -299,14 +825,23 @@ fn config_value(w: &mut dyn Write, name: &dyn Display, inner: &dyn Fn(&mut dyn W
     write!(w, r#"</td></tr>"#).unwrap();
 }
 
-fn kv_mapping(w: &mut dyn Write, map: &HashMap<impl Display + Ord, impl Display>) {
+fn kv_mapping(w: &mut dyn Write, map: impl IntoIterator<Item = (impl Display, impl Display)>) {
     write!(w, r#"<table>"#).unwrap();
 
-    let mut pairs = map.iter().collect::<Vec<_>>();
+    let mut pairs = map
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect::<Vec<_>>();
     pairs.sort_by(|(k_a, _), (k_b, _)| k_a.cmp(k_b));
 
     for (key, value) in pairs {
-        write!(w, r#"<tr><th scope="row">{key}</th><td>{value}</td></tr>"#).unwrap();
+        write!(
+            w,
+            r#"<tr><th scope="row">{}</th><td>{}</td></tr>"#,
+            Escaped(key),
+            Escaped(value)
+        )
+        .unwrap();
     }
 
     write!(w, r#"</table>"#).unwrap();
@@ -6,33 +6,46 @@ use std::{
 };
 
 use axum::{
-    extract::State,
-    http::{header, HeaderValue, StatusCode},
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::Html,
     routing::method_routing,
 };
 use config_table::config_table;
+use mcproxy_model::Hostname;
+use serde::Deserialize;
 use tokio::{
     io::{self},
     net::TcpListener,
     sync::watch::{Receiver, Sender},
-    task,
 };
 use tracing::{debug, info};
 use tracing_error::{InstrumentError, TracedError};
 
-use crate::config::{
-    self,
-    schema::{Config, UiServerConfig},
+use crate::{
+    config::{
+        self,
+        schema::{Config, UiServerConfig},
+    },
+    health::UpstreamHealth,
+    maintenance::Maintenance,
+    routing::balance::LoadBalancer,
+    sleep::SleepingServers,
 };
 
 mod config_table;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn listen(
     config: UiServerConfig,
     config_path: PathBuf,
     sender: Sender<Arc<Config>>,
     config_receiver: Receiver<Arc<Config>>,
+    #[cfg(feature = "discovery")] discovered_servers: Arc<mcproxy_discovery::DiscoveredServers>,
+    sleeping_servers: Arc<SleepingServers>,
+    upstream_health: Arc<UpstreamHealth>,
+    load_balancer: Arc<LoadBalancer>,
+    maintenance: Arc<Maintenance>,
     #[cfg(feature = "metrics")] registry: prometheus_client::registry::Registry,
 ) -> Result<(), TracedError<io::Error>> {
     let router = axum::Router::new()
@@ -56,26 +69,32 @@ pub async fn listen(
             "/-/reload",
             method_routing::post(config_reload).with_state((sender, Arc::from(config_path))),
         )
+        .route(
+            "/-/maintenance",
+            method_routing::post(set_maintenance).with_state(maintenance.clone()),
+        )
         .route(
             "/-/config",
-            method_routing::get(print_config).with_state(config_receiver),
+            method_routing::get(print_config).with_state(ConfigState {
+                config: config_receiver,
+                #[cfg(feature = "discovery")]
+                discovered_servers,
+                sleeping_servers,
+                upstream_health,
+                load_balancer,
+                maintenance,
+            }),
         );
 
     #[cfg(feature = "metrics")]
     let router = router.route(
         "/metrics",
         method_routing::get(|State::<Arc<_>>(registry)| async move {
-            let result = task::spawn_blocking(move || {
-                let mut output = String::new();
-
-                prometheus_client::encoding::text::encode(&mut output, &registry)?;
+            // Collectors only read cached values, so encoding does not block
+            let mut output = String::new();
 
-                Ok::<_, std::fmt::Error>(output)
-            })
-            .await;
-
-            match result {
-                Ok(Ok(output)) => Ok((
+            match prometheus_client::encoding::text::encode(&mut output, &registry) {
+                Ok(()) => Ok((
                     [(
                         header::CONTENT_TYPE,
                         header::HeaderValue::from_static(
@@ -84,7 +103,6 @@ pub async fn listen(
                     )],
                     output,
                 )),
-                Ok(Err(error)) => Err((StatusCode::INTERNAL_SERVER_ERROR, error.to_string())),
                 Err(error) => Err((StatusCode::INTERNAL_SERVER_ERROR, error.to_string())),
             }
         })
@@ -99,9 +117,94 @@ pub async fn listen(
         .map_err(InstrumentError::in_current_span)
 }
 
+#[derive(Clone)]
+struct ConfigState {
+    config: Receiver<Arc<Config>>,
+    #[cfg(feature = "discovery")]
+    discovered_servers: Arc<mcproxy_discovery::DiscoveredServers>,
+    sleeping_servers: Arc<SleepingServers>,
+    upstream_health: Arc<UpstreamHealth>,
+    load_balancer: Arc<LoadBalancer>,
+    maintenance: Arc<Maintenance>,
+}
+
 #[axum::debug_handler]
-async fn print_config(State(config): State<Receiver<Arc<Config>>>) -> Html<String> {
-    Html(config_table(config.borrow().clone()))
+async fn print_config(State(state): State<ConfigState>) -> Html<String> {
+    Html(config_table(
+        state.config.borrow().clone(),
+        #[cfg(feature = "discovery")]
+        &state.discovered_servers,
+        &state.sleeping_servers,
+        &state.upstream_health,
+        &state.load_balancer,
+        &state.maintenance,
+    ))
+}
+
+/// Whether a request was sent by a page on another site, which the browser made without asking
+///
+/// Browsers say where a `POST` comes from, so a page can not change anything on the UI behind
+/// the back of whoever has it open. Clients like curl send neither header and are let through.
+fn cross_site(headers: &HeaderMap) -> bool {
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return !matches!(site.as_bytes(), b"same-origin" | b"none");
+    }
+
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return false;
+    };
+    let origin_host = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, host)| host);
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+
+    origin_host.is_none() || origin_host != host
+}
+
+/// Which hostname to turn maintenance on or off for, every hostname if none is given
+#[derive(Debug, Deserialize)]
+struct MaintenanceQuery {
+    hostname: Option<Hostname>,
+    /// Leaving this out goes back to what the config says
+    enabled: Option<bool>,
+}
+
+#[tracing::instrument(skip_all, fields(?query))]
+#[axum::debug_handler]
+async fn set_maintenance(
+    State(maintenance): State<Arc<Maintenance>>,
+    headers: HeaderMap,
+    Query(query): Query<MaintenanceQuery>,
+) -> (StatusCode, &'static str) {
+    if cross_site(&headers) {
+        return (StatusCode::FORBIDDEN, "Cross-site requests are not allowed");
+    }
+    if query
+        .hostname
+        .as_ref()
+        .is_some_and(|hostname| !hostname.is_dns_name())
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            "The hostname is not a valid DNS name",
+        );
+    }
+
+    maintenance.set(query.hostname, query.enabled);
+    info!("maintenance changed");
+
+    match query.enabled {
+        Some(true) => (StatusCode::OK, "Maintenance turned on"),
+        Some(false) => (StatusCode::OK, "Maintenance turned off"),
+        None => (
+            StatusCode::OK,
+            "Maintenance follows the configuration again",
+        ),
+    }
 }
 
 #[tracing::instrument(skip_all)]
@@ -129,3 +232,41 @@ async fn config_reload(
 
     Ok((StatusCode::OK, "Configuration reloaded successfully"))
 }
+
+#[cfg(test)]
+mod test {
+    use axum::http::HeaderMap;
+
+    use super::cross_site;
+
+    #[test]
+    fn cross_site_requests() {
+        for (headers, cross) in [
+            (&[][..], false),
+            (
+                &[
+                    ("host", "127.0.0.1:8080"),
+                    ("origin", "http://127.0.0.1:8080"),
+                ][..],
+                false,
+            ),
+            (
+                &[
+                    ("host", "127.0.0.1:8080"),
+                    ("origin", "https://evil.example"),
+                ][..],
+                true,
+            ),
+            (&[("host", "127.0.0.1:8080"), ("origin", "null")][..], true),
+            (&[("sec-fetch-site", "same-origin")][..], false),
+            (&[("sec-fetch-site", "cross-site")][..], true),
+        ] {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+                .collect::<HeaderMap>();
+
+            assert_eq!(cross_site(&headers), cross, "{headers:?}");
+        }
+    }
+}